@@ -0,0 +1,179 @@
+// HTTP client for talking to other nodes
+// Uses the addresses and API port learned from discovery, tries each address
+// in turn with a short timeout and remembers the one that answered
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use super::NodesResponse;
+use crate::discovery::ping::LatencyReport;
+use crate::discovery::NodeInfo;
+use crate::routes::RoutingTable;
+
+const REQUEST_TIMEOUT_SECS: u64 = 3;
+const ROUTING_TABLE_CACHE_SECS: u64 = 30;
+
+struct CachedTable {
+    table: RoutingTable,
+    fetched_at: Instant,
+}
+
+pub struct NodeClient {
+    http: reqwest::Client,
+    cache_ttl: Duration,
+    routing_tables: RwLock<HashMap<String, CachedTable>>,
+    /// Last address that answered for each node, tried first next time
+    working_addresses: RwLock<HashMap<String, IpAddr>>,
+}
+
+impl NodeClient {
+    pub fn new() -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .connect_timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .unwrap_or_default();
+
+        NodeClient {
+            http,
+            cache_ttl: Duration::from_secs(ROUTING_TABLE_CACHE_SECS),
+            routing_tables: RwLock::new(HashMap::new()),
+            working_addresses: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Fetch a peer's routing table, served from cache when recent enough
+    pub async fn fetch_routing_table(&self, node: &NodeInfo) -> Result<RoutingTable, String> {
+        if let Some(cached) = self.routing_tables.read().await.get(&node.id) {
+            if cached.fetched_at.elapsed() < self.cache_ttl {
+                return Ok(cached.table.clone());
+            }
+        }
+
+        let table: RoutingTable = self.get_json(node, "/api/routing-table").await?;
+
+        self.routing_tables.write().await.insert(
+            node.id.clone(),
+            CachedTable {
+                table: table.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+
+        Ok(table)
+    }
+
+    /// Fetch the peer's latency measurements to its own peers
+    pub async fn fetch_latency_report(&self, node: &NodeInfo) -> Result<LatencyReport, String> {
+        self.get_json(node, "/api/latency").await
+    }
+
+    /// Ask a node at a known address (e.g. a static seed) who it is and who it knows
+    pub async fn fetch_nodes(&self, address: SocketAddr) -> Result<NodesResponse, String> {
+        self.get_url(&format!("http://{}/api/nodes", address)).await
+    }
+
+    /// GET a JSON document from the node, trying each of its addresses
+    async fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        node: &NodeInfo,
+        path: &str,
+    ) -> Result<T, String> {
+        // The last address that worked, else the one we'd route to first
+        let working = self.working_addresses.read().await.get(&node.id).copied();
+        let preferred = working
+            .or_else(|| node.preferred_address(&crate::discovery::interfaces::local_addresses()));
+        let addresses = ordered_addresses(&node.addresses, preferred);
+
+        if addresses.is_empty() {
+            return Err(format!("Node {} has no known addresses", node.hostname));
+        }
+
+        let mut errors = Vec::new();
+        for address in addresses {
+            let url = format!("http://{}{}", SocketAddr::new(address, node.port), path);
+
+            match self.get_url(&url).await {
+                Ok(value) => {
+                    self.working_addresses
+                        .write()
+                        .await
+                        .insert(node.id.clone(), address);
+                    return Ok(value);
+                }
+                Err(e) => {
+                    tracing::debug!("Request to {} failed: {}", url, e);
+                    errors.push(format!("{}: {}", address, e));
+                }
+            }
+        }
+
+        Err(format!(
+            "Could not reach {} on any address ({})",
+            node.hostname,
+            errors.join("; ")
+        ))
+    }
+
+    async fn get_url<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, String> {
+        let response = self.http.get(url).send().await.map_err(|e| e.to_string())?;
+        let response = response.error_for_status().map_err(|e| e.to_string())?;
+        response.json::<T>().await.map_err(|e| e.to_string())
+    }
+}
+
+impl Default for NodeClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Put the last working address first, then IPv4 before IPv6 link-local
+fn ordered_addresses(addresses: &[IpAddr], preferred: Option<IpAddr>) -> Vec<IpAddr> {
+    let mut ordered: Vec<IpAddr> = addresses
+        .iter()
+        .copied()
+        .filter(|a| !a.is_unspecified())
+        .collect();
+
+    // Link-local IPv6 needs a scope id we don't have, so try it last
+    ordered.sort_by_key(|a| match a {
+        IpAddr::V4(_) => 0,
+        IpAddr::V6(v6) if (v6.segments().first().copied().unwrap_or(0) & 0xffc0) == 0xfe80 => 2,
+        IpAddr::V6(_) => 1,
+    });
+
+    if let Some(preferred) = preferred {
+        if let Some(pos) = ordered.iter().position(|a| *a == preferred) {
+            let address = ordered.remove(pos);
+            ordered.insert(0, address);
+        }
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ordered_addresses() {
+        let addresses: Vec<IpAddr> = vec![
+            "fe80::1".parse().unwrap(),
+            "2001:db8::1".parse().unwrap(),
+            "10.8.0.2".parse().unwrap(),
+            "192.168.1.20".parse().unwrap(),
+        ];
+
+        let ordered = ordered_addresses(&addresses, None);
+        assert_eq!(ordered.first(), Some(&"10.8.0.2".parse().unwrap()));
+        assert_eq!(ordered.last(), Some(&"fe80::1".parse().unwrap()));
+
+        let ordered = ordered_addresses(&addresses, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(ordered.first(), Some(&"2001:db8::1".parse().unwrap()));
+        assert_eq!(ordered.len(), 4);
+    }
+}
@@ -1,5 +1,6 @@
 // API module - REST endpoints and WebSocket handling
 
+pub mod client;
 pub mod rest;
 pub mod websocket;
 
@@ -8,6 +9,12 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TraceRouteRequest {
     pub destination: String,
+    /// Protocol used for the firewall verdict ("tcp", "udp" or "icmp"; default "tcp")
+    #[serde(default)]
+    pub protocol: Option<String>,
+    /// Destination port used for the firewall verdict
+    #[serde(default)]
+    pub port: Option<u16>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,6 +22,82 @@ pub struct TraceRouteResponse {
     pub destination: String,
     pub resolved_ip: String,
     pub matched_route: Option<crate::routes::Route>,
+    /// DNS details, present when the destination was a hostname
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsResolution>,
+    /// What the local firewall does with the packet, if a ruleset could be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firewall: Option<crate::diagnostics::firewall::FirewallVerdict>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DnsResolution {
+    /// Every A/AAAA answer with its own route lookup
+    pub addresses: Vec<ResolvedAddress>,
+    /// Resolver that the query is routed to (longest matching DNS domain)
+    pub resolver: Option<ResolverPath>,
+    /// All configured resolvers
+    pub resolvers: Vec<ResolverPath>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResolvedAddress {
+    pub ip: String,
+    pub matched_route: Option<crate::routes::Route>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResolverPath {
+    #[serde(flatten)]
+    pub resolver: crate::routes::resolver::Resolver,
+    /// Interface the resolver is reached through, according to the routing table
+    pub interface: Option<String>,
+    pub matched_route: Option<crate::routes::Route>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiagnoseRequest {
+    /// Node id, node hostname, IP address or DNS name
+    pub target: String,
+    /// TCP port to probe (defaults to the node's API port)
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PathRequest {
+    /// Source node id or hostname (defaults to the local node)
+    #[serde(default)]
+    pub from: Option<String>,
+    /// Destination node id or hostname
+    pub to: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NodesResponse {
+    pub nodes: Vec<crate::discovery::NodeInfo>,
+    pub local_node_id: String,
+    /// Lets nodes that poll this endpoint (static seeds) name this node
+    #[serde(default)]
+    pub local_hostname: String,
+    /// Enabled discovery sources and what each found last
+    #[serde(default)]
+    pub sources: Vec<crate::discovery::source::SourceStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiscoveryStats {
+    /// Received datagrams by outcome
+    #[serde(flatten)]
+    pub packets: crate::discovery::wire::PacketCounts,
+    pub registry: crate::discovery::RegistryStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VpnPeersResponse {
+    /// The latest list from each enabled VPN peer source
+    pub sources: Vec<crate::discovery::vpn_peers::VpnPeerList>,
+    pub timestamp: String,
 }
 
 #[derive(Debug, Serialize)]
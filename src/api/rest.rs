@@ -7,33 +7,78 @@ use axum::{
     routing::{get, post},
     Router,
 };
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 use std::sync::Arc;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch, RwLock};
 
+use super::client::NodeClient;
 use super::websocket::ServerMessage;
 use super::{
-    ErrorResponse, TraceRouteRequest, TraceRouteResponse, TracerouteRequest, TracerouteResult,
+    DiagnoseRequest, DiscoveryStats, DnsResolution, ErrorResponse, NodesResponse, PathRequest,
+    ResolvedAddress, ResolverPath, TraceRouteRequest, TraceRouteResponse, TracerouteRequest,
+    TracerouteResult, VpnPeersResponse,
 };
+use crate::diagnostics::diagnose::{self, DiagnosisReport};
+use crate::diagnostics::firewall;
+use crate::diagnostics::leak::{self, LeakReport};
+use crate::diagnostics::sysctl::{self, SysctlReport};
+use crate::discovery::ping::{self, LatencyHistory, LatencyMatrix, LatencyReport};
+use crate::discovery::source::SourceRegistry;
+use crate::discovery::trust::{TrustStore, TrustSummary};
+use crate::discovery::vpn_peers::VpnPeerLists;
+use crate::discovery::wire::PacketStats;
+use crate::discovery::wireguard::{self, WireGuardReport};
 use crate::discovery::{traceroute::TracerouteExecutor, NodeInfo, PeerRegistry};
-use crate::routes::{lookup::RouteEngine, parser, RoutingTable};
+use crate::routes::path::{self, PathAnalysis, PathNode};
+use crate::routes::{lookup::RouteEngine, parser, resolver, RoutingTable};
 
 #[derive(Clone)]
 pub struct AppState {
     pub peer_registry: Arc<PeerRegistry>,
     pub broadcast_tx: broadcast::Sender<ServerMessage>,
     pub bandwidth_service: Option<Arc<crate::discovery::bandwidth::BandwidthService>>,
+    pub leak_report: Arc<RwLock<Option<LeakReport>>>,
+    pub node_client: Arc<NodeClient>,
+    pub latency_histories: Arc<RwLock<HashMap<String, LatencyHistory>>>,
+    pub trust_store: Option<Arc<TrustStore>>,
+    pub discovery_sources: Option<Arc<SourceRegistry>>,
+    /// Counters for received discovery datagrams
+    pub packet_stats: Arc<PacketStats>,
+    /// Peer lists read from Tailscale, ZeroTier and OpenVPN by their sources
+    pub vpn_peers: VpnPeerLists,
+    /// Flipped to true once the process starts shutting down
+    pub shutdown_tx: Arc<watch::Sender<bool>>,
 }
 
 impl AppState {
     pub fn new(peer_registry: Arc<PeerRegistry>) -> Self {
-        let (tx, _) = broadcast::channel(100);
+        let (shutdown_tx, _) = watch::channel(false);
         AppState {
+            broadcast_tx: peer_registry.update_sender(),
             peer_registry,
-            broadcast_tx: tx,
             bandwidth_service: None,
+            leak_report: Arc::new(RwLock::new(None)),
+            node_client: Arc::new(NodeClient::new()),
+            latency_histories: Arc::new(RwLock::new(HashMap::new())),
+            trust_store: None,
+            discovery_sources: None,
+            packet_stats: Arc::new(PacketStats::default()),
+            vpn_peers: Arc::new(RwLock::new(BTreeMap::new())),
+            shutdown_tx: Arc::new(shutdown_tx),
         }
     }
 
+    pub fn with_trust_store(mut self, trust_store: Arc<TrustStore>) -> Self {
+        self.trust_store = Some(trust_store);
+        self
+    }
+
+    pub fn with_discovery_sources(mut self, sources: Arc<SourceRegistry>) -> Self {
+        self.discovery_sources = Some(sources);
+        self
+    }
+
     pub fn with_bandwidth_service(
         mut self,
         service: Arc<crate::discovery::bandwidth::BandwidthService>,
@@ -49,6 +94,16 @@ impl AppState {
     pub fn send_update(&self, msg: ServerMessage) {
         let _ = self.broadcast_tx.send(msg);
     }
+
+    /// Receiver that background tasks watch to know when to stop
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown_tx.subscribe()
+    }
+
+    /// Tell every background task and WebSocket client to wind down
+    pub fn begin_shutdown(&self) {
+        self.shutdown_tx.send_replace(true);
+    }
 }
 
 pub fn create_api_router(state: Arc<AppState>) -> Router {
@@ -58,6 +113,16 @@ pub fn create_api_router(state: Arc<AppState>) -> Router {
         .route("/api/routing-table", get(get_routing_table))
         .route("/api/trace-route", post(trace_route))
         .route("/api/traceroute", post(traceroute))
+        .route("/api/path", post(compute_path))
+        .route("/api/diagnose", post(diagnose_target))
+        .route("/api/leak-check", get(leak_check))
+        .route("/api/diagnostics/sysctl", get(sysctl_check))
+        .route("/api/latency", get(get_latency))
+        .route("/api/latency/matrix", get(get_latency_matrix))
+        .route("/api/trust", get(get_trust))
+        .route("/api/discovery/stats", get(get_discovery_stats))
+        .route("/api/wireguard", get(get_wireguard))
+        .route("/api/vpn-peers", get(get_vpn_peers))
         .route("/api/nodes", get(get_nodes))
         .route("/api/nodes/:node_id", get(get_node))
         .route(
@@ -74,8 +139,19 @@ async fn hello_world(State(_state): State<Arc<AppState>>) -> &'static str {
      - GET  /api/routing-table      - Get current routing table\n\
      - POST /api/trace-route        - Trace route to destination\n\
      - POST /api/traceroute         - Perform traceroute to destination\n\
+     - POST /api/path               - Compute the path between two nodes\n\
+     - POST /api/diagnose           - Diagnose connectivity to a node or address\n\
+     - GET  /api/leak-check         - Check for DNS/IPv6 leaks around a VPN\n\
+     - GET  /api/diagnostics/sysctl - Check rp_filter/forwarding settings\n\
+     - GET  /api/latency            - This node's latency to its peers\n\
+     - GET  /api/latency/matrix     - Latency between every pair of nodes\n\
+     - GET  /api/trust              - Peer signing keys and rejected peers\n\
+     - GET  /api/discovery/stats    - Discovery packet counters and registry size\n\
+     - GET  /api/wireguard          - WireGuard interfaces and peer health\n\
+     - GET  /api/vpn-peers          - Peers listed by Tailscale, ZeroTier and OpenVPN\n\
      - GET  /api/nodes              - List discovered nodes\n\
      - GET  /api/nodes/{id}         - Get node details\n\
+     - GET  /api/nodes/{id}/routing-table - Get a node's routing table\n\
      - WS   /ws                     - WebSocket for real-time updates\n\
      \n\
      Web UI: /static/index.html"
@@ -84,7 +160,17 @@ async fn hello_world(State(_state): State<Arc<AppState>>) -> &'static str {
 async fn get_routing_table(
     State(_state): State<Arc<AppState>>,
 ) -> Result<Json<RoutingTable>, (StatusCode, Json<ErrorResponse>)> {
-    match parser::get_routing_table() {
+    let result = tokio::task::spawn_blocking(|| {
+        let mut table = parser::get_routing_table()?;
+        let interfaces = wireguard::read_wireguard_interfaces().unwrap_or_default();
+        wireguard::annotate_routes(&mut table.routes, &interfaces);
+        Ok(table)
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))
+    .and_then(|r| r);
+
+    match result {
         Ok(table) => Ok(Json(table)),
         Err(e) => Err((
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -100,35 +186,38 @@ async fn trace_route(
     State(_state): State<Arc<AppState>>,
     Json(request): Json<TraceRouteRequest>,
 ) -> Result<Json<TraceRouteResponse>, (StatusCode, Json<ErrorResponse>)> {
-    // Resolve destination to IP
-    let ip = match request.destination.parse() {
-        Ok(ip) => ip,
+    // Resolve destination to every address it has, remembering whether DNS was involved
+    let (ips, via_dns) = match request.destination.parse::<IpAddr>() {
+        Ok(ip) => (vec![ip], false),
         Err(_) => {
-            // Try DNS resolution
-            match tokio::net::lookup_host(format!("{}:0", request.destination))
-                .await
-                .ok()
-                .and_then(|mut addrs| addrs.next())
-            {
-                Some(addr) => addr.ip(),
-                None => {
-                    return Err((
-                        StatusCode::BAD_REQUEST,
-                        Json(ErrorResponse {
-                            error: "InvalidDestination".to_string(),
-                            message: format!(
-                                "Could not resolve destination: {}",
-                                request.destination
-                            ),
-                        }),
-                    ));
+            let mut ips: Vec<IpAddr> = Vec::new();
+            if let Ok(addrs) = tokio::net::lookup_host(format!("{}:0", request.destination)).await {
+                for addr in addrs {
+                    if !ips.contains(&addr.ip()) {
+                        ips.push(addr.ip());
+                    }
                 }
             }
+
+            if ips.is_empty() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "InvalidDestination".to_string(),
+                        message: format!("Could not resolve destination: {}", request.destination),
+                    }),
+                ));
+            }
+            (ips, true)
         }
     };
 
     // Get routing table
-    let routing_table = match parser::get_routing_table() {
+    let routing_table = match tokio::task::spawn_blocking(parser::get_routing_table)
+        .await
+        .map_err(|e| format!("Task join error: {}", e))
+        .and_then(|r| r)
+    {
         Ok(table) => table,
         Err(e) => {
             return Err((
@@ -141,24 +230,79 @@ async fn trace_route(
         }
     };
 
-    // Perform route lookup
+    // Perform route lookup for each address
     let engine = RouteEngine::new(&routing_table);
-    let matched_route = engine.lookup(ip).cloned();
+    let addresses: Vec<ResolvedAddress> = ips
+        .iter()
+        .map(|ip| ResolvedAddress {
+            ip: ip.to_string(),
+            matched_route: engine.lookup(*ip).cloned(),
+        })
+        .collect();
 
-    if matched_route.is_none() {
+    // Report the first routable address as the primary result
+    let Some((&primary_ip, primary)) = ips
+        .iter()
+        .zip(&addresses)
+        .find(|(_, a)| a.matched_route.is_some())
+    else {
+        let ip_list: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
         return Err((
             StatusCode::NOT_FOUND,
             Json(ErrorResponse {
                 error: "NoRouteToHost".to_string(),
-                message: format!("No route found to {}", ip),
+                message: format!("No route found to {}", ip_list.join(", ")),
             }),
         ));
-    }
+    };
+    let resolved_ip = primary_ip.to_string();
+    let matched_route = primary.matched_route.clone();
+
+    let dns = if via_dns {
+        let resolvers = tokio::task::spawn_blocking(resolver::get_resolvers)
+            .await
+            .unwrap_or_default();
+        let selected = resolver::select_resolver(&resolvers, &request.destination).cloned();
+        let resolver_path = |resolver: resolver::Resolver| {
+            let matched_route = engine.lookup(resolver.address).cloned();
+            ResolverPath {
+                // Per-link servers are bound to their link; others follow the routing table
+                interface: resolver
+                    .link
+                    .clone()
+                    .or_else(|| matched_route.as_ref().map(|r| r.interface.clone())),
+                resolver,
+                matched_route,
+            }
+        };
+
+        Some(DnsResolution {
+            addresses,
+            resolver: selected.map(resolver_path),
+            resolvers: resolvers.into_iter().map(resolver_path).collect(),
+        })
+    } else {
+        None
+    };
+
+    // Check whether the local firewall lets the packet out along that route
+    let packet = firewall::Packet {
+        destination: primary_ip,
+        protocol: request.protocol.unwrap_or_else(|| "tcp".to_string()),
+        port: request.port,
+        out_interface: matched_route.as_ref().map(|r| r.interface.clone()),
+    };
+    let firewall = tokio::task::spawn_blocking(move || firewall::check_packet(&packet))
+        .await
+        .ok()
+        .flatten();
 
     Ok(Json(TraceRouteResponse {
         destination: request.destination,
-        resolved_ip: ip.to_string(),
+        resolved_ip,
         matched_route,
+        dns,
+        firewall,
     }))
 }
 
@@ -206,10 +350,195 @@ async fn traceroute(
     }
 }
 
-#[derive(serde::Serialize)]
-struct NodesResponse {
-    nodes: Vec<NodeInfo>,
-    local_node_id: String,
+async fn compute_path(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<PathRequest>,
+) -> Result<Json<PathAnalysis>, (StatusCode, Json<ErrorResponse>)> {
+    let nodes = collect_path_nodes(&state).await;
+    let from = request
+        .from
+        .unwrap_or_else(|| state.peer_registry.local_node_id().to_string());
+
+    match path::compute_path(&nodes, &from, &request.to) {
+        Ok(analysis) => Ok(Json(analysis)),
+        Err(e) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "NodeNotFound".to_string(),
+                message: e,
+            }),
+        )),
+    }
+}
+
+/// Gather the local node and every discovered node with its routing table
+async fn collect_path_nodes(state: &AppState) -> Vec<PathNode> {
+    let local_table = tokio::task::spawn_blocking(parser::get_routing_table)
+        .await
+        .ok()
+        .and_then(|r| r.ok());
+
+    let mut nodes = vec![PathNode {
+        id: state.peer_registry.local_node_id().to_string(),
+        hostname: local_table
+            .as_ref()
+            .map(|t| t.hostname.clone())
+            .unwrap_or_else(|| "local".to_string()),
+        addresses: crate::discovery::interfaces::local_addresses()
+            .into_iter()
+            .map(|i| i.address)
+            .collect(),
+        routing_table: local_table,
+    }];
+
+    let peers = state.peer_registry.get_all_nodes().await;
+    let tables = futures::future::join_all(
+        peers
+            .iter()
+            .map(|node| state.node_client.fetch_routing_table(node)),
+    )
+    .await;
+
+    for (node, table) in peers.into_iter().zip(tables) {
+        if let Err(e) = &table {
+            tracing::debug!("No routing table for {}: {}", node.hostname, e);
+        }
+        nodes.push(PathNode {
+            id: node.id,
+            hostname: node.hostname,
+            addresses: node.addresses,
+            routing_table: table.ok(),
+        });
+    }
+
+    nodes
+}
+
+async fn diagnose_target(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<DiagnoseRequest>,
+) -> Result<Json<DiagnosisReport>, (StatusCode, Json<ErrorResponse>)> {
+    let target = match diagnose::resolve_target(&state.peer_registry, &request.target).await {
+        Ok(target) => target,
+        Err(e) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "InvalidTarget".to_string(),
+                    message: e,
+                }),
+            ));
+        }
+    };
+
+    match diagnose::run_diagnosis(target, request.port, &state.node_client).await {
+        Ok(report) => Ok(Json(report)),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "DiagnosisFailed".to_string(),
+                message: e,
+            }),
+        )),
+    }
+}
+
+async fn leak_check(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<LeakReport>, (StatusCode, Json<ErrorResponse>)> {
+    let result = tokio::task::spawn_blocking(leak::run_leak_check)
+        .await
+        .map_err(|e| format!("Task join error: {}", e))
+        .and_then(|r| r);
+
+    match result {
+        Ok(report) => {
+            *state.leak_report.write().await = Some(report.clone());
+            Ok(Json(report))
+        }
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "LeakCheckFailed".to_string(),
+                message: e,
+            }),
+        )),
+    }
+}
+
+async fn sysctl_check(
+    State(_state): State<Arc<AppState>>,
+) -> Result<Json<SysctlReport>, (StatusCode, Json<ErrorResponse>)> {
+    let result = tokio::task::spawn_blocking(sysctl::run_sysctl_check)
+        .await
+        .map_err(|e| format!("Task join error: {}", e))
+        .and_then(|r| r);
+
+    match result {
+        Ok(report) => Ok(Json(report)),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: "SysctlCheckFailed".to_string(),
+                message: e,
+            }),
+        )),
+    }
+}
+
+async fn get_latency(State(state): State<Arc<AppState>>) -> Json<LatencyReport> {
+    Json(ping::local_latency_report(&state).await)
+}
+
+async fn get_latency_matrix(State(state): State<Arc<AppState>>) -> Json<LatencyMatrix> {
+    Json(ping::gather_latency_matrix(&state).await)
+}
+
+async fn get_trust(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<TrustSummary>, (StatusCode, Json<ErrorResponse>)> {
+    match &state.trust_store {
+        Some(trust_store) => Ok(Json(trust_store.summary().await)),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "DiscoveryDisabled".to_string(),
+                message: "Node discovery is disabled".to_string(),
+            }),
+        )),
+    }
+}
+
+async fn get_wireguard() -> Result<Json<WireGuardReport>, (StatusCode, Json<ErrorResponse>)> {
+    let result = tokio::task::spawn_blocking(wireguard::wireguard_report)
+        .await
+        .map_err(|e| format!("Task join error: {}", e))
+        .and_then(|r| r);
+
+    match result {
+        Ok(report) => Ok(Json(report)),
+        Err(e) => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "WireGuardUnavailable".to_string(),
+                message: e,
+            }),
+        )),
+    }
+}
+
+async fn get_vpn_peers(State(state): State<Arc<AppState>>) -> Json<VpnPeersResponse> {
+    Json(VpnPeersResponse {
+        sources: state.vpn_peers.read().await.values().cloned().collect(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    })
+}
+
+async fn get_discovery_stats(State(state): State<Arc<AppState>>) -> Json<DiscoveryStats> {
+    Json(DiscoveryStats {
+        packets: state.packet_stats.counts(),
+        registry: state.peer_registry.stats().await,
+    })
 }
 
 async fn get_nodes(
@@ -217,10 +546,20 @@ async fn get_nodes(
 ) -> Result<Json<NodesResponse>, (StatusCode, Json<ErrorResponse>)> {
     let nodes = state.peer_registry.get_all_nodes().await;
     let local_node_id = state.peer_registry.local_node_id().to_string();
+    let local_hostname = hostname::get()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .unwrap_or_else(|| "unknown".to_string());
+    let sources = match &state.discovery_sources {
+        Some(sources) => sources.statuses().await,
+        None => Vec::new(),
+    };
 
     Ok(Json(NodesResponse {
         nodes,
         local_node_id,
+        local_hostname,
+        sources,
     }))
 }
 
@@ -241,16 +580,31 @@ async fn get_node(
 }
 
 async fn get_remote_routing_table(
-    State(_state): State<Arc<AppState>>,
-    axum::extract::Path(_node_id): axum::extract::Path<String>,
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(node_id): axum::extract::Path<String>,
 ) -> Result<Json<RoutingTable>, (StatusCode, Json<ErrorResponse>)> {
-    // In a real implementation, this would fetch the routing table from the remote node
-    // For now, return an error
-    Err((
-        StatusCode::NOT_IMPLEMENTED,
-        Json(ErrorResponse {
-            error: "NotImplemented".to_string(),
-            message: "Remote routing table fetching not yet implemented".to_string(),
-        }),
-    ))
+    if node_id == state.peer_registry.local_node_id() {
+        return get_routing_table(State(state)).await;
+    }
+
+    let Some(node) = state.peer_registry.get_node(&node_id).await else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "NodeNotFound".to_string(),
+                message: format!("Node {} not found", node_id),
+            }),
+        ));
+    };
+
+    match state.node_client.fetch_routing_table(&node).await {
+        Ok(table) => Ok(Json(table)),
+        Err(e) => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "NodeUnreachable".to_string(),
+                message: e,
+            }),
+        )),
+    }
 }
@@ -2,7 +2,7 @@
 
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
         State,
     },
     response::Response,
@@ -34,6 +34,14 @@ pub enum ClientMessage {
 #[derive(Debug, Clone, Serialize)]
 #[serde(tag = "type")]
 pub enum ServerMessage {
+    #[serde(rename = "node_discovered")]
+    NodeDiscovered { node: crate::discovery::NodeInfo },
+    #[serde(rename = "node_status_changed")]
+    NodeStatusChanged {
+        node_id: String,
+        status: crate::discovery::NodeStatus,
+        reason: String,
+    },
     #[serde(rename = "latency_update")]
     LatencyUpdate { connections: Vec<Connection> },
     #[serde(rename = "error")]
@@ -57,6 +65,16 @@ pub enum ServerMessage {
         download_mbps: f64,
         duration_secs: u64,
     },
+    #[serde(rename = "remote_routing_table")]
+    RemoteRoutingTable {
+        request_id: String,
+        node_id: String,
+        routing_table: crate::routes::RoutingTable,
+    },
+    #[serde(rename = "leak_check_result")]
+    LeakCheckResult {
+        report: crate::diagnostics::leak::LeakReport,
+    },
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -79,10 +97,29 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
 
     // Subscribe to broadcast channel for node updates
     let mut rx = state.subscribe_to_updates();
+    let mut shutdown = state.shutdown_signal();
 
     // Spawn task to forward broadcast messages to WebSocket
     let mut send_task = tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
+        loop {
+            let msg = tokio::select! {
+                msg = rx.recv() => msg,
+                // The watch guard isn't Send, so drop it before awaiting below
+                _ = async { let _ = shutdown.wait_for(|stopping| *stopping).await; } => {
+                    // Let the browser know this is deliberate rather than a dropped connection
+                    let _ = sender
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "server shutting down".into(),
+                        })))
+                        .await;
+                    break;
+                }
+            };
+
+            let Ok(msg) = msg else {
+                break;
+            };
             let json = serde_json::to_string(&msg).unwrap_or_default();
             if sender.send(Message::Text(json)).await.is_err() {
                 break;
@@ -126,6 +163,35 @@ async fn handle_client_message(msg: ClientMessage, state: &Arc<AppState>) {
             node_id,
         } => {
             tracing::info!("Get remote routing table: {} for {}", request_id, node_id);
+
+            let Some(node) = state.peer_registry.get_node(&node_id).await else {
+                state.send_update(ServerMessage::Error {
+                    request_id: Some(request_id),
+                    error_code: "NodeNotFound".to_string(),
+                    message: format!("Node {} not found", node_id),
+                });
+                return;
+            };
+
+            let state_clone = state.clone();
+            tokio::spawn(async move {
+                match state_clone.node_client.fetch_routing_table(&node).await {
+                    Ok(routing_table) => {
+                        state_clone.send_update(ServerMessage::RemoteRoutingTable {
+                            request_id,
+                            node_id,
+                            routing_table,
+                        });
+                    }
+                    Err(e) => {
+                        state_clone.send_update(ServerMessage::Error {
+                            request_id: Some(request_id),
+                            error_code: "NodeUnreachable".to_string(),
+                            message: e,
+                        });
+                    }
+                }
+            });
         }
         ClientMessage::StartBandwidthTest { test_id, node_id } => {
             tracing::info!("Bandwidth test request: {} to {}", test_id, node_id);
@@ -133,8 +199,10 @@ async fn handle_client_message(msg: ClientMessage, state: &Arc<AppState>) {
             if let Some(bandwidth_service) = &state.bandwidth_service {
                 // Get node info to find IP address
                 if let Some(node) = state.peer_registry.get_node(&node_id).await {
-                    if let Some(&ip_addr) = node.addresses.first() {
-                        let target_addr = std::net::SocketAddr::new(ip_addr, 8081);
+                    let local_addresses = crate::discovery::interfaces::local_addresses();
+                    if let Some(ip_addr) = node.preferred_address(&local_addresses) {
+                        let target_addr =
+                            std::net::SocketAddr::new(ip_addr, bandwidth_service.test_port());
                         let bandwidth_service = bandwidth_service.clone();
                         let state_clone = state.clone();
                         let test_id_clone = test_id.clone();
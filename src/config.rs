@@ -3,54 +3,82 @@
 
 use clap::Parser;
 use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::path::PathBuf;
 use tracing::Level;
 
+use crate::discovery::trust::{parse_trust_mode, TrustMode};
+
 /// Network Route Visualizer - Visualize routing tables and network topology in 3D
 #[derive(Parser, Debug, Clone)]
 #[command(name = "network-route-visualizer")]
 #[command(author, version, about, long_about = None)]
 pub struct CliArgs {
-    /// Web server port
-    #[arg(short, long, default_value = "8080", env = "NRV_PORT")]
-    pub port: u16,
+    /// Web server port [default: 8080]
+    #[arg(short, long, env = "NRV_PORT")]
+    pub port: Option<u16>,
 
     /// Don't auto-open browser
     #[arg(long, env = "NRV_NO_BROWSER")]
     pub no_browser: bool,
 
-    /// Logging level (error, warn, info, debug, trace)
-    #[arg(short, long, default_value = "info", env = "RUST_LOG")]
-    pub log_level: String,
+    /// Logging level (error, warn, info, debug, trace) [default: info]
+    #[arg(short, long, env = "RUST_LOG")]
+    pub log_level: Option<String>,
 
     /// Path to configuration file
     #[arg(short, long, env = "NRV_CONFIG")]
     pub config: Option<PathBuf>,
 
-    /// Discovery interval in seconds
-    #[arg(long, default_value = "30", env = "NRV_DISCOVERY_INTERVAL")]
-    pub discovery_interval: u64,
+    /// Discovery interval in seconds [default: 30]
+    #[arg(long, env = "NRV_DISCOVERY_INTERVAL")]
+    pub discovery_interval: Option<u64>,
 
-    /// Peer timeout in seconds
-    #[arg(long, default_value = "90", env = "NRV_PEER_TIMEOUT")]
-    pub peer_timeout: u64,
+    /// Peer timeout in seconds [default: 90]
+    #[arg(long, env = "NRV_PEER_TIMEOUT")]
+    pub peer_timeout: Option<u64>,
 
-    /// Ping interval in seconds
-    #[arg(long, default_value = "60", env = "NRV_PING_INTERVAL")]
-    pub ping_interval: u64,
+    /// Ping interval in seconds [default: 60]
+    #[arg(long, env = "NRV_PING_INTERVAL")]
+    pub ping_interval: Option<u64>,
 
-    /// Bandwidth test duration in seconds
-    #[arg(long, default_value = "10", env = "NRV_BANDWIDTH_DURATION")]
-    pub bandwidth_duration: u64,
+    /// Bandwidth test duration in seconds [default: 10]
+    #[arg(long, env = "NRV_BANDWIDTH_DURATION")]
+    pub bandwidth_duration: Option<u64>,
 
-    /// Bandwidth test port
-    #[arg(long, default_value = "9090", env = "NRV_BANDWIDTH_PORT")]
-    pub bandwidth_port: u16,
+    /// Bandwidth test port [default: 9090]
+    #[arg(long, env = "NRV_BANDWIDTH_PORT")]
+    pub bandwidth_port: Option<u16>,
+
+    /// DNS/IPv6 leak check interval in seconds [default: 300]
+    #[arg(long, env = "NRV_LEAK_CHECK_INTERVAL")]
+    pub leak_check_interval: Option<u64>,
+
+    /// Directory for persistent state (node ID)
+    #[arg(long, env = "NRV_STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Override the persisted node ID
+    #[arg(long, env = "NRV_NODE_ID")]
+    pub node_id: Option<String>,
+
+    /// Static seed peer (host:port or DNS name), may be repeated
+    #[arg(long = "seed-peer", env = "NRV_SEED_PEERS", value_delimiter = ',')]
+    pub seed_peers: Vec<String>,
 
     /// Disable node discovery
     #[arg(long, env = "NRV_NO_DISCOVERY")]
     pub no_discovery: bool,
 
+    /// Disable a discovery source (multicast, wireguard, vpn_scan, tailscale, zerotier,
+    /// openvpn, gossip, static, mdns), may be repeated
+    #[arg(
+        long = "disable-source",
+        env = "NRV_DISABLE_SOURCES",
+        value_delimiter = ','
+    )]
+    pub disable_sources: Vec<String>,
+
     /// Disable automatic ping
     #[arg(long, env = "NRV_NO_PING")]
     pub no_ping: bool,
@@ -89,6 +117,14 @@ pub struct ServerConfig {
     /// Bind address (default: 127.0.0.1)
     #[serde(default = "default_bind_address")]
     pub bind_address: String,
+
+    /// Directory for persistent state such as the node ID
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+
+    /// Fixed node ID instead of the persisted one
+    #[serde(default)]
+    pub node_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -109,9 +145,85 @@ pub struct DiscoveryConfig {
     #[serde(default = "default_multicast_group")]
     pub multicast_group: String,
 
+    /// IPv6 multicast group, joined on every interface with IPv6
+    #[serde(default = "default_multicast_group_v6")]
+    pub multicast_group_v6: String,
+
     /// Multicast port
     #[serde(default = "default_multicast_port")]
     pub multicast_port: u16,
+
+    /// How to trust signing keys of new peers ("tofu" or "allowlist")
+    #[serde(default = "default_trust_mode")]
+    pub trust_mode: String,
+
+    /// Hex-encoded ed25519 public keys that are always trusted
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+
+    /// Peers to contact directly ("host:port", or DNS names resolving to several nodes)
+    #[serde(default)]
+    pub seed_peers: Vec<String>,
+
+    /// Most nodes kept in the registry
+    #[serde(default = "default_max_peers")]
+    pub max_peers: usize,
+
+    /// Discovery datagrams accepted per second from one source address
+    #[serde(default = "default_max_packets_per_source")]
+    pub max_packets_per_source: u32,
+
+    /// OpenVPN server status file to read clients from
+    #[serde(default)]
+    pub openvpn_status: Option<PathBuf>,
+
+    /// OpenVPN management interface ("host:port" or a unix socket path),
+    /// asked instead of the status file
+    #[serde(default)]
+    pub openvpn_management: Option<String>,
+
+    /// Which discovery mechanisms to use
+    #[serde(default)]
+    pub sources: SourcesConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourcesConfig {
+    /// Announce to the LAN multicast group
+    #[serde(default = "default_true")]
+    pub multicast: bool,
+
+    /// Unicast announcements to WireGuard peers, through the tunnel and to their endpoints
+    #[serde(default = "default_true")]
+    pub wireguard: bool,
+
+    /// Ping-sweep VPN subnets and unicast to responders
+    #[serde(default = "default_true")]
+    pub vpn_scan: bool,
+
+    /// Unicast announcements to the peers `tailscale status` lists
+    #[serde(default = "default_true")]
+    pub tailscale: bool,
+
+    /// Unicast announcements to the peers `zerotier-cli listpeers` lists
+    #[serde(default = "default_true")]
+    pub zerotier: bool,
+
+    /// Unicast announcements to the clients of a local OpenVPN server
+    #[serde(default = "default_true")]
+    pub openvpn: bool,
+
+    /// Unicast announcements to nodes learned through gossip
+    #[serde(default = "default_true")]
+    pub gossip: bool,
+
+    /// Poll `seed_peers` over HTTP
+    #[serde(default = "default_true", rename = "static")]
+    pub static_peers: bool,
+
+    /// Publish and browse _nrv._tcp.local over mDNS
+    #[serde(default = "default_true")]
+    pub mdns: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -131,6 +243,10 @@ pub struct TestingConfig {
     /// Bandwidth test port
     #[serde(default = "default_bandwidth_port")]
     pub bandwidth_port: u16,
+
+    /// DNS/IPv6 leak check interval in seconds
+    #[serde(default = "default_leak_check_interval")]
+    pub leak_check_interval_seconds: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -163,9 +279,21 @@ fn default_peer_timeout() -> u64 {
 fn default_multicast_group() -> String {
     "239.255.42.1".to_string()
 }
+fn default_multicast_group_v6() -> String {
+    "ff02::42:1".to_string()
+}
 fn default_multicast_port() -> u16 {
     5678
 }
+fn default_trust_mode() -> String {
+    "tofu".to_string()
+}
+fn default_max_peers() -> usize {
+    crate::discovery::DEFAULT_MAX_NODES
+}
+fn default_max_packets_per_source() -> u32 {
+    20
+}
 fn default_ping_interval() -> u64 {
     60
 }
@@ -175,6 +303,9 @@ fn default_bandwidth_duration() -> u64 {
 fn default_bandwidth_port() -> u16 {
     9090
 }
+fn default_leak_check_interval() -> u64 {
+    300
+}
 fn default_log_level() -> String {
     "info".to_string()
 }
@@ -185,6 +316,8 @@ impl Default for ServerConfig {
             port: default_port(),
             auto_open_browser: default_true(),
             bind_address: default_bind_address(),
+            state_dir: None,
+            node_id: None,
         }
     }
 }
@@ -196,11 +329,56 @@ impl Default for DiscoveryConfig {
             interval_seconds: default_discovery_interval(),
             timeout_seconds: default_peer_timeout(),
             multicast_group: default_multicast_group(),
+            multicast_group_v6: default_multicast_group_v6(),
             multicast_port: default_multicast_port(),
+            trust_mode: default_trust_mode(),
+            trusted_keys: Vec::new(),
+            seed_peers: Vec::new(),
+            max_peers: default_max_peers(),
+            max_packets_per_source: default_max_packets_per_source(),
+            openvpn_status: None,
+            openvpn_management: None,
+            sources: SourcesConfig::default(),
         }
     }
 }
 
+impl Default for SourcesConfig {
+    fn default() -> Self {
+        SourcesConfig {
+            multicast: default_true(),
+            wireguard: default_true(),
+            vpn_scan: default_true(),
+            tailscale: default_true(),
+            zerotier: default_true(),
+            openvpn: default_true(),
+            gossip: default_true(),
+            static_peers: default_true(),
+            mdns: default_true(),
+        }
+    }
+}
+
+impl SourcesConfig {
+    /// Turn off a source by the name used in config and `/api/nodes`
+    pub fn disable(&mut self, name: &str) -> Result<(), String> {
+        let flag = match name.trim().to_lowercase().as_str() {
+            "multicast" => &mut self.multicast,
+            "wireguard" => &mut self.wireguard,
+            "vpn_scan" => &mut self.vpn_scan,
+            "tailscale" => &mut self.tailscale,
+            "zerotier" => &mut self.zerotier,
+            "openvpn" => &mut self.openvpn,
+            "gossip" => &mut self.gossip,
+            "static" => &mut self.static_peers,
+            "mdns" => &mut self.mdns,
+            _ => return Err(format!("Unknown discovery source: {}", name)),
+        };
+        *flag = false;
+        Ok(())
+    }
+}
+
 impl Default for TestingConfig {
     fn default() -> Self {
         TestingConfig {
@@ -208,6 +386,7 @@ impl Default for TestingConfig {
             ping_interval_seconds: default_ping_interval(),
             bandwidth_test_duration: default_bandwidth_duration(),
             bandwidth_port: default_bandwidth_port(),
+            leak_check_interval_seconds: default_leak_check_interval(),
         }
     }
 }
@@ -227,15 +406,28 @@ pub struct Config {
     pub port: u16,
     pub auto_open_browser: bool,
     pub bind_address: String,
+    pub state_dir: PathBuf,
+    pub node_id: Option<String>,
     pub log_level: Level,
     pub discovery_enabled: bool,
     pub discovery_interval: u64,
     pub peer_timeout: u64,
-    pub multicast_group: String,
+    pub multicast_group: Ipv4Addr,
+    pub multicast_group_v6: Ipv6Addr,
     pub multicast_port: u16,
+    pub trust_mode: TrustMode,
+    pub trusted_keys: Vec<String>,
+    pub seed_peers: Vec<String>,
+    pub max_peers: usize,
+    pub max_packets_per_source: u32,
+    pub openvpn_status: Option<PathBuf>,
+    pub openvpn_management: Option<String>,
+    pub sources: SourcesConfig,
     pub ping_enabled: bool,
     pub ping_interval: u64,
+    pub bandwidth_duration: u64,
     pub bandwidth_port: u16,
+    pub leak_check_interval: u64,
 }
 
 impl Config {
@@ -269,36 +461,118 @@ impl Config {
             loaded_config.unwrap_or_default()
         };
 
-        // Merge configuration (CLI args override config file)
-        let port = cli_args.port;
+        Self::merge(cli_args, config_file)
+    }
+
+    /// Merge CLI args over a config file; anything left unset on the command
+    /// line comes from the file, or its defaults
+    pub fn merge(cli_args: CliArgs, config_file: ConfigFile) -> anyhow::Result<Self> {
+        let port = cli_args.port.unwrap_or(config_file.server.port);
         let auto_open_browser = !cli_args.no_browser && config_file.server.auto_open_browser;
         let bind_address = config_file.server.bind_address;
+        let Some(state_dir) = cli_args
+            .state_dir
+            .or(config_file.server.state_dir)
+            .or_else(crate::discovery::identity::default_state_dir)
+        else {
+            anyhow::bail!(
+                "No state directory for the node ID and key: set --state-dir (NRV_STATE_DIR), server.state_dir, XDG_STATE_HOME or HOME"
+            );
+        };
+        let node_id = cli_args.node_id.or(config_file.server.node_id);
 
-        let log_level = parse_log_level(&cli_args.log_level)?;
+        let log_level = parse_log_level(
+            cli_args
+                .log_level
+                .as_deref()
+                .unwrap_or(&config_file.logging.level),
+        )?;
 
         let discovery_enabled = !cli_args.no_discovery && config_file.discovery.enabled;
-        let discovery_interval = cli_args.discovery_interval;
-        let peer_timeout = cli_args.peer_timeout;
-        let multicast_group = config_file.discovery.multicast_group;
+        let discovery_interval = cli_args
+            .discovery_interval
+            .unwrap_or(config_file.discovery.interval_seconds);
+        let peer_timeout = cli_args
+            .peer_timeout
+            .unwrap_or(config_file.discovery.timeout_seconds);
+        let multicast_group = parse_multicast_group(&config_file.discovery.multicast_group)?;
+        let multicast_group_v6 = parse_multicast_group(&config_file.discovery.multicast_group_v6)?;
         let multicast_port = config_file.discovery.multicast_port;
+        let trust_mode =
+            parse_trust_mode(&config_file.discovery.trust_mode).map_err(anyhow::Error::msg)?;
+        let trusted_keys = config_file.discovery.trusted_keys;
+        let seed_peers = if cli_args.seed_peers.is_empty() {
+            config_file.discovery.seed_peers
+        } else {
+            cli_args.seed_peers
+        };
+
+        let max_peers = config_file.discovery.max_peers;
+        let max_packets_per_source = config_file.discovery.max_packets_per_source;
+        if max_peers == 0 || max_packets_per_source == 0 {
+            anyhow::bail!("max_peers and max_packets_per_source must be at least 1");
+        }
+
+        let openvpn_status = config_file.discovery.openvpn_status;
+        let openvpn_management = config_file.discovery.openvpn_management;
+
+        let mut sources = config_file.discovery.sources;
+        for name in &cli_args.disable_sources {
+            sources.disable(name).map_err(anyhow::Error::msg)?;
+        }
 
         let ping_enabled = !cli_args.no_ping && config_file.testing.ping_enabled;
-        let ping_interval = cli_args.ping_interval;
-        let bandwidth_port = cli_args.bandwidth_port;
+        let ping_interval = cli_args
+            .ping_interval
+            .unwrap_or(config_file.testing.ping_interval_seconds);
+        let bandwidth_duration = cli_args
+            .bandwidth_duration
+            .unwrap_or(config_file.testing.bandwidth_test_duration);
+        let bandwidth_port = cli_args
+            .bandwidth_port
+            .unwrap_or(config_file.testing.bandwidth_port);
+        let leak_check_interval = cli_args
+            .leak_check_interval
+            .unwrap_or(config_file.testing.leak_check_interval_seconds);
+
+        for (name, seconds) in [
+            ("discovery interval", discovery_interval),
+            ("peer timeout", peer_timeout),
+            ("ping interval", ping_interval),
+            ("bandwidth test duration", bandwidth_duration),
+            ("leak check interval", leak_check_interval),
+        ] {
+            if seconds == 0 {
+                anyhow::bail!("The {} must be at least 1 second", name);
+            }
+        }
 
         Ok(Config {
             port,
             auto_open_browser,
             bind_address,
+            state_dir,
+            node_id,
             log_level,
             discovery_enabled,
             discovery_interval,
             peer_timeout,
             multicast_group,
+            multicast_group_v6,
             multicast_port,
+            trust_mode,
+            trusted_keys,
+            seed_peers,
+            max_peers,
+            max_packets_per_source,
+            openvpn_status,
+            openvpn_management,
+            sources,
             ping_enabled,
             ping_interval,
+            bandwidth_duration,
             bandwidth_port,
+            leak_check_interval,
         })
     }
 }
@@ -314,6 +588,19 @@ fn parse_log_level(level_str: &str) -> anyhow::Result<Level> {
     }
 }
 
+fn parse_multicast_group<A>(group: &str) -> anyhow::Result<A>
+where
+    A: std::str::FromStr + Into<std::net::IpAddr> + Copy,
+{
+    let addr: A = group
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid multicast group: {}", group))?;
+    if !addr.into().is_multicast() {
+        anyhow::bail!("{} is not a multicast address", group);
+    }
+    Ok(addr)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -332,4 +619,83 @@ mod tests {
         assert!(matches!(parse_log_level("debug"), Ok(Level::DEBUG)));
         assert!(parse_log_level("invalid").is_err());
     }
+
+    #[test]
+    fn test_discovery_sources() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+            [discovery.sources]
+            vpn_scan = false
+            static = false
+            "#,
+        )
+        .unwrap();
+        let mut sources = config.discovery.sources;
+        assert!(sources.multicast);
+        assert!(!sources.vpn_scan);
+        assert!(!sources.static_peers);
+
+        sources.disable("mdns").unwrap();
+        assert!(!sources.mdns);
+        assert!(sources.tailscale);
+        sources.disable("openvpn").unwrap();
+        assert!(!sources.openvpn);
+        assert!(sources.disable("carrier-pigeon").is_err());
+    }
+
+    #[test]
+    fn test_merge_prefers_cli_over_file() {
+        let config_file: ConfigFile = toml::from_str(
+            r#"
+            [server]
+            port = 9000
+
+            [discovery]
+            interval_seconds = 10
+            timeout_seconds = 40
+            multicast_group = "239.1.2.3"
+            multicast_port = 6000
+
+            [testing]
+            ping_interval_seconds = 15
+            bandwidth_port = 9191
+            "#,
+        )
+        .unwrap();
+
+        let cli_args =
+            CliArgs::parse_from(["nrv", "--peer-timeout", "120", "--bandwidth-port", "9292"]);
+        let config = Config::merge(cli_args, config_file).unwrap();
+
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.discovery_interval, 10);
+        assert_eq!(config.peer_timeout, 120);
+        assert_eq!(config.multicast_group, Ipv4Addr::new(239, 1, 2, 3));
+        assert_eq!(
+            config.multicast_group_v6,
+            "ff02::42:1".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(config.multicast_port, 6000);
+        assert_eq!(config.ping_interval, 15);
+        assert_eq!(config.bandwidth_port, 9292);
+        assert_eq!(config.bandwidth_duration, 10);
+    }
+
+    #[test]
+    fn test_merge_rejects_bad_values() {
+        let mut config_file = ConfigFile::default();
+        config_file.discovery.multicast_group = "10.0.0.1".to_string();
+        assert!(Config::merge(CliArgs::parse_from(["nrv"]), config_file).is_err());
+
+        let mut config_file = ConfigFile::default();
+        config_file.discovery.multicast_group_v6 = "fe80::1".to_string();
+        assert!(Config::merge(CliArgs::parse_from(["nrv"]), config_file).is_err());
+
+        let cli_args = CliArgs::parse_from(["nrv", "--ping-interval", "0"]);
+        assert!(Config::merge(cli_args, ConfigFile::default()).is_err());
+
+        let mut config_file = ConfigFile::default();
+        config_file.discovery.max_packets_per_source = 0;
+        assert!(Config::merge(CliArgs::parse_from(["nrv"]), config_file).is_err());
+    }
 }
@@ -0,0 +1,678 @@
+// Connectivity diagnosis
+// Runs a fixed sequence of checks against a node or address (local route,
+// next-hop neighbour state, ping, TCP to the API port, remote route back) and
+// turns the first failure into a verdict with copy-paste fixes per platform
+
+use serde::Serialize;
+use std::net::{IpAddr, SocketAddr};
+use std::process::Command;
+use std::time::Duration;
+
+use super::firewall::{self, PacketVerdict};
+use crate::api::client::NodeClient;
+use crate::discovery::ping::PingService;
+use crate::discovery::vpn_scan::is_vpn_interface_name;
+use crate::discovery::{NodeInfo, PeerRegistry};
+use crate::routes::lookup::RouteEngine;
+use crate::routes::{parser, Route, RoutingTable};
+
+const TCP_CONNECT_TIMEOUT_SECS: u64 = 3;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    Route,
+    Neighbour,
+    Ping,
+    TcpConnect,
+    RouteBack,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+    Skipped,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub enum IssueType {
+    NoRoute,
+    GatewayUnreachable,
+    HostUnreachable,
+    PortUnreachable,
+    NoRouteBack,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Linux,
+    Macos,
+    Windows,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SuggestedFix {
+    pub platform: Platform,
+    pub description: String,
+    pub command: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticCheck {
+    pub check: CheckKind,
+    pub status: CheckStatus,
+    pub description: String,
+    /// Issue this check points at when it fails
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issue_type: Option<IssueType>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggested_fixes: Vec<SuggestedFix>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnosis {
+    pub issue_type: IssueType,
+    pub description: String,
+    pub suggested_fixes: Vec<SuggestedFix>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosisReport {
+    pub target: String,
+    /// Hostname of the node, when the target is a known peer
+    pub target_node: Option<String>,
+    /// Address the checks were run against
+    pub target_ip: IpAddr,
+    /// The node was only reported by other nodes, never heard from directly
+    pub known_via_gossip: bool,
+    pub reachable: bool,
+    pub checks: Vec<DiagnosticCheck>,
+    /// The first failing check, if any
+    pub diagnosis: Option<Diagnosis>,
+    pub checked_at: String,
+}
+
+/// A diagnosis target resolved to a peer and/or candidate addresses
+#[derive(Debug, Clone)]
+pub struct DiagnosisTarget {
+    pub target: String,
+    pub node: Option<NodeInfo>,
+    pub addresses: Vec<IpAddr>,
+}
+
+/// Resolve a node id, node hostname, IP address or DNS name
+pub async fn resolve_target(
+    peer_registry: &PeerRegistry,
+    target: &str,
+) -> Result<DiagnosisTarget, String> {
+    let nodes = peer_registry.get_all_nodes().await;
+
+    let node = nodes
+        .iter()
+        .find(|n| n.id == target)
+        .or_else(|| match target.parse::<IpAddr>() {
+            Ok(ip) => nodes.iter().find(|n| n.addresses.contains(&ip)),
+            Err(_) => nodes
+                .iter()
+                .find(|n| n.hostname.eq_ignore_ascii_case(target)),
+        })
+        .cloned();
+
+    let addresses = match (&node, target.parse::<IpAddr>()) {
+        (_, Ok(ip)) => vec![ip],
+        (Some(node), Err(_)) => node.addresses.clone(),
+        (None, Err(_)) => {
+            let mut ips: Vec<IpAddr> = Vec::new();
+            if let Ok(addrs) = tokio::net::lookup_host(format!("{}:0", target)).await {
+                for addr in addrs {
+                    if !ips.contains(&addr.ip()) {
+                        ips.push(addr.ip());
+                    }
+                }
+            }
+            ips
+        }
+    };
+
+    if addresses.is_empty() {
+        return Err(format!(
+            "{} is not a known node and could not be resolved",
+            target
+        ));
+    }
+
+    Ok(DiagnosisTarget {
+        target: target.to_string(),
+        node,
+        addresses,
+    })
+}
+
+/// Run every check against the target and summarise the first failure
+pub async fn run_diagnosis(
+    target: DiagnosisTarget,
+    port: Option<u16>,
+    client: &NodeClient,
+) -> Result<DiagnosisReport, String> {
+    let routing_table = tokio::task::spawn_blocking(parser::get_routing_table)
+        .await
+        .map_err(|e| format!("Task join error: {}", e))??;
+
+    // Prefer the first address we actually have a route to
+    let engine = RouteEngine::new(&routing_table);
+    let target_ip = target
+        .addresses
+        .iter()
+        .copied()
+        .find(|ip| engine.lookup(*ip).is_some())
+        .or_else(|| target.addresses.first().copied())
+        .ok_or_else(|| format!("No address for {}", target.target))?;
+    let route = engine.lookup(target_ip).cloned();
+
+    let mut checks = vec![check_route(target_ip, route.as_ref())];
+
+    let neighbour = match &route {
+        Some(route) => {
+            let next_hop = route.gateway.unwrap_or(target_ip);
+            let route = route.clone();
+            tokio::task::spawn_blocking(move || check_neighbour(next_hop, &route))
+                .await
+                .map_err(|e| format!("Task join error: {}", e))?
+        }
+        None => skipped(CheckKind::Neighbour, "No route, so no next hop to check"),
+    };
+    checks.push(neighbour);
+
+    let latency = PingService::ping_address(target_ip).await;
+    let mut ping = check_ping(target_ip, latency);
+
+    let port = port.or_else(|| target.node.as_ref().map(|n| n.port));
+    let (tcp, source_ip) = match port {
+        Some(port) => check_tcp(target_ip, port, route.as_ref()).await,
+        None => (
+            skipped(
+                CheckKind::TcpConnect,
+                "Target is not a known node and no port was given",
+            ),
+            None,
+        ),
+    };
+
+    // ICMP is often filtered; a working TCP connection proves the host is up
+    if ping.status == CheckStatus::Fail && tcp.status == CheckStatus::Pass {
+        ping.status = CheckStatus::Warn;
+        ping.issue_type = None;
+        ping.description = format!(
+            "{} does not answer ping but accepts TCP connections (ICMP is probably filtered)",
+            target_ip
+        );
+        ping.suggested_fixes.clear();
+    }
+    checks.push(ping);
+    checks.push(tcp);
+
+    // The peer sees our connection coming from `source_ip`; it needs a route back to it
+    let route_back = match (&target.node, source_ip) {
+        (Some(node), Some(source)) => match client.fetch_routing_table(node).await {
+            Ok(remote_table) => check_route_back(&remote_table, source),
+            Err(e) => DiagnosticCheck {
+                check: CheckKind::RouteBack,
+                status: CheckStatus::Skipped,
+                description: format!("Could not fetch the remote routing table: {}", e),
+                issue_type: None,
+                suggested_fixes: Vec::new(),
+            },
+        },
+        (Some(_), None) => skipped(
+            CheckKind::RouteBack,
+            "No local source address known for the route back",
+        ),
+        (None, _) => skipped(CheckKind::RouteBack, "Target is not a known node"),
+    };
+    checks.push(route_back);
+
+    let reachable = checks.iter().all(|c| c.status != CheckStatus::Fail);
+    let diagnosis = summarise(&checks);
+
+    Ok(DiagnosisReport {
+        target: target.target,
+        target_node: target.node.as_ref().map(|n| n.hostname.clone()),
+        target_ip,
+        known_via_gossip: target
+            .node
+            .as_ref()
+            .is_some_and(|n| n.learned_from.is_some()),
+        reachable,
+        checks,
+        diagnosis,
+        checked_at: chrono::Utc::now().to_rfc3339(),
+    })
+}
+
+/// Turn the first failing check into the headline diagnosis
+pub fn summarise(checks: &[DiagnosticCheck]) -> Option<Diagnosis> {
+    checks
+        .iter()
+        .find(|c| c.status == CheckStatus::Fail)
+        .and_then(|check| {
+            check.issue_type.map(|issue_type| Diagnosis {
+                issue_type,
+                description: check.description.clone(),
+                suggested_fixes: check.suggested_fixes.clone(),
+            })
+        })
+}
+
+fn skipped(check: CheckKind, description: &str) -> DiagnosticCheck {
+    DiagnosticCheck {
+        check,
+        status: CheckStatus::Skipped,
+        description: description.to_string(),
+        issue_type: None,
+        suggested_fixes: Vec::new(),
+    }
+}
+
+fn check_route(target: IpAddr, route: Option<&Route>) -> DiagnosticCheck {
+    match route {
+        Some(route) => DiagnosticCheck {
+            check: CheckKind::Route,
+            status: CheckStatus::Pass,
+            description: format!(
+                "{} is routed via {} ({})",
+                target,
+                route.interface,
+                route
+                    .gateway
+                    .map(|g| format!("gateway {}", g))
+                    .unwrap_or_else(|| "directly connected".to_string())
+            ),
+            issue_type: None,
+            suggested_fixes: Vec::new(),
+        },
+        None => DiagnosticCheck {
+            check: CheckKind::Route,
+            status: CheckStatus::Fail,
+            description: format!("No route exists to {}", target),
+            issue_type: Some(IssueType::NoRoute),
+            suggested_fixes: add_route_fixes(target, "<gateway>", "<interface>"),
+        },
+    }
+}
+
+/// Check the kernel's neighbour (ARP/NDP) entry for the next hop
+fn check_neighbour(next_hop: IpAddr, route: &Route) -> DiagnosticCheck {
+    // Point-to-point tunnels have no link layer, so there is nothing to resolve
+    if route.gateway.is_none() && is_vpn_interface_name(&route.interface) {
+        return DiagnosticCheck {
+            check: CheckKind::Neighbour,
+            status: CheckStatus::Pass,
+            description: format!("{} is a point-to-point interface", route.interface),
+            issue_type: None,
+            suggested_fixes: Vec::new(),
+        };
+    }
+
+    let output = Command::new("ip")
+        .args(["-json", "neigh", "show", "to"])
+        .arg(next_hop.to_string())
+        .args(["dev", &route.interface])
+        .output();
+
+    let state = match output {
+        Ok(output) if output.status.success() => {
+            parse_neighbour_state(&String::from_utf8_lossy(&output.stdout), next_hop)
+        }
+        Ok(_) | Err(_) => {
+            return skipped(
+                CheckKind::Neighbour,
+                "Neighbour table is not available on this platform",
+            );
+        }
+    };
+
+    match state.as_deref() {
+        Some("REACHABLE") | Some("STALE") | Some("DELAY") | Some("PROBE") | Some("PERMANENT")
+        | Some("NOARP") => DiagnosticCheck {
+            check: CheckKind::Neighbour,
+            status: CheckStatus::Pass,
+            description: format!(
+                "Next hop {} on {} is {}",
+                next_hop,
+                route.interface,
+                state.as_deref().unwrap_or_default().to_lowercase()
+            ),
+            issue_type: None,
+            suggested_fixes: Vec::new(),
+        },
+        Some(state) => DiagnosticCheck {
+            check: CheckKind::Neighbour,
+            status: CheckStatus::Fail,
+            description: format!(
+                "Next hop {} on {} does not answer ARP/NDP ({})",
+                next_hop,
+                route.interface,
+                state.to_lowercase()
+            ),
+            issue_type: Some(IssueType::GatewayUnreachable),
+            suggested_fixes: neighbour_fixes(next_hop, &route.interface),
+        },
+        None => DiagnosticCheck {
+            check: CheckKind::Neighbour,
+            status: CheckStatus::Warn,
+            description: format!(
+                "No neighbour entry for next hop {} on {} yet",
+                next_hop, route.interface
+            ),
+            issue_type: None,
+            suggested_fixes: Vec::new(),
+        },
+    }
+}
+
+/// Extract the state of `address` from `ip -json neigh` output
+pub fn parse_neighbour_state(json: &str, address: IpAddr) -> Option<String> {
+    let entries: Vec<serde_json::Value> = serde_json::from_str(json).ok()?;
+    let address = address.to_string();
+
+    entries
+        .iter()
+        .find(|e| e.get("dst").and_then(|d| d.as_str()) == Some(address.as_str()))
+        .and_then(|e| e.get("state"))
+        .and_then(|s| s.as_array())
+        .and_then(|states| states.first())
+        .and_then(|s| s.as_str())
+        .map(String::from)
+}
+
+fn check_ping(target: IpAddr, latency_ms: Option<f64>) -> DiagnosticCheck {
+    match latency_ms {
+        Some(latency) => DiagnosticCheck {
+            check: CheckKind::Ping,
+            status: CheckStatus::Pass,
+            description: format!("{} answered ping in {:.1} ms", target, latency),
+            issue_type: None,
+            suggested_fixes: Vec::new(),
+        },
+        None => DiagnosticCheck {
+            check: CheckKind::Ping,
+            status: CheckStatus::Fail,
+            description: format!("{} does not answer ping", target),
+            issue_type: Some(IssueType::HostUnreachable),
+            suggested_fixes: vec![
+                SuggestedFix {
+                    platform: Platform::Linux,
+                    description: "Trace where packets stop".to_string(),
+                    command: format!("traceroute -n {}", target),
+                },
+                SuggestedFix {
+                    platform: Platform::Macos,
+                    description: "Trace where packets stop".to_string(),
+                    command: format!("traceroute -n {}", target),
+                },
+                SuggestedFix {
+                    platform: Platform::Windows,
+                    description: "Trace where packets stop".to_string(),
+                    command: format!("tracert -d {}", target),
+                },
+            ],
+        },
+    }
+}
+
+/// Try a TCP connection, returning the check and the local source address used
+async fn check_tcp(
+    target: IpAddr,
+    port: u16,
+    route: Option<&Route>,
+) -> (DiagnosticCheck, Option<IpAddr>) {
+    let addr = SocketAddr::new(target, port);
+    let result = tokio::time::timeout(
+        Duration::from_secs(TCP_CONNECT_TIMEOUT_SECS),
+        tokio::net::TcpStream::connect(addr),
+    )
+    .await;
+
+    let reason = match result {
+        Ok(Ok(stream)) => {
+            let source = stream.local_addr().ok().map(|a| a.ip());
+            return (
+                DiagnosticCheck {
+                    check: CheckKind::TcpConnect,
+                    status: CheckStatus::Pass,
+                    description: format!("Connected to {}", addr),
+                    issue_type: None,
+                    suggested_fixes: Vec::new(),
+                },
+                source,
+            );
+        }
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            "connection refused (nothing listening, or rejected by a firewall)".to_string()
+        }
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => format!(
+            "timed out after {}s (probably dropped by a firewall)",
+            TCP_CONNECT_TIMEOUT_SECS
+        ),
+    };
+
+    // Rule out our own firewall before blaming the remote side
+    let packet = firewall::Packet {
+        destination: target,
+        protocol: "tcp".to_string(),
+        port: Some(port),
+        out_interface: route.map(|r| r.interface.clone()),
+    };
+    let local_verdict = tokio::task::spawn_blocking(move || firewall::check_packet(&packet))
+        .await
+        .ok()
+        .flatten();
+
+    let description = match local_verdict {
+        Some(v) if v.verdict != PacketVerdict::Accept => format!(
+            "TCP connection to {} failed: {}; the local {} ruleset blocks it ({})",
+            addr,
+            reason,
+            v.source,
+            v.decided_by.unwrap_or_else(|| "chain policy".to_string())
+        ),
+        Some(_) | None => format!("TCP connection to {} failed: {}", addr, reason),
+    };
+
+    (
+        DiagnosticCheck {
+            check: CheckKind::TcpConnect,
+            status: CheckStatus::Fail,
+            description,
+            issue_type: Some(IssueType::PortUnreachable),
+            suggested_fixes: open_port_fixes(port),
+        },
+        None,
+    )
+}
+
+/// Check that the remote routing table has a route back to our source address
+pub fn check_route_back(remote_table: &RoutingTable, source: IpAddr) -> DiagnosticCheck {
+    let engine = RouteEngine::new(remote_table);
+    match engine.lookup(source) {
+        Some(route) => DiagnosticCheck {
+            check: CheckKind::RouteBack,
+            status: CheckStatus::Pass,
+            description: format!(
+                "{} routes replies to {} via {}",
+                remote_table.hostname, source, route.interface
+            ),
+            issue_type: None,
+            suggested_fixes: Vec::new(),
+        },
+        None => DiagnosticCheck {
+            check: CheckKind::RouteBack,
+            status: CheckStatus::Fail,
+            description: format!(
+                "{} has no route back to {}; run the fix on {}",
+                remote_table.hostname, source, remote_table.hostname
+            ),
+            issue_type: Some(IssueType::NoRouteBack),
+            suggested_fixes: add_route_fixes(source, "<gateway>", "<interface>"),
+        },
+    }
+}
+
+/// Commands that add a host route to `target` on each platform
+pub fn add_route_fixes(target: IpAddr, gateway: &str, interface: &str) -> Vec<SuggestedFix> {
+    let description = "Add a host route via the gateway that can reach it".to_string();
+    let (linux, macos, windows) = match target {
+        IpAddr::V4(_) => (
+            format!(
+                "sudo ip route add {}/32 via {} dev {}",
+                target, gateway, interface
+            ),
+            format!("sudo route -n add -host {} {}", target, gateway),
+            format!("route add {} mask 255.255.255.255 {}", target, gateway),
+        ),
+        IpAddr::V6(_) => (
+            format!(
+                "sudo ip -6 route add {}/128 via {} dev {}",
+                target, gateway, interface
+            ),
+            format!("sudo route -n add -inet6 -host {} {}", target, gateway),
+            format!(
+                "netsh interface ipv6 add route {}/128 \"{}\" {}",
+                target, interface, gateway
+            ),
+        ),
+    };
+
+    vec![
+        SuggestedFix {
+            platform: Platform::Linux,
+            description: description.clone(),
+            command: linux,
+        },
+        SuggestedFix {
+            platform: Platform::Macos,
+            description: description.clone(),
+            command: macos,
+        },
+        SuggestedFix {
+            platform: Platform::Windows,
+            description,
+            command: windows,
+        },
+    ]
+}
+
+fn neighbour_fixes(next_hop: IpAddr, interface: &str) -> Vec<SuggestedFix> {
+    vec![
+        SuggestedFix {
+            platform: Platform::Linux,
+            description: "Check the link is up and retry address resolution".to_string(),
+            command: format!(
+                "ip link show dev {} && sudo ip neigh flush dev {} && ping -c 3 {}",
+                interface, interface, next_hop
+            ),
+        },
+        SuggestedFix {
+            platform: Platform::Macos,
+            description: "Check the link is up and retry address resolution".to_string(),
+            command: format!(
+                "ifconfig {} && sudo arp -d {} && ping -c 3 {}",
+                interface, next_hop, next_hop
+            ),
+        },
+        SuggestedFix {
+            platform: Platform::Windows,
+            description: "Clear the neighbour cache and retry".to_string(),
+            command: format!(
+                "netsh interface ip delete arpcache && ping -n 3 {}",
+                next_hop
+            ),
+        },
+    ]
+}
+
+fn open_port_fixes(port: u16) -> Vec<SuggestedFix> {
+    let description = format!("Allow inbound TCP {} on the target", port);
+    vec![
+        SuggestedFix {
+            platform: Platform::Linux,
+            description: description.clone(),
+            command: format!(
+                "sudo iptables -I INPUT -p tcp --dport {} -j ACCEPT",
+                port
+            ),
+        },
+        SuggestedFix {
+            platform: Platform::Macos,
+            description: "Allow the server through the application firewall".to_string(),
+            command: "sudo /usr/libexec/ApplicationFirewall/socketfilterfw --unblockapp \"$(which network-route-visualizer)\"".to_string(),
+        },
+        SuggestedFix {
+            platform: Platform::Windows,
+            description,
+            command: format!(
+                "netsh advfirewall firewall add rule name=\"Network Route Visualizer\" dir=in action=allow protocol=TCP localport={}",
+                port
+            ),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::test_support::{route, table};
+
+    #[test]
+    fn test_parse_neighbour_state() {
+        let json = r#"[
+            {"dst":"192.168.1.1","dev":"eth0","lladdr":"aa:bb:cc:dd:ee:ff","state":["REACHABLE"]},
+            {"dst":"192.168.1.7","dev":"eth0","state":["FAILED"]}
+        ]"#;
+
+        assert_eq!(
+            parse_neighbour_state(json, "192.168.1.1".parse().unwrap()).as_deref(),
+            Some("REACHABLE")
+        );
+        assert_eq!(
+            parse_neighbour_state(json, "192.168.1.7".parse().unwrap()).as_deref(),
+            Some("FAILED")
+        );
+        assert!(parse_neighbour_state(json, "192.168.1.9".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_no_route_diagnosis_has_fixes_for_every_platform() {
+        let target: IpAddr = "10.20.0.15".parse().unwrap();
+        let checks = vec![
+            check_route(target, None),
+            skipped(CheckKind::Neighbour, "No route"),
+        ];
+
+        let diagnosis = summarise(&checks).unwrap();
+        assert_eq!(diagnosis.issue_type, IssueType::NoRoute);
+        assert_eq!(diagnosis.suggested_fixes.len(), 3);
+        assert!(diagnosis
+            .suggested_fixes
+            .iter()
+            .any(|f| f.platform == Platform::Windows
+                && f.command == "route add 10.20.0.15 mask 255.255.255.255 <gateway>"));
+    }
+
+    #[test]
+    fn test_route_back() {
+        let remote = table(vec![route("10.20.0.0/24", None, "wg0")]);
+
+        let ok = check_route_back(&remote, "10.20.0.2".parse().unwrap());
+        assert_eq!(ok.status, CheckStatus::Pass);
+
+        let missing = check_route_back(&remote, "192.168.1.10".parse().unwrap());
+        assert_eq!(missing.status, CheckStatus::Fail);
+        assert_eq!(missing.issue_type, Some(IssueType::NoRouteBack));
+    }
+}
@@ -0,0 +1,1055 @@
+// Firewall-aware path verdicts
+// Parses `nft -j list ruleset` (or `iptables-save` as a fallback) and walks the
+// output hook for a destination, protocol and port to predict whether a locally
+// generated packet is accepted, dropped, or marked for a different routing table
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::net::IpAddr;
+use std::process::Command;
+
+use crate::routes::lookup::{matches_cidr, RouteEngine};
+use crate::routes::{parser, Route, RoutingTable};
+
+/// Guards against rulesets whose jumps form a cycle
+const MAX_JUMP_DEPTH: usize = 16;
+
+/// A locally generated packet to evaluate against the output hook
+#[derive(Debug, Clone)]
+pub struct Packet {
+    pub destination: IpAddr,
+    /// "tcp", "udp" or "icmp"
+    pub protocol: String,
+    pub port: Option<u16>,
+    /// Egress interface chosen by the routing table
+    pub out_interface: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketVerdict {
+    Accept,
+    Drop,
+    Reject,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirewallVerdict {
+    pub verdict: PacketVerdict,
+    /// Ruleset the verdict came from ("nftables" or "iptables")
+    pub source: String,
+    /// Rule (or chain policy) that decided the verdict
+    pub decided_by: Option<String>,
+    /// fwmark set on the packet in the output hook
+    pub fwmark: Option<u32>,
+    /// Policy-routing table the fwmark selects, when it differs from main
+    pub policy_table: Option<String>,
+    /// Route the marked packet takes in that table
+    pub policy_route: Option<Route>,
+    /// Rules skipped because they use matches this evaluator doesn't understand
+    pub unevaluated_rules: usize,
+}
+
+/// Firewall ruleset normalised from nftables or iptables
+#[derive(Debug, Clone)]
+pub struct Ruleset {
+    pub source: String,
+    pub chains: Vec<Chain>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Chain {
+    /// "ip", "ip6" or "inet"
+    pub family: String,
+    pub table: String,
+    pub name: String,
+    /// Priority of base chains attached to the output hook
+    pub output_priority: Option<i32>,
+    pub policy: PacketVerdict,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub description: String,
+    pub matches: Vec<Match>,
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Match {
+    DestAddr {
+        cidrs: Vec<String>,
+        negate: bool,
+    },
+    Protocol {
+        protocols: Vec<String>,
+        negate: bool,
+    },
+    DestPort {
+        ranges: Vec<(u16, u16)>,
+        negate: bool,
+    },
+    OutInterface {
+        names: Vec<String>,
+        negate: bool,
+    },
+    CtState {
+        states: Vec<String>,
+        negate: bool,
+    },
+    Mark {
+        value: u32,
+        mask: u32,
+        negate: bool,
+    },
+    Family(String),
+    Unsupported(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Accept,
+    Drop,
+    Reject,
+    Return,
+    Jump(String),
+    Goto(String),
+    SetMark { value: u32, mask: u32 },
+    Unsupported(String),
+}
+
+/// Evaluate the packet against the live ruleset, or None if no ruleset is readable
+pub fn check_packet(packet: &Packet) -> Option<FirewallVerdict> {
+    let ruleset = load_ruleset(packet.destination)?;
+    let mut verdict = evaluate(&ruleset, packet);
+
+    if let Some(mark) = verdict.fwmark {
+        if let Some((table, route)) = policy_route_for_mark(mark, packet.destination) {
+            if table != "main" {
+                verdict.policy_table = Some(table);
+                verdict.policy_route = Some(route);
+            }
+        }
+    }
+
+    Some(verdict)
+}
+
+/// Read the ruleset from nftables, falling back to iptables-save/ip6tables-save
+fn load_ruleset(destination: IpAddr) -> Option<Ruleset> {
+    if let Ok(output) = Command::new("nft").args(["-j", "list", "ruleset"]).output() {
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            match parse_nft_json(&stdout) {
+                Ok(ruleset) => return Some(ruleset),
+                Err(e) => tracing::debug!("Failed to parse nft ruleset: {}", e),
+            }
+        }
+    }
+
+    let (command, family) = match destination {
+        IpAddr::V4(_) => ("iptables-save", "ip"),
+        IpAddr::V6(_) => ("ip6tables-save", "ip6"),
+    };
+
+    let output = Command::new(command).output().ok()?;
+    if !output.status.success() {
+        tracing::debug!("{} failed, skipping firewall evaluation", command);
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Some(parse_iptables_save(&stdout, family))
+}
+
+/// Parse `nft -j list ruleset`
+pub fn parse_nft_json(json_str: &str) -> Result<Ruleset, String> {
+    let root: Value =
+        serde_json::from_str(json_str).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    let objects = root
+        .get("nftables")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| "Missing nftables array".to_string())?;
+
+    let mut chains: Vec<Chain> = Vec::new();
+
+    for object in objects {
+        if let Some(chain) = object.get("chain") {
+            let hook = chain.get("hook").and_then(|v| v.as_str());
+            chains.push(Chain {
+                family: str_field(chain, "family"),
+                table: str_field(chain, "table"),
+                name: str_field(chain, "name"),
+                output_priority: match hook {
+                    Some("output") => chain
+                        .get("prio")
+                        .and_then(|v| v.as_i64())
+                        .map(|p| p as i32)
+                        .or(Some(0)),
+                    _ => None,
+                },
+                policy: match chain.get("policy").and_then(|v| v.as_str()) {
+                    Some("drop") => PacketVerdict::Drop,
+                    _ => PacketVerdict::Accept,
+                },
+                rules: Vec::new(),
+            });
+        } else if let Some(rule) = object.get("rule") {
+            let family = str_field(rule, "family");
+            let table = str_field(rule, "table");
+            let chain_name = str_field(rule, "chain");
+            let handle = rule.get("handle").and_then(|v| v.as_u64()).unwrap_or(0);
+
+            let parsed = parse_nft_rule(rule.get("expr").and_then(|v| v.as_array()));
+            let chain = chains
+                .iter_mut()
+                .find(|c| c.family == family && c.table == table && c.name == chain_name);
+
+            if let Some(chain) = chain {
+                chain.rules.push(Rule {
+                    description: format!("{} {} {} handle {}", family, table, chain_name, handle),
+                    matches: parsed.0,
+                    statements: parsed.1,
+                });
+            }
+        }
+    }
+
+    Ok(Ruleset {
+        source: "nftables".to_string(),
+        chains,
+    })
+}
+
+fn parse_nft_rule(exprs: Option<&Vec<Value>>) -> (Vec<Match>, Vec<Statement>) {
+    let mut matches = Vec::new();
+    let mut statements = Vec::new();
+
+    for expr in exprs.into_iter().flatten() {
+        if let Some(m) = expr.get("match") {
+            let negate = m.get("op").and_then(|v| v.as_str()) == Some("!=");
+            let left = m.get("left").unwrap_or(&Value::Null);
+            let right = nft_values(m.get("right").unwrap_or(&Value::Null));
+
+            if let Some(payload) = left.get("payload") {
+                let protocol = payload.get("protocol").and_then(|v| v.as_str());
+                let field = payload.get("field").and_then(|v| v.as_str());
+                match (protocol, field) {
+                    (Some("ip"), Some("daddr")) | (Some("ip6"), Some("daddr")) => {
+                        matches.push(Match::Family(protocol.unwrap_or_default().to_string()));
+                        matches.push(Match::DestAddr {
+                            cidrs: right,
+                            negate,
+                        });
+                    }
+                    (Some("ip"), Some("protocol")) | (Some("ip6"), Some("nexthdr")) => {
+                        matches.push(Match::Protocol {
+                            protocols: right,
+                            negate,
+                        });
+                    }
+                    (Some(proto), Some("dport")) => {
+                        if proto != "th" {
+                            matches.push(Match::Protocol {
+                                protocols: vec![proto.to_string()],
+                                negate: false,
+                            });
+                        }
+                        matches.push(Match::DestPort {
+                            ranges: right.iter().filter_map(|v| parse_port_range(v)).collect(),
+                            negate,
+                        });
+                    }
+                    _ => matches.push(Match::Unsupported(format!(
+                        "{} {}",
+                        protocol.unwrap_or("?"),
+                        field.unwrap_or("?")
+                    ))),
+                }
+            } else if let Some(meta) = left.get("meta") {
+                match meta.get("key").and_then(|v| v.as_str()) {
+                    Some("oifname") => matches.push(Match::OutInterface {
+                        names: right,
+                        negate,
+                    }),
+                    Some("l4proto") => matches.push(Match::Protocol {
+                        protocols: right,
+                        negate,
+                    }),
+                    Some("nfproto") => {
+                        if let Some(family) = right.first() {
+                            let family = if family == "ipv6" { "ip6" } else { "ip" };
+                            matches.push(Match::Family(family.to_string()));
+                        }
+                    }
+                    Some("mark") => matches.push(Match::Mark {
+                        value: right.first().and_then(|v| parse_mark(v)).unwrap_or(0),
+                        mask: u32::MAX,
+                        negate,
+                    }),
+                    other => {
+                        matches.push(Match::Unsupported(format!("meta {}", other.unwrap_or("?"))))
+                    }
+                }
+            } else if left
+                .get("ct")
+                .and_then(|ct| ct.get("key"))
+                .and_then(|v| v.as_str())
+                == Some("state")
+            {
+                matches.push(Match::CtState {
+                    states: right,
+                    negate,
+                });
+            } else {
+                matches.push(Match::Unsupported(left.to_string()));
+            }
+        } else if expr.get("accept").is_some() {
+            statements.push(Statement::Accept);
+        } else if expr.get("drop").is_some() {
+            statements.push(Statement::Drop);
+        } else if expr.get("reject").is_some() {
+            statements.push(Statement::Reject);
+        } else if expr.get("return").is_some() {
+            statements.push(Statement::Return);
+        } else if let Some(target) = expr.pointer("/jump/target").and_then(|v| v.as_str()) {
+            statements.push(Statement::Jump(target.to_string()));
+        } else if let Some(target) = expr.pointer("/goto/target").and_then(|v| v.as_str()) {
+            statements.push(Statement::Goto(target.to_string()));
+        } else if let Some(mangle) = expr.get("mangle") {
+            let sets_mark =
+                mangle.pointer("/key/meta/key").and_then(|v| v.as_str()) == Some("mark");
+            match mangle.get("value").and_then(parse_mark_value) {
+                Some(value) if sets_mark => statements.push(Statement::SetMark {
+                    value,
+                    mask: u32::MAX,
+                }),
+                _ => statements.push(Statement::Unsupported(format!("mangle {}", mangle))),
+            }
+        } else if expr.get("counter").is_none() && expr.get("log").is_none() {
+            statements.push(Statement::Unsupported(expr.to_string()));
+        }
+    }
+
+    (matches, statements)
+}
+
+/// Flatten an nft right-hand side (scalar, set, prefix, range or list) into strings
+fn nft_values(value: &Value) -> Vec<String> {
+    if let Some(s) = value.as_str() {
+        return vec![s.to_string()];
+    }
+    if let Some(n) = value.as_u64() {
+        return vec![n.to_string()];
+    }
+    if let Some(items) = value.as_array() {
+        return items.iter().flat_map(nft_values).collect();
+    }
+    if let Some(items) = value.get("set") {
+        return nft_values(items);
+    }
+    if let Some(prefix) = value.get("prefix") {
+        let addr = prefix.get("addr").and_then(|v| v.as_str()).unwrap_or("");
+        let len = prefix.get("len").and_then(|v| v.as_u64()).unwrap_or(0);
+        return vec![format!("{}/{}", addr, len)];
+    }
+    if let Some(range) = value.get("range").and_then(|v| v.as_array()) {
+        let bounds: Vec<String> = range.iter().flat_map(nft_values).collect();
+        return vec![bounds.join("-")];
+    }
+    Vec::new()
+}
+
+fn parse_mark_value(value: &Value) -> Option<u32> {
+    value
+        .as_u64()
+        .map(|n| n as u32)
+        .or_else(|| value.as_str().and_then(parse_mark))
+}
+
+/// Parse `iptables-save` output for one address family ("ip" or "ip6")
+pub fn parse_iptables_save(output: &str, family: &str) -> Ruleset {
+    let mut chains: Vec<Chain> = Vec::new();
+    let mut table = String::new();
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        if let Some(name) = line.strip_prefix('*') {
+            table = name.to_string();
+        } else if let Some(declaration) = line.strip_prefix(':') {
+            let mut parts = declaration.split_whitespace();
+            let name = parts.next().unwrap_or_default().to_string();
+            let policy = match parts.next() {
+                Some("DROP") => PacketVerdict::Drop,
+                _ => PacketVerdict::Accept,
+            };
+            // Built-in OUTPUT chains run in netfilter's fixed table order
+            let output_priority = match (name.as_str(), table.as_str()) {
+                ("OUTPUT", "raw") => Some(-300),
+                ("OUTPUT", "mangle") => Some(-150),
+                ("OUTPUT", "filter") => Some(0),
+                ("OUTPUT", "security") => Some(50),
+                _ => None,
+            };
+            chains.push(Chain {
+                family: family.to_string(),
+                table: table.clone(),
+                name,
+                output_priority,
+                policy,
+                rules: Vec::new(),
+            });
+        } else if let Some(rule) = line.strip_prefix("-A ") {
+            let tokens = tokenize(rule);
+            let Some((chain_name, args)) = tokens.split_first() else {
+                continue;
+            };
+            let (matches, statements) = parse_iptables_rule(args);
+            if let Some(chain) = chains
+                .iter_mut()
+                .find(|c| c.table == table && &c.name == chain_name)
+            {
+                chain.rules.push(Rule {
+                    description: format!("-t {} -A {}", table, rule),
+                    matches,
+                    statements,
+                });
+            }
+        }
+    }
+
+    Ruleset {
+        source: "iptables".to_string(),
+        chains,
+    }
+}
+
+fn parse_iptables_rule(args: &[String]) -> (Vec<Match>, Vec<Statement>) {
+    let mut matches = Vec::new();
+    let mut statements = Vec::new();
+    let mut negate = false;
+    let mut iter = args.iter().peekable();
+
+    while let Some(arg) = iter.next() {
+        let list = |value: Option<&String>| -> Vec<String> {
+            value
+                .map(|v| v.split(',').map(|s| s.to_string()).collect())
+                .unwrap_or_default()
+        };
+
+        match arg.as_str() {
+            "!" => {
+                negate = true;
+                continue;
+            }
+            "-d" | "--destination" => matches.push(Match::DestAddr {
+                cidrs: list(iter.next()),
+                negate,
+            }),
+            "-p" | "--protocol" => matches.push(Match::Protocol {
+                protocols: list(iter.next()),
+                negate,
+            }),
+            "-o" | "--out-interface" => matches.push(Match::OutInterface {
+                names: list(iter.next()),
+                negate,
+            }),
+            "--dport" | "--destination-port" | "--dports" | "--destination-ports" => {
+                matches.push(Match::DestPort {
+                    ranges: list(iter.next())
+                        .iter()
+                        .filter_map(|p| parse_port_range(&p.replace(':', "-")))
+                        .collect(),
+                    negate,
+                })
+            }
+            "--ctstate" | "--state" => matches.push(Match::CtState {
+                states: list(iter.next()).iter().map(|s| s.to_lowercase()).collect(),
+                negate,
+            }),
+            "--mark" => {
+                let (value, mask) = iter.next().map(|v| parse_mark_mask(v)).unwrap_or((0, 0));
+                matches.push(Match::Mark {
+                    value,
+                    mask,
+                    negate,
+                });
+            }
+            "-j" | "--jump" | "-g" | "--goto" => {
+                let goto = arg == "-g" || arg == "--goto";
+                match iter.next().map(|s| s.as_str()) {
+                    Some("ACCEPT") => statements.push(Statement::Accept),
+                    Some("DROP") => statements.push(Statement::Drop),
+                    Some("REJECT") => statements.push(Statement::Reject),
+                    Some("RETURN") => statements.push(Statement::Return),
+                    // Mark value follows as --set-mark/--set-xmark
+                    Some("MARK") => {}
+                    Some("LOG") | Some("NFLOG") => {}
+                    Some(target) if goto => statements.push(Statement::Goto(target.to_string())),
+                    Some(target) => statements.push(Statement::Jump(target.to_string())),
+                    None => {}
+                }
+            }
+            "--set-mark" | "--set-xmark" => {
+                if let Some(value) = iter.next() {
+                    let (value, mask) = parse_mark_mask(value);
+                    statements.push(Statement::SetMark { value, mask });
+                }
+            }
+            // Modules, comments and reject types carry a value but don't affect matching
+            "-m" | "--match" | "--comment" | "--reject-with" | "--log-prefix" => {
+                iter.next();
+            }
+            other => {
+                // Unknown option: skip its value so it isn't misread as an option
+                if iter.peek().is_some_and(|next| !next.starts_with('-')) {
+                    iter.next();
+                }
+                matches.push(Match::Unsupported(other.to_string()));
+            }
+        }
+        negate = false;
+    }
+
+    (matches, statements)
+}
+
+/// Split an iptables-save rule into arguments, keeping quoted strings together
+fn tokenize(rule: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in rule.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    tokens
+}
+
+/// Running state while walking the output hook
+struct Evaluation<'a> {
+    ruleset: &'a Ruleset,
+    packet: &'a Packet,
+    mark: u32,
+    mark_set: bool,
+    unevaluated_rules: usize,
+}
+
+enum ChainOutcome {
+    Verdict(PacketVerdict, String),
+    /// Fell off the end of the chain (or hit `return`)
+    Continue,
+}
+
+/// Walk every output-hook base chain in priority order
+pub fn evaluate(ruleset: &Ruleset, packet: &Packet) -> FirewallVerdict {
+    let mut base_chains: Vec<&Chain> = ruleset
+        .chains
+        .iter()
+        .filter(|c| c.output_priority.is_some() && family_applies(&c.family, packet))
+        .collect();
+    base_chains.sort_by_key(|c| c.output_priority);
+
+    let mut eval = Evaluation {
+        ruleset,
+        packet,
+        mark: 0,
+        mark_set: false,
+        unevaluated_rules: 0,
+    };
+    let mut decided_by = None;
+
+    for chain in base_chains {
+        let (verdict, by) = match eval.run_chain(chain, 0) {
+            ChainOutcome::Verdict(verdict, by) => (verdict, by),
+            ChainOutcome::Continue => (
+                chain.policy,
+                format!("{} {} {} policy", chain.family, chain.table, chain.name),
+            ),
+        };
+
+        // Accept only ends this chain; later chains on the same hook still run
+        match verdict {
+            PacketVerdict::Accept => decided_by = Some(by),
+            PacketVerdict::Drop | PacketVerdict::Reject => {
+                return eval.finish(verdict, Some(by));
+            }
+        }
+    }
+
+    eval.finish(PacketVerdict::Accept, decided_by)
+}
+
+impl Evaluation<'_> {
+    fn finish(self, verdict: PacketVerdict, decided_by: Option<String>) -> FirewallVerdict {
+        FirewallVerdict {
+            verdict,
+            source: self.ruleset.source.clone(),
+            decided_by,
+            fwmark: self.mark_set.then_some(self.mark),
+            policy_table: None,
+            policy_route: None,
+            unevaluated_rules: self.unevaluated_rules,
+        }
+    }
+
+    fn run_chain(&mut self, chain: &Chain, depth: usize) -> ChainOutcome {
+        if depth > MAX_JUMP_DEPTH {
+            tracing::debug!("Firewall jump depth exceeded in chain {}", chain.name);
+            return ChainOutcome::Continue;
+        }
+
+        for rule in &chain.rules {
+            match self.rule_matches(rule) {
+                Some(true) => {}
+                Some(false) => continue,
+                None => {
+                    self.unevaluated_rules += 1;
+                    continue;
+                }
+            }
+
+            for statement in &rule.statements {
+                match statement {
+                    Statement::Accept => {
+                        return ChainOutcome::Verdict(
+                            PacketVerdict::Accept,
+                            rule.description.clone(),
+                        )
+                    }
+                    Statement::Drop => {
+                        return ChainOutcome::Verdict(PacketVerdict::Drop, rule.description.clone())
+                    }
+                    Statement::Reject => {
+                        return ChainOutcome::Verdict(
+                            PacketVerdict::Reject,
+                            rule.description.clone(),
+                        )
+                    }
+                    Statement::Return => return ChainOutcome::Continue,
+                    Statement::SetMark { value, mask } => {
+                        self.mark = (self.mark & !mask) | value;
+                        self.mark_set = true;
+                    }
+                    Statement::Jump(target) | Statement::Goto(target) => {
+                        let Some(next) = find_chain(self.ruleset, chain, target) else {
+                            self.unevaluated_rules += 1;
+                            continue;
+                        };
+                        match self.run_chain(next, depth + 1) {
+                            verdict @ ChainOutcome::Verdict(..) => return verdict,
+                            // A goto never comes back to this chain
+                            ChainOutcome::Continue if matches!(statement, Statement::Goto(_)) => {
+                                return ChainOutcome::Continue
+                            }
+                            ChainOutcome::Continue => {}
+                        }
+                    }
+                    Statement::Unsupported(what) => {
+                        tracing::trace!("Cannot evaluate firewall statement: {}", what);
+                        self.unevaluated_rules += 1;
+                    }
+                }
+            }
+        }
+
+        ChainOutcome::Continue
+    }
+
+    /// Some(true/false) when every match could be evaluated, None otherwise
+    fn rule_matches(&self, rule: &Rule) -> Option<bool> {
+        for m in &rule.matches {
+            if !self.match_applies(m)? {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    fn match_applies(&self, m: &Match) -> Option<bool> {
+        let packet = self.packet;
+        let result = match m {
+            Match::DestAddr { cidrs, negate } => {
+                cidrs
+                    .iter()
+                    .any(|c| matches_cidr(c, packet.destination).is_some())
+                    != *negate
+            }
+            Match::Protocol { protocols, negate } => {
+                let protocol = normalize_protocol(&packet.protocol);
+                protocols.iter().any(|p| normalize_protocol(p) == protocol) != *negate
+            }
+            Match::DestPort { ranges, negate } => {
+                let port = packet.port?;
+                ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&port)) != *negate
+            }
+            Match::OutInterface { names, negate } => {
+                let iface = packet.out_interface.as_deref()?;
+                names.iter().any(|n| interface_matches(n, iface)) != *negate
+            }
+            // The evaluated packet starts a new connection
+            Match::CtState { states, negate } => states.iter().any(|s| s == "new") != *negate,
+            Match::Mark {
+                value,
+                mask,
+                negate,
+            } => ((self.mark & mask) == *value) != *negate,
+            Match::Family(family) => family_applies(family, packet),
+            Match::Unsupported(what) => {
+                tracing::trace!("Cannot evaluate firewall match: {}", what);
+                return None;
+            }
+        };
+        Some(result)
+    }
+}
+
+/// Jump targets live in the same table as the chain jumping to them
+fn find_chain<'a>(ruleset: &'a Ruleset, from: &Chain, name: &str) -> Option<&'a Chain> {
+    ruleset
+        .chains
+        .iter()
+        .find(|c| c.family == from.family && c.table == from.table && c.name == name)
+}
+
+fn family_applies(family: &str, packet: &Packet) -> bool {
+    match family {
+        "ip" => packet.destination.is_ipv4(),
+        "ip6" => packet.destination.is_ipv6(),
+        _ => true,
+    }
+}
+
+/// Interface names may end in a wildcard ("wg+" in iptables, "wg*" in nftables)
+fn interface_matches(pattern: &str, iface: &str) -> bool {
+    match pattern
+        .strip_suffix('+')
+        .or_else(|| pattern.strip_suffix('*'))
+    {
+        Some(prefix) => iface.starts_with(prefix),
+        None => pattern == iface,
+    }
+}
+
+/// Map protocol numbers and ICMPv6 aliases onto the names packets are described with
+fn normalize_protocol(protocol: &str) -> String {
+    match protocol.to_lowercase().as_str() {
+        "6" => "tcp".to_string(),
+        "17" => "udp".to_string(),
+        "1" | "58" | "ipv6-icmp" | "icmpv6" => "icmp".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn parse_port_range(value: &str) -> Option<(u16, u16)> {
+    match value.split_once('-') {
+        Some((lo, hi)) => Some((lo.parse().ok()?, hi.parse().ok()?)),
+        None => {
+            let port = value.parse().ok()?;
+            Some((port, port))
+        }
+    }
+}
+
+fn parse_mark(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Parse "value/mask" (mask defaults to all bits)
+fn parse_mark_mask(value: &str) -> (u32, u32) {
+    match value.split_once('/') {
+        Some((v, m)) => (
+            parse_mark(v).unwrap_or(0),
+            parse_mark(m).unwrap_or(u32::MAX),
+        ),
+        None => (parse_mark(value).unwrap_or(0), u32::MAX),
+    }
+}
+
+fn str_field(value: &Value, key: &str) -> String {
+    value
+        .get(key)
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// An `ip rule` entry
+#[derive(Debug, Clone)]
+pub struct PolicyRule {
+    pub priority: u32,
+    pub table: String,
+    pub fwmark: Option<(u32, u32)>,
+    pub not: bool,
+    pub suppress_prefixlength: Option<u8>,
+    /// False when the rule selects on something we can't evaluate (iif, dst, uid, ...)
+    pub evaluable: bool,
+}
+
+/// Parse `ip -json rule show`
+pub fn parse_ip_rules(json_str: &str) -> Result<Vec<PolicyRule>, String> {
+    let rules: Vec<Value> =
+        serde_json::from_str(json_str).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+
+    let mut parsed = Vec::new();
+    for rule in rules {
+        let Some(table) = rule.get("table").and_then(|v| v.as_str()) else {
+            continue; // goto/blackhole/unreachable actions
+        };
+
+        let fwmark = rule.get("fwmark").and_then(|v| v.as_str()).map(|mark| {
+            let (value, mask) = parse_mark_mask(mark);
+            let mask = rule
+                .get("fwmask")
+                .and_then(|v| v.as_str())
+                .and_then(parse_mark)
+                .unwrap_or(mask);
+            (value, mask)
+        });
+
+        let src_all = rule
+            .get("src")
+            .and_then(|v| v.as_str())
+            .is_none_or(|s| s == "all");
+        let other_selectors = [
+            "dst", "iif", "oif", "ipproto", "sport", "dport", "uidrange", "tos",
+        ]
+        .iter()
+        .any(|key| rule.get(*key).is_some());
+
+        parsed.push(PolicyRule {
+            priority: rule.get("priority").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
+            table: table.to_string(),
+            fwmark,
+            not: rule.get("not").is_some(),
+            suppress_prefixlength: rule
+                .get("suppress_prefixlength")
+                .and_then(|v| v.as_u64())
+                .map(|p| p as u8),
+            evaluable: src_all && !other_selectors,
+        });
+    }
+
+    parsed.sort_by_key(|r| r.priority);
+    Ok(parsed)
+}
+
+/// Walk the policy rules for a packet with `mark` and return the table and route chosen
+pub fn select_policy_route(
+    rules: &[PolicyRule],
+    mark: u32,
+    destination: IpAddr,
+    load_table: impl Fn(&str) -> Option<RoutingTable>,
+) -> Option<(String, Route)> {
+    for rule in rules {
+        if !rule.evaluable {
+            continue;
+        }
+
+        let selected = match rule.fwmark {
+            Some((value, mask)) => (mark & mask) == value,
+            None => true,
+        };
+        if selected == rule.not {
+            continue;
+        }
+
+        let Some(table) = load_table(&rule.table) else {
+            continue;
+        };
+        let engine = RouteEngine::new(&table);
+        if let Some((route, prefix_len)) = engine.lookup_with_prefix(destination) {
+            // suppress_prefixlength N ignores matches with a prefix of N or shorter
+            if rule.suppress_prefixlength.is_some_and(|s| prefix_len <= s) {
+                continue;
+            }
+            return Some((rule.table.clone(), route.clone()));
+        }
+    }
+
+    None
+}
+
+/// `ip rule` for each address family, empty without policy routing
+#[derive(Debug, Default)]
+pub struct PolicyRules {
+    pub v4: Vec<PolicyRule>,
+    pub v6: Vec<PolicyRule>,
+}
+
+impl PolicyRules {
+    /// The rules that apply to packets for `destination`
+    pub fn for_destination(&self, destination: IpAddr) -> &[PolicyRule] {
+        if destination.is_ipv4() {
+            &self.v4
+        } else {
+            &self.v6
+        }
+    }
+}
+
+/// Read `ip rule` for both families; a family that can't be read has no rules
+pub fn read_policy_rules() -> PolicyRules {
+    let read = |family: &str| {
+        Command::new("ip")
+            .args([family, "-json", "rule", "show"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| parse_ip_rules(&String::from_utf8_lossy(&output.stdout)).ok())
+            .unwrap_or_default()
+    };
+    PolicyRules {
+        v4: read("-4"),
+        v6: read("-6"),
+    }
+}
+
+/// Find the routing table and route a packet carrying `mark` ends up in
+fn policy_route_for_mark(mark: u32, destination: IpAddr) -> Option<(String, Route)> {
+    let family = if destination.is_ipv4() { "-4" } else { "-6" };
+    let output = Command::new("ip")
+        .args([family, "-json", "rule", "show"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let rules = parse_ip_rules(&String::from_utf8_lossy(&output.stdout)).ok()?;
+    select_policy_route(&rules, mark, destination, |table| {
+        parser::get_policy_routing_table(table).ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::test_support::{route, table};
+
+    const NFT_RULESET: &str = include_str!("fixtures/nft_ruleset.json");
+    const IPTABLES_SAVE: &str = include_str!("fixtures/iptables_save.txt");
+    const IP_RULES: &str = include_str!("fixtures/ip_rule.json");
+
+    fn packet(destination: &str, protocol: &str, port: Option<u16>, iface: &str) -> Packet {
+        Packet {
+            destination: destination.parse().unwrap(),
+            protocol: protocol.to_string(),
+            port,
+            out_interface: Some(iface.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_nft_verdicts() {
+        let ruleset = parse_nft_json(NFT_RULESET).unwrap();
+
+        let ssh = evaluate(&ruleset, &packet("192.168.1.5", "tcp", Some(22), "eth0"));
+        assert_eq!(ssh.verdict, PacketVerdict::Accept);
+        assert_eq!(
+            ssh.decided_by.as_deref(),
+            Some("inet filter output handle 12")
+        );
+
+        let corp_dns = evaluate(&ruleset, &packet("10.1.2.3", "udp", Some(53), "tun0"));
+        assert_eq!(corp_dns.verdict, PacketVerdict::Reject);
+
+        let corp_web = evaluate(&ruleset, &packet("10.1.2.3", "tcp", Some(8080), "tun0"));
+        assert_eq!(corp_web.verdict, PacketVerdict::Accept);
+
+        let blocked = evaluate(&ruleset, &packet("192.168.1.5", "tcp", Some(8080), "eth0"));
+        assert_eq!(blocked.verdict, PacketVerdict::Drop);
+        assert_eq!(
+            blocked.decided_by.as_deref(),
+            Some("inet filter output policy")
+        );
+
+        let ping6 = evaluate(&ruleset, &packet("2001:db8::1", "icmp", None, "eth0"));
+        assert_eq!(ping6.verdict, PacketVerdict::Accept);
+    }
+
+    #[test]
+    fn test_nft_fwmark() {
+        let ruleset = parse_nft_json(NFT_RULESET).unwrap();
+
+        let marked = evaluate(&ruleset, &packet("172.16.4.4", "tcp", Some(8080), "wg0"));
+        assert_eq!(marked.fwmark, Some(51820));
+        assert_eq!(marked.verdict, PacketVerdict::Accept);
+
+        let unmarked = evaluate(&ruleset, &packet("192.168.1.5", "tcp", Some(22), "eth0"));
+        assert_eq!(unmarked.fwmark, None);
+    }
+
+    #[test]
+    fn test_iptables_verdicts() {
+        let ruleset = parse_iptables_save(IPTABLES_SAVE, "ip");
+
+        let https = evaluate(&ruleset, &packet("93.184.216.34", "tcp", Some(443), "eth0"));
+        assert_eq!(https.verdict, PacketVerdict::Accept);
+        assert_eq!(https.source, "iptables");
+
+        let corp_dns = evaluate(&ruleset, &packet("10.1.2.3", "udp", Some(53), "tun0"));
+        assert_eq!(corp_dns.verdict, PacketVerdict::Reject);
+
+        let wg = evaluate(&ruleset, &packet("172.16.4.4", "tcp", Some(8080), "wg0"));
+        assert_eq!(wg.verdict, PacketVerdict::Accept);
+        assert_eq!(wg.fwmark, Some(0xca6c));
+
+        // The NTP rule matches on source address, which we can't evaluate
+        let ntp = evaluate(&ruleset, &packet("192.168.1.1", "udp", Some(123), "eth0"));
+        assert_eq!(ntp.verdict, PacketVerdict::Drop);
+        assert_eq!(ntp.unevaluated_rules, 1);
+    }
+
+    #[test]
+    fn test_policy_routing_by_fwmark() {
+        let rules = parse_ip_rules(IP_RULES).unwrap();
+        assert_eq!(rules.len(), 5);
+
+        let load_table = |name: &str| {
+            let routes = match name {
+                "main" => vec![
+                    route("0.0.0.0/0", None, "eth0"),
+                    route("192.168.1.0/24", None, "eth0"),
+                ],
+                "51820" => vec![route("0.0.0.0/0", None, "wg0")],
+                _ => Vec::new(),
+            };
+            Some(table(routes))
+        };
+        let dest: IpAddr = "8.8.8.8".parse().unwrap();
+
+        // wg-quick: unmarked traffic goes into the tunnel table
+        let (table, selected) = select_policy_route(&rules, 0, dest, load_table).unwrap();
+        assert_eq!(table, "51820");
+        assert_eq!(selected.interface, "wg0");
+
+        // Packets carrying WireGuard's own mark bypass the tunnel
+        let (table, selected) = select_policy_route(&rules, 0xca6c, dest, load_table).unwrap();
+        assert_eq!(table, "main");
+        assert_eq!(selected.interface, "eth0");
+    }
+
+    #[test]
+    fn test_tokenize_quoted_comment() {
+        let tokens = tokenize(r#"OUTPUT -m comment --comment "allow dns" -j ACCEPT"#);
+        assert_eq!(tokens.get(4).map(|s| s.as_str()), Some("allow dns"));
+    }
+}
@@ -0,0 +1,346 @@
+// DNS and IPv6 leak detection for full-tunnel VPNs
+// When the default route points into a tunnel, every configured resolver and the
+// IPv6 default route should go through that tunnel too; anything else leaks.
+// Routes are looked up through `ip rule` where there is one, since a wg-quick
+// full tunnel lives in its own table behind an fwmark rule rather than in main
+
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+
+use super::firewall::{self, PolicyRules};
+use crate::api::rest::AppState;
+use crate::api::websocket::ServerMessage;
+use crate::config::Config;
+use crate::discovery::vpn_scan::is_vpn_interface_name;
+use crate::routes::lookup::RouteEngine;
+use crate::routes::resolver::{self, Resolver};
+use crate::routes::{parser, Route, RoutingTable};
+
+/// Public addresses used to probe where internet-bound traffic is routed
+const PROBE_V4: &str = "1.1.1.1";
+const PROBE_V6: &str = "2606:4700:4700::1111";
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LeakVerdict {
+    /// No full-tunnel VPN is active, so there is nothing to leak around
+    NoTunnel,
+    /// A full-tunnel VPN is active and everything goes through it
+    Secure,
+    /// A full-tunnel VPN is active but some traffic bypasses it
+    Leak,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LeakKind {
+    Dns,
+    Ipv6,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Leak {
+    pub kind: LeakKind,
+    pub description: String,
+    /// Resolver that bypasses the tunnel (DNS leaks only)
+    pub resolver: Option<IpAddr>,
+    /// Route the leaking traffic takes
+    pub route: Option<Route>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LeakReport {
+    pub verdict: LeakVerdict,
+    /// Tunnel interface carrying the IPv4 default route, if any
+    pub tunnel_interface: Option<String>,
+    pub leaks: Vec<Leak>,
+    /// Resolvers that could not be judged (e.g. a local stub resolver)
+    pub unchecked_resolvers: Vec<IpAddr>,
+    pub checked_at: String,
+}
+
+/// Run a leak check against the live routing table and resolver configuration
+pub fn run_leak_check() -> Result<LeakReport, String> {
+    let routing_table = parser::get_routing_table()?;
+    let resolvers = resolver::get_resolvers();
+    let policy = firewall::read_policy_rules();
+    Ok(check_leaks(
+        &routing_table,
+        &policy,
+        |table| parser::get_policy_routing_table(table).ok(),
+        &resolvers,
+    ))
+}
+
+/// Decide whether DNS or IPv6 traffic escapes a full-tunnel VPN
+///
+/// `load_table` fetches the tables the policy rules name; `routing_table`
+/// stands in for main.
+pub fn check_leaks(
+    routing_table: &RoutingTable,
+    policy: &PolicyRules,
+    load_table: impl Fn(&str) -> Option<RoutingTable>,
+    resolvers: &[Resolver],
+) -> LeakReport {
+    let engine = RouteEngine::new(routing_table);
+    let checked_at = chrono::Utc::now().to_rfc3339();
+
+    // Where unmarked traffic, as applications send it, is routed
+    let lookup = |destination: IpAddr| -> Option<Route> {
+        let rules = policy.for_destination(destination);
+        if rules.is_empty() {
+            return engine.lookup(destination).cloned();
+        }
+        firewall::select_policy_route(rules, 0, destination, |table| match table {
+            "main" => Some(routing_table.clone()),
+            other => load_table(other),
+        })
+        .map(|(_, route)| route)
+    };
+
+    let tunnel_interface = PROBE_V4
+        .parse()
+        .ok()
+        .and_then(lookup)
+        .map(|route| route.interface)
+        .filter(|iface| is_vpn_interface_name(iface));
+
+    let Some(tunnel) = tunnel_interface else {
+        return LeakReport {
+            verdict: LeakVerdict::NoTunnel,
+            tunnel_interface: None,
+            leaks: Vec::new(),
+            unchecked_resolvers: Vec::new(),
+            checked_at,
+        };
+    };
+
+    let mut leaks = Vec::new();
+    let mut unchecked_resolvers = Vec::new();
+
+    for resolver in resolvers {
+        // Split-DNS resolvers that only answer their own domains don't see general queries
+        if !resolver.default_route {
+            continue;
+        }
+
+        // A local stub forwards elsewhere; without resolvectl we can't see where
+        if resolver.address.is_loopback() {
+            unchecked_resolvers.push(resolver.address);
+            continue;
+        }
+
+        let route = lookup(resolver.address);
+        let through_tunnel = route.as_ref().is_some_and(|r| r.interface == tunnel);
+        if !through_tunnel {
+            leaks.push(Leak {
+                kind: LeakKind::Dns,
+                description: match &route {
+                    Some(r) => format!(
+                        "Resolver {} is reached via {} instead of {}",
+                        resolver.address, r.interface, tunnel
+                    ),
+                    None => format!("Resolver {} has no route", resolver.address),
+                },
+                resolver: Some(resolver.address),
+                route,
+            });
+        }
+    }
+
+    // An IPv4-only tunnel with a native IPv6 default route sends all v6 traffic outside
+    if let Some(route) = PROBE_V6.parse().ok().and_then(lookup) {
+        if !is_vpn_interface_name(&route.interface) {
+            leaks.push(Leak {
+                kind: LeakKind::Ipv6,
+                description: format!(
+                    "IPv6 traffic is routed via {} while IPv4 uses {}",
+                    route.interface, tunnel
+                ),
+                resolver: None,
+                route: Some(route),
+            });
+        }
+    }
+
+    LeakReport {
+        verdict: if leaks.is_empty() {
+            LeakVerdict::Secure
+        } else {
+            LeakVerdict::Leak
+        },
+        tunnel_interface: Some(tunnel),
+        leaks,
+        unchecked_resolvers,
+        checked_at,
+    }
+}
+
+/// Periodic background leak check
+pub struct LeakCheckService {
+    config: Arc<Config>,
+    state: Arc<AppState>,
+}
+
+impl LeakCheckService {
+    pub fn new(config: Arc<Config>, state: Arc<AppState>) -> Self {
+        LeakCheckService { config, state }
+    }
+
+    pub async fn start_check_task(&self) -> JoinHandle<()> {
+        let state = self.state.clone();
+        let interval_secs = self.config.leak_check_interval;
+        let mut shutdown = state.shutdown_signal();
+
+        tokio::spawn(async move {
+            let mut check_interval = interval(Duration::from_secs(interval_secs));
+
+            loop {
+                tokio::select! {
+                    _ = check_interval.tick() => {}
+                    _ = shutdown.wait_for(|stopping| *stopping) => break,
+                }
+
+                let report = match tokio::task::spawn_blocking(run_leak_check).await {
+                    Ok(Ok(report)) => report,
+                    Ok(Err(e)) => {
+                        tracing::debug!("Leak check failed: {}", e);
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::debug!("Leak check task failed: {}", e);
+                        continue;
+                    }
+                };
+
+                let mut last_report = state.leak_report.write().await;
+                let changed = last_report
+                    .as_ref()
+                    .is_none_or(|last| last.verdict != report.verdict);
+
+                if changed {
+                    if report.verdict == LeakVerdict::Leak {
+                        for leak in &report.leaks {
+                            tracing::warn!("VPN leak detected: {}", leak.description);
+                        }
+                    }
+                    state.send_update(ServerMessage::LeakCheckResult {
+                        report: report.clone(),
+                    });
+                }
+
+                *last_report = Some(report);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::test_support::{route, table};
+
+    const IP_RULES: &str = include_str!("fixtures/ip_rule.json");
+
+    fn resolver(address: &str, default_route: bool) -> Resolver {
+        Resolver {
+            address: address.parse().unwrap(),
+            link: None,
+            domains: Vec::new(),
+            default_route,
+            source: "resolv.conf".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_no_tunnel() {
+        let table = table(vec![route("0.0.0.0/0", Some("192.168.1.1"), "eth0")]);
+        let report = check_leaks(
+            &table,
+            &PolicyRules::default(),
+            |_| None,
+            &[resolver("192.168.1.1", true)],
+        );
+        assert_eq!(report.verdict, LeakVerdict::NoTunnel);
+    }
+
+    #[test]
+    fn test_dns_and_ipv6_leak() {
+        let table = table(vec![
+            route("0.0.0.0/0", Some("10.8.0.1"), "tun0"),
+            route("192.168.1.0/24", None, "eth0"),
+            route("::/0", Some("fe80::1"), "eth0"),
+        ]);
+        let resolvers = [
+            resolver("10.8.0.1", true),
+            resolver("192.168.1.1", true),
+            resolver("192.168.1.53", false),
+        ];
+
+        let report = check_leaks(&table, &PolicyRules::default(), |_| None, &resolvers);
+        assert_eq!(report.verdict, LeakVerdict::Leak);
+        assert_eq!(report.tunnel_interface.as_deref(), Some("tun0"));
+        assert_eq!(report.leaks.len(), 2);
+        assert!(
+            report
+                .leaks
+                .iter()
+                .any(|l| l.kind == LeakKind::Dns
+                    && l.resolver == Some("192.168.1.1".parse().unwrap()))
+        );
+        assert!(report.leaks.iter().any(|l| l.kind == LeakKind::Ipv6));
+    }
+
+    #[test]
+    fn test_secure_tunnel() {
+        let table = table(vec![
+            route("0.0.0.0/0", Some("10.8.0.1"), "tun0"),
+            route("192.168.1.0/24", None, "eth0"),
+        ]);
+        let resolvers = [resolver("10.8.0.1", true), resolver("127.0.0.53", true)];
+
+        let report = check_leaks(&table, &PolicyRules::default(), |_| None, &resolvers);
+        assert_eq!(report.verdict, LeakVerdict::Secure);
+        assert_eq!(report.unchecked_resolvers.len(), 1);
+    }
+
+    #[test]
+    fn test_wg_quick_full_tunnel() {
+        // wg-quick puts the tunnel's default route in table 51820 and sends
+        // unmarked traffic there; main keeps the physical default route
+        let policy = PolicyRules {
+            v4: firewall::parse_ip_rules(IP_RULES).unwrap(),
+            v6: Vec::new(),
+        };
+        let main = table(vec![
+            route("0.0.0.0/0", Some("192.168.1.1"), "eth0"),
+            route("192.168.1.0/24", None, "eth0"),
+        ]);
+        let load_table = |name: &str| match name {
+            "51820" => Some(table(vec![route("0.0.0.0/0", None, "wg0")])),
+            _ => None,
+        };
+
+        let resolvers = [resolver("10.64.0.1", true)];
+        let report = check_leaks(&main, &policy, load_table, &resolvers);
+        assert_eq!(report.verdict, LeakVerdict::Secure);
+        assert_eq!(report.tunnel_interface.as_deref(), Some("wg0"));
+
+        // A resolver on the LAN is still reached directly
+        let resolvers = [resolver("10.64.0.1", true), resolver("192.168.1.1", true)];
+        let report = check_leaks(&main, &policy, load_table, &resolvers);
+        assert_eq!(report.verdict, LeakVerdict::Leak);
+        assert_eq!(
+            report.leaks.first().and_then(|l| l.resolver),
+            Some("192.168.1.1".parse().unwrap())
+        );
+
+        // The main table alone doesn't show the tunnel
+        let report = check_leaks(&main, &PolicyRules::default(), load_table, &resolvers);
+        assert_eq!(report.verdict, LeakVerdict::NoTunnel);
+    }
+}
@@ -0,0 +1,7 @@
+// Diagnostics module - local checks that explain why traffic fails even when
+// a route exists (leaks around a VPN tunnel, kernel settings, firewall rules)
+
+pub mod diagnose;
+pub mod firewall;
+pub mod leak;
+pub mod sysctl;
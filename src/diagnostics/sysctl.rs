@@ -0,0 +1,428 @@
+// Kernel networking sysctl checks for VPN routing
+// Reads rp_filter, forwarding and src_valid_mark from /proc/sys/net and flags
+// combinations that silently break the routes in the current routing table
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+use super::firewall::{self, PolicyRule};
+use crate::discovery::vpn_scan::is_vpn_interface_name;
+use crate::routes::{parser, RoutingTable};
+
+const PROC_NET: &str = "/proc/sys/net";
+
+/// Kernel settings relevant to routing
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SysctlSnapshot {
+    /// net.ipv4.ip_forward
+    pub ip_forward: Option<bool>,
+    /// net.ipv6.conf.all.forwarding
+    pub ipv6_forwarding: Option<bool>,
+    /// net.ipv4.conf.all.rp_filter
+    pub rp_filter_all: Option<u8>,
+    /// net.ipv4.conf.all.src_valid_mark
+    pub src_valid_mark: Option<bool>,
+    pub interfaces: BTreeMap<String, InterfaceSysctl>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InterfaceSysctl {
+    /// net.ipv4.conf.<iface>.rp_filter
+    pub rp_filter: Option<u8>,
+    /// net.ipv4.conf.<iface>.forwarding
+    pub forwarding: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Info,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SysctlFinding {
+    pub severity: Severity,
+    pub setting: String,
+    pub current: String,
+    pub recommended: String,
+    pub description: String,
+    /// Commands to apply the fix now and persist it across reboots
+    pub fix_commands: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SysctlReport {
+    pub snapshot: SysctlSnapshot,
+    pub findings: Vec<SysctlFinding>,
+    pub checked_at: String,
+}
+
+/// Read the live settings and check them against the current routing table
+pub fn run_sysctl_check() -> Result<SysctlReport, String> {
+    let routing_table = parser::get_routing_table()?;
+    let snapshot = read_snapshot(Path::new(PROC_NET));
+    let policy_rules = read_policy_rules();
+    let findings = check_sysctls(&snapshot, &routing_table, &policy_rules);
+
+    Ok(SysctlReport {
+        snapshot,
+        findings,
+        checked_at: chrono::Utc::now().to_rfc3339(),
+    })
+}
+
+/// Read settings from a /proc/sys/net style directory tree
+pub fn read_snapshot(root: &Path) -> SysctlSnapshot {
+    let read = |path: &str| -> Option<u8> {
+        std::fs::read_to_string(root.join(path))
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+    };
+
+    let mut interfaces = BTreeMap::new();
+    if let Ok(entries) = std::fs::read_dir(root.join("ipv4/conf")) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == "all" || name == "default" {
+                continue;
+            }
+            interfaces.insert(
+                name.clone(),
+                InterfaceSysctl {
+                    rp_filter: read(&format!("ipv4/conf/{}/rp_filter", name)),
+                    forwarding: read(&format!("ipv4/conf/{}/forwarding", name)).map(|v| v != 0),
+                },
+            );
+        }
+    }
+
+    SysctlSnapshot {
+        ip_forward: read("ipv4/ip_forward").map(|v| v != 0),
+        ipv6_forwarding: read("ipv6/conf/all/forwarding").map(|v| v != 0),
+        rp_filter_all: read("ipv4/conf/all/rp_filter"),
+        src_valid_mark: read("ipv4/conf/all/src_valid_mark").map(|v| v != 0),
+        interfaces,
+    }
+}
+
+fn read_policy_rules() -> Vec<PolicyRule> {
+    Command::new("ip")
+        .args(["-4", "-json", "rule", "show"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| firewall::parse_ip_rules(&String::from_utf8_lossy(&output.stdout)).ok())
+        .unwrap_or_default()
+}
+
+/// Flag settings that break the routes in `routing_table`
+pub fn check_sysctls(
+    snapshot: &SysctlSnapshot,
+    routing_table: &RoutingTable,
+    policy_rules: &[PolicyRule],
+) -> Vec<SysctlFinding> {
+    let mut findings = Vec::new();
+
+    let default_interfaces: Vec<&str> = routing_table
+        .routes
+        .iter()
+        .filter(|r| r.destination == "default" || r.destination == "0.0.0.0/0")
+        .map(|r| r.interface.as_str())
+        .collect();
+
+    let mut routed_interfaces: Vec<&str> = routing_table
+        .routes
+        .iter()
+        .map(|r| r.interface.as_str())
+        .collect();
+    routed_interfaces.sort_unstable();
+    routed_interfaces.dedup();
+
+    let uses_fwmark_routing = policy_rules.iter().any(|r| r.fwmark.is_some());
+
+    // Strict reverse-path filtering drops replies that arrive on a tunnel whose
+    // source would be routed out another interface (split tunnels, multi-homing)
+    for iface in &routed_interfaces {
+        let effective = effective_rp_filter(snapshot, iface);
+        if effective != Some(1) {
+            continue;
+        }
+
+        let is_tunnel = is_vpn_interface_name(iface);
+        let carries_default = default_interfaces.contains(iface);
+        let multi_homed = default_interfaces.len() > 1 && carries_default;
+
+        let reason = if is_tunnel && !carries_default && !uses_fwmark_routing {
+            format!(
+                "Strict rp_filter on {} drops return traffic from hosts that are not \
+                 covered by a route via {}",
+                iface, iface
+            )
+        } else if is_tunnel && uses_fwmark_routing {
+            format!(
+                "Strict rp_filter on {} drops tunnel traffic selected by fwmark policy \
+                 routing, because the main table routes the replies elsewhere",
+                iface
+            )
+        } else if multi_homed {
+            format!(
+                "Strict rp_filter on {} with default routes on {} drops replies that \
+                 arrive on the non-preferred uplink",
+                iface,
+                default_interfaces.join(", ")
+            )
+        } else {
+            continue;
+        };
+
+        let setting = format!("net.ipv4.conf.{}.rp_filter", iface);
+        findings.push(SysctlFinding {
+            severity: Severity::Warning,
+            current: "1 (strict)".to_string(),
+            recommended: "2 (loose)".to_string(),
+            description: reason,
+            fix_commands: fix_commands(&setting, "2"),
+            setting,
+        });
+    }
+
+    // wg-quick style fwmark routing needs src_valid_mark for rp_filter to see the mark
+    if uses_fwmark_routing
+        && snapshot.src_valid_mark == Some(false)
+        && snapshot.rp_filter_all.is_some_and(|v| v != 0)
+    {
+        let setting = "net.ipv4.conf.all.src_valid_mark".to_string();
+        findings.push(SysctlFinding {
+            severity: Severity::Warning,
+            current: "0".to_string(),
+            recommended: "1".to_string(),
+            description: "fwmark policy routing is in use but rp_filter ignores packet marks, \
+                          so replies through the marked table fail the reverse-path check"
+                .to_string(),
+            fix_commands: fix_commands(&setting, "1"),
+            setting,
+        });
+    }
+
+    // A host with both a tunnel and a LAN can only act as the site's VPN gateway
+    // when it forwards between them
+    let tunnels: Vec<&str> = routed_interfaces
+        .iter()
+        .copied()
+        .filter(|iface| is_vpn_interface_name(iface))
+        .collect();
+    let lans: Vec<&str> = routing_table
+        .routes
+        .iter()
+        .filter(|r| r.gateway.is_none() && r.destination != "default")
+        .filter(|r| !r.destination.ends_with("/0") && r.destination != "fe80::/64")
+        .map(|r| r.interface.as_str())
+        .filter(|iface| !is_vpn_interface_name(iface) && *iface != "lo")
+        .collect();
+
+    if !tunnels.is_empty() && !lans.is_empty() {
+        if snapshot.ip_forward == Some(false) {
+            let setting = "net.ipv4.ip_forward".to_string();
+            findings.push(SysctlFinding {
+                severity: Severity::Info,
+                current: "0".to_string(),
+                recommended: "1".to_string(),
+                description: format!(
+                    "IPv4 forwarding is disabled, so other machines on {} cannot use this \
+                     host to reach networks behind {}",
+                    lans.first().copied().unwrap_or_default(),
+                    tunnels.join(", ")
+                ),
+                fix_commands: fix_commands(&setting, "1"),
+                setting,
+            });
+        } else if snapshot.ip_forward == Some(true) {
+            for tunnel in &tunnels {
+                let forwarding = snapshot.interfaces.get(*tunnel).and_then(|i| i.forwarding);
+                if forwarding == Some(false) {
+                    let setting = format!("net.ipv4.conf.{}.forwarding", tunnel);
+                    findings.push(SysctlFinding {
+                        severity: Severity::Warning,
+                        current: "0".to_string(),
+                        recommended: "1".to_string(),
+                        description: format!(
+                            "Forwarding is enabled globally but disabled on {}, so routed \
+                             traffic cannot leave through the tunnel",
+                            tunnel
+                        ),
+                        fix_commands: fix_commands(&setting, "1"),
+                        setting,
+                    });
+                }
+            }
+        }
+
+        // A router for IPv4 that doesn't forward IPv6 drops the LAN's IPv6
+        // traffic to the tunnel's IPv6 networks
+        let tunnel_ipv6 = routing_table
+            .routes
+            .iter()
+            .any(|r| r.destination.contains(':') && tunnels.contains(&r.interface.as_str()));
+        if snapshot.ip_forward == Some(true)
+            && snapshot.ipv6_forwarding == Some(false)
+            && tunnel_ipv6
+        {
+            let setting = "net.ipv6.conf.all.forwarding".to_string();
+            findings.push(SysctlFinding {
+                severity: Severity::Warning,
+                current: "0".to_string(),
+                recommended: "1".to_string(),
+                description: format!(
+                    "IPv4 forwarding is enabled but IPv6 forwarding is not, so machines on \
+                     {} cannot reach the IPv6 networks behind {}",
+                    lans.first().copied().unwrap_or_default(),
+                    tunnels.join(", ")
+                ),
+                fix_commands: fix_commands(&setting, "1"),
+                setting,
+            });
+        }
+    }
+
+    findings
+}
+
+/// The kernel applies the higher of the "all" and per-interface values, so
+/// loose (2) on either side beats strict (1)
+fn effective_rp_filter(snapshot: &SysctlSnapshot, iface: &str) -> Option<u8> {
+    let per_interface = snapshot.interfaces.get(iface).and_then(|i| i.rp_filter);
+    match (snapshot.rp_filter_all, per_interface) {
+        (Some(all), Some(iface)) => Some(all.max(iface)),
+        (all, iface) => all.or(iface),
+    }
+}
+
+fn fix_commands(setting: &str, value: &str) -> Vec<String> {
+    vec![
+        format!("sudo sysctl -w {}={}", setting, value),
+        format!(
+            "echo '{} = {}' | sudo tee -a /etc/sysctl.d/90-network-route-visualizer.conf",
+            setting, value
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::test_support::{route, table};
+
+    fn snapshot(rp_filter_all: u8, interfaces: &[(&str, u8)]) -> SysctlSnapshot {
+        SysctlSnapshot {
+            ip_forward: Some(true),
+            ipv6_forwarding: Some(false),
+            rp_filter_all: Some(rp_filter_all),
+            src_valid_mark: Some(false),
+            interfaces: interfaces
+                .iter()
+                .map(|(name, rp)| {
+                    (
+                        name.to_string(),
+                        InterfaceSysctl {
+                            rp_filter: Some(*rp),
+                            forwarding: Some(true),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_strict_rp_filter_on_split_tunnel() {
+        let table = table(vec![
+            route("0.0.0.0/0", Some("192.168.1.1"), "eth0"),
+            route("192.168.1.0/24", None, "eth0"),
+            route("10.20.0.0/16", Some("10.8.0.1"), "tun0"),
+        ]);
+        let snapshot = snapshot(0, &[("eth0", 1), ("tun0", 1)]);
+
+        let findings = check_sysctls(&snapshot, &table, &[]);
+        assert_eq!(findings.len(), 1);
+        let finding = findings.first().unwrap();
+        assert_eq!(finding.setting, "net.ipv4.conf.tun0.rp_filter");
+        assert_eq!(
+            finding.fix_commands.first().map(|s| s.as_str()),
+            Some("sudo sysctl -w net.ipv4.conf.tun0.rp_filter=2")
+        );
+    }
+
+    #[test]
+    fn test_loose_rp_filter_is_fine() {
+        let table = table(vec![
+            route("0.0.0.0/0", Some("192.168.1.1"), "eth0"),
+            route("10.20.0.0/16", Some("10.8.0.1"), "tun0"),
+        ]);
+        // "all" strict but the tunnel is loose: the kernel takes the max, so loose wins
+        let snapshot = snapshot(1, &[("eth0", 1), ("tun0", 2)]);
+
+        let findings = check_sysctls(&snapshot, &table, &[]);
+        assert!(findings.is_empty());
+    }
+
+    #[test]
+    fn test_ip_forward_disabled_on_gateway() {
+        let table = table(vec![
+            route("0.0.0.0/0", Some("192.168.1.1"), "eth0"),
+            route("192.168.1.0/24", None, "eth0"),
+            route("10.20.0.0/16", None, "wg0"),
+        ]);
+        let mut snapshot = snapshot(0, &[("eth0", 0), ("wg0", 0)]);
+        snapshot.ip_forward = Some(false);
+
+        let findings = check_sysctls(&snapshot, &table, &[]);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings.first().map(|f| f.setting.as_str()),
+            Some("net.ipv4.ip_forward")
+        );
+    }
+
+    #[test]
+    fn test_ipv6_forwarding_disabled_on_gateway() {
+        let mut routes = vec![
+            route("0.0.0.0/0", Some("192.168.1.1"), "eth0"),
+            route("192.168.1.0/24", None, "eth0"),
+            route("10.20.0.0/16", None, "wg0"),
+        ];
+        let snapshot = snapshot(0, &[("eth0", 0), ("wg0", 0)]);
+
+        // No IPv6 through the tunnel, nothing to forward
+        assert!(check_sysctls(&snapshot, &table(routes.clone()), &[]).is_empty());
+
+        routes.push(route("fd00:20::/64", None, "wg0"));
+        let findings = check_sysctls(&snapshot, &table(routes), &[]);
+        assert_eq!(
+            findings
+                .iter()
+                .map(|f| f.setting.as_str())
+                .collect::<Vec<_>>(),
+            vec!["net.ipv6.conf.all.forwarding"]
+        );
+    }
+
+    #[test]
+    fn test_read_snapshot_from_directory() {
+        let root = std::env::temp_dir().join(format!("nrv-sysctl-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("ipv4/conf/all")).unwrap();
+        std::fs::create_dir_all(root.join("ipv4/conf/wg0")).unwrap();
+        std::fs::write(root.join("ipv4/ip_forward"), "1\n").unwrap();
+        std::fs::write(root.join("ipv4/conf/all/rp_filter"), "2\n").unwrap();
+        std::fs::write(root.join("ipv4/conf/wg0/rp_filter"), "1\n").unwrap();
+
+        let snapshot = read_snapshot(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(snapshot.ip_forward, Some(true));
+        assert_eq!(snapshot.rp_filter_all, Some(2));
+        assert_eq!(effective_rp_filter(&snapshot, "wg0"), Some(2));
+    }
+}
@@ -6,12 +6,12 @@ use std::time::Instant;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
 
 use crate::api::rest::AppState;
 use crate::api::websocket::ServerMessage;
+use crate::config::Config;
 
-const BANDWIDTH_TEST_PORT: u16 = 8081;
-const BANDWIDTH_TEST_DURATION_SECS: u64 = 10;
 const CHUNK_SIZE: usize = 64 * 1024; // 64KB chunks
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -33,23 +33,33 @@ pub struct BandwidthTestProgress {
 }
 
 pub struct BandwidthService {
+    config: Arc<Config>,
     state: Arc<AppState>,
     active_tests: Arc<RwLock<std::collections::HashMap<String, BandwidthTestProgress>>>,
 }
 
 impl BandwidthService {
-    pub fn new(state: Arc<AppState>) -> Self {
+    pub fn new(config: Arc<Config>, state: Arc<AppState>) -> Self {
         BandwidthService {
+            config,
             state,
             active_tests: Arc::new(RwLock::new(std::collections::HashMap::new())),
         }
     }
 
-    pub async fn start_server(&self) {
+    /// Port the test server listens on; peers are assumed to use the same one
+    pub fn test_port(&self) -> u16 {
+        self.config.bandwidth_port
+    }
+
+    pub async fn start_server(&self) -> JoinHandle<()> {
         let state = self.state.clone();
+        let port = self.config.bandwidth_port;
+        let duration_secs = self.config.bandwidth_duration;
+        let mut shutdown = state.shutdown_signal();
 
         tokio::spawn(async move {
-            let addr = SocketAddr::from(([0, 0, 0, 0], BANDWIDTH_TEST_PORT));
+            let addr = SocketAddr::from(([0, 0, 0, 0], port));
             let listener = match TcpListener::bind(addr).await {
                 Ok(l) => l,
                 Err(e) => {
@@ -58,26 +68,36 @@ impl BandwidthService {
                 }
             };
 
-            tracing::info!(
-                "Bandwidth test server listening on port {}",
-                BANDWIDTH_TEST_PORT
-            );
+            tracing::info!("Bandwidth test server listening on port {}", port);
 
             loop {
-                match listener.accept().await {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    _ = shutdown.wait_for(|stopping| *stopping) => break,
+                };
+
+                match accepted {
                     Ok((socket, addr)) => {
                         tracing::info!("Bandwidth test connection from {}", addr);
-                        tokio::spawn(Self::handle_test_connection(socket, state.clone()));
+                        tokio::spawn(Self::handle_test_connection(
+                            socket,
+                            state.clone(),
+                            duration_secs,
+                        ));
                     }
                     Err(e) => {
                         tracing::error!("Failed to accept bandwidth test connection: {}", e);
                     }
                 }
             }
-        });
+        })
     }
 
-    async fn handle_test_connection(mut socket: TcpStream, _state: Arc<AppState>) {
+    async fn handle_test_connection(
+        mut socket: TcpStream,
+        _state: Arc<AppState>,
+        duration_secs: u64,
+    ) {
         // Read test header (1 byte: 0 = receive mode, 1 = send mode)
         let mut mode_byte = [0u8; 1];
         if socket.read_exact(&mut mode_byte).await.is_err() {
@@ -91,7 +111,7 @@ impl BandwidthService {
                 let mut total_bytes = 0u64;
                 let start = Instant::now();
 
-                while start.elapsed().as_secs() < BANDWIDTH_TEST_DURATION_SECS {
+                while start.elapsed().as_secs() < duration_secs {
                     match socket.read(&mut buffer).await {
                         Ok(0) => break, // Connection closed
                         Ok(n) => total_bytes += n as u64,
@@ -106,7 +126,7 @@ impl BandwidthService {
                 let buffer = vec![0u8; CHUNK_SIZE];
                 let start = Instant::now();
 
-                while start.elapsed().as_secs() < BANDWIDTH_TEST_DURATION_SECS {
+                while start.elapsed().as_secs() < duration_secs {
                     if socket.write_all(&buffer).await.is_err() {
                         break;
                     }
@@ -159,7 +179,7 @@ impl BandwidthService {
             target_node_id,
             upload_mbps,
             download_mbps,
-            duration_secs: BANDWIDTH_TEST_DURATION_SECS * 2,
+            duration_secs: self.config.bandwidth_duration * 2,
             timestamp: chrono::Utc::now().to_rfc3339(),
         })
     }
@@ -178,19 +198,19 @@ impl BandwidthService {
             .await
             .map_err(|e| format!("Failed to send mode: {}", e))?;
 
+        let duration_secs = self.config.bandwidth_duration;
         let buffer = vec![0u8; CHUNK_SIZE];
         let mut total_bytes = 0u64;
         let start = Instant::now();
 
-        while start.elapsed().as_secs() < BANDWIDTH_TEST_DURATION_SECS {
+        while start.elapsed().as_secs() < duration_secs {
             if socket.write_all(&buffer).await.is_err() {
                 break;
             }
             total_bytes += CHUNK_SIZE as u64;
 
             // Update progress
-            let progress =
-                10 + ((start.elapsed().as_secs() * 40) / BANDWIDTH_TEST_DURATION_SECS) as u8;
+            let progress = 10 + ((start.elapsed().as_secs() * 40) / duration_secs) as u8;
             self.update_progress(test_id.clone(), progress, "upload".to_string(), total_bytes)
                 .await;
         }
@@ -216,11 +236,12 @@ impl BandwidthService {
             .await
             .map_err(|e| format!("Failed to send mode: {}", e))?;
 
+        let duration_secs = self.config.bandwidth_duration;
         let mut buffer = vec![0u8; CHUNK_SIZE];
         let mut total_bytes = 0u64;
         let start = Instant::now();
 
-        while start.elapsed().as_secs() < BANDWIDTH_TEST_DURATION_SECS {
+        while start.elapsed().as_secs() < duration_secs {
             match socket.read(&mut buffer).await {
                 Ok(0) => break,
                 Ok(n) => total_bytes += n as u64,
@@ -228,8 +249,7 @@ impl BandwidthService {
             }
 
             // Update progress
-            let progress =
-                50 + ((start.elapsed().as_secs() * 40) / BANDWIDTH_TEST_DURATION_SECS) as u8;
+            let progress = 50 + ((start.elapsed().as_secs() * 40) / duration_secs) as u8;
             self.update_progress(
                 test_id.clone(),
                 progress,
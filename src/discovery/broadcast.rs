@@ -1,20 +1,27 @@
 // UDP multicast broadcast for node discovery
-// Also supports unicast to WireGuard peers (since multicast doesn't traverse WireGuard tunnels)
-// and unicast to VPN peers discovered via subnet scanning (for OpenConnect, OpenVPN, etc.)
+// Announcements are handed to every enabled discovery source (see source.rs), so
+// besides multicast they also go out by unicast to WireGuard peers, VPN sweep
+// results, static seeds and gossip-learned nodes
 
+use futures::future::BoxFuture;
 use serde::{Deserialize, Serialize};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::UdpSocket;
-use tokio::sync::RwLock;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 
-use super::vpn_scan;
+use super::gossip::{self, PeerSummary};
+use super::interfaces::{self, InterfaceAddress};
+use super::limits::{RateLimiter, ReplayGuard};
+use super::source::{DiscoverySource, SourceRegistry};
+use super::trust::{self, TrustStore};
+use super::wire::{self, PacketError, PacketStats};
 use super::wireguard;
-
-const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 42, 1);
-const MULTICAST_PORT: u16 = 5678;
-// VPN subnet scan interval: every N announcements (N * 30s = 30 minutes at N=60)
-const VPN_SCAN_INTERVAL: u32 = 60;
+use crate::config::Config;
+use ed25519_dalek::SigningKey;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -27,59 +34,123 @@ pub enum DiscoveryMessage {
         port: u16,
         timestamp: String,
         version: String,
-        known_peers: Vec<String>,
+        /// Peers this node knows about, for transitive discovery
+        #[serde(default)]
+        peers: Vec<PeerSummary>,
+        /// `addresses` tagged with interface and VPN kind
+        #[serde(default)]
+        interfaces: Vec<InterfaceAddress>,
     },
     #[serde(rename = "goodbye")]
-    Goodbye { node_id: String, reason: String },
+    Goodbye {
+        node_id: String,
+        reason: String,
+        timestamp: String,
+    },
+    /// Peer summaries that didn't fit in the sender's announcement
+    #[serde(rename = "peers")]
+    Peers {
+        node_id: String,
+        peers: Vec<PeerSummary>,
+        timestamp: String,
+    },
+}
+
+impl DiscoveryMessage {
+    pub fn node_id(&self) -> &str {
+        match self {
+            DiscoveryMessage::Announce { node_id, .. }
+            | DiscoveryMessage::Goodbye { node_id, .. }
+            | DiscoveryMessage::Peers { node_id, .. } => node_id,
+        }
+    }
+
+    /// When the sender sent it, unique per message from one node
+    pub fn timestamp(&self) -> &str {
+        match self {
+            DiscoveryMessage::Announce { timestamp, .. }
+            | DiscoveryMessage::Goodbye { timestamp, .. }
+            | DiscoveryMessage::Peers { timestamp, .. } => timestamp,
+        }
+    }
 }
 
 pub struct DiscoveryService {
+    config: Arc<Config>,
     node_id: String,
     hostname: String,
-    port: u16,
+    signing_key: SigningKey,
+    trust_store: Arc<TrustStore>,
+    sources: Arc<SourceRegistry>,
+    shutdown: watch::Receiver<bool>,
 }
 
 impl DiscoveryService {
-    pub fn new(node_id: String, hostname: String, port: u16) -> Self {
+    pub fn new(
+        config: Arc<Config>,
+        node_id: String,
+        hostname: String,
+        signing_key: SigningKey,
+        trust_store: Arc<TrustStore>,
+        sources: Arc<SourceRegistry>,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
         DiscoveryService {
+            config,
             node_id,
             hostname,
-            port,
+            signing_key,
+            trust_store,
+            sources,
+            shutdown,
         }
     }
 
     pub async fn start_announcing(
         &self,
         peer_registry: Arc<super::PeerRegistry>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<JoinHandle<()>, Box<dyn std::error::Error>> {
         let socket = UdpSocket::bind("0.0.0.0:0").await?;
         socket.set_broadcast(true)?;
 
-        let multicast_addr = SocketAddr::new(IpAddr::V4(MULTICAST_ADDR), MULTICAST_PORT);
-
         let node_id = self.node_id.clone();
         let hostname = self.hostname.clone();
-        let port = self.port;
+        let port = self.config.port;
+        let announce_period = Duration::from_secs(self.config.discovery_interval);
+        let signing_key = self.signing_key.clone();
+        let sources = self.sources.clone();
+        let mut shutdown = self.shutdown.clone();
 
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(30));
-            // Start at VPN_SCAN_INTERVAL to trigger first scan on startup
-            let mut vpn_scan_counter: u32 = VPN_SCAN_INTERVAL;
-            let vpn_peer_cache: Arc<RwLock<Vec<IpAddr>>> = Arc::new(RwLock::new(Vec::new()));
+        Ok(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(announce_period);
 
             loop {
-                interval.tick().await;
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    // The watch guard isn't Send, so drop it before awaiting below
+                    _ = async { let _ = shutdown.wait_for(|stopping| *stopping).await; } => {
+                        // Tell peers we're leaving instead of letting them time us out
+                        let goodbye = DiscoveryMessage::Goodbye {
+                            node_id: node_id.clone(),
+                            reason: "shutdown".to_string(),
+                            timestamp: chrono::Utc::now().to_rfc3339(),
+                        };
+                        match trust::seal(&goodbye, &signing_key) {
+                            Ok(packet) => {
+                                sources.announce(&socket, &[packet]).await;
+                                tracing::info!("Sent discovery goodbye");
+                            }
+                            Err(e) => tracing::warn!("Failed to sign discovery goodbye: {}", e),
+                        }
+                        break;
+                    }
+                }
 
-                // Get local IP addresses
-                let addresses = get_local_addresses();
+                // Every local address, tagged so peers can pick one they can reach
+                let local_interfaces = interfaces::local_addresses();
+                let addresses = local_interfaces.iter().map(|i| i.address).collect();
 
-                // Get known peers
-                let known_peers: Vec<String> = peer_registry
-                    .get_all_nodes()
-                    .await
-                    .iter()
-                    .map(|n| n.id.clone())
-                    .collect();
+                let nodes = peer_registry.get_all_nodes().await;
 
                 let announce = DiscoveryMessage::Announce {
                     node_id: node_id.clone(),
@@ -88,157 +159,374 @@ impl DiscoveryService {
                     port,
                     timestamp: chrono::Utc::now().to_rfc3339(),
                     version: env!("CARGO_PKG_VERSION").to_string(),
-                    known_peers,
+                    peers: gossip::peer_summaries(&nodes),
+                    interfaces: local_interfaces,
                 };
 
-                if let Ok(json) = serde_json::to_string(&announce) {
-                    // Send via multicast (works on regular networks)
-                    let _ = socket.send_to(json.as_bytes(), multicast_addr).await;
-                    tracing::debug!("Sent discovery announcement via multicast");
-
-                    // Also send unicast to WireGuard peers (multicast doesn't traverse WG tunnels)
-                    let wg_peer_ips = wireguard::get_wireguard_peer_ips();
-                    if !wg_peer_ips.is_empty() {
-                        tracing::debug!(
-                            "Sending unicast discovery to {} WireGuard peers",
-                            wg_peer_ips.len()
-                        );
-                        for peer_ip in wg_peer_ips {
-                            let peer_addr = SocketAddr::new(peer_ip, MULTICAST_PORT);
-                            if let Err(e) = socket.send_to(json.as_bytes(), peer_addr).await {
-                                tracing::trace!(
-                                    "Failed to send to WireGuard peer {}: {}",
-                                    peer_addr,
-                                    e
-                                );
-                            }
-                        }
-                    }
-
-                    // Periodically scan VPN subnets for peers (OpenConnect, OpenVPN, etc.)
-                    // This is more expensive than WireGuard lookup, so we do it less frequently
-                    vpn_scan_counter += 1;
-                    if vpn_scan_counter >= VPN_SCAN_INTERVAL {
-                        vpn_scan_counter = 0;
-
-                        // Spawn scan in background to not block announcements
-                        let cache = vpn_peer_cache.clone();
-                        tokio::spawn(async move {
-                            tracing::debug!("Starting VPN subnet scan for peer discovery");
-                            let peers = vpn_scan::get_vpn_peer_ips().await;
-                            let mut cache_guard = cache.write().await;
-                            *cache_guard = peers;
-                        });
-                    }
-
-                    // Send unicast to cached VPN peers
-                    let vpn_peers = vpn_peer_cache.read().await;
-                    if !vpn_peers.is_empty() {
-                        tracing::debug!(
-                            "Sending unicast discovery to {} VPN peers",
-                            vpn_peers.len()
-                        );
-                        for &peer_ip in vpn_peers.iter() {
-                            let peer_addr = SocketAddr::new(peer_ip, MULTICAST_PORT);
-                            if let Err(e) = socket.send_to(json.as_bytes(), peer_addr).await {
-                                tracing::trace!("Failed to send to VPN peer {}: {}", peer_addr, e);
-                            }
-                        }
-                    }
+                // Peer summaries spill into extra packets to stay under the MTU
+                let packets = wire::split_announcement(announce).and_then(|messages| {
+                    messages
+                        .iter()
+                        .map(|message| trust::seal(message, &signing_key))
+                        .collect::<Result<Vec<_>, _>>()
+                });
+                match packets {
+                    Ok(packets) => sources.announce(&socket, &packets).await,
+                    Err(e) => tracing::warn!("Failed to sign discovery announcement: {}", e),
                 }
             }
-        });
-
-        Ok(())
+        }))
     }
 
+    /// Listen on the IPv4 group and, where the host has IPv6, the IPv6 group
+    ///
+    /// Every received datagram is counted in `packet_stats`.
     pub async fn start_listening(
         &self,
         peer_registry: Arc<super::PeerRegistry>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        packet_stats: Arc<PacketStats>,
+    ) -> Result<Vec<JoinHandle<()>>, Box<dyn std::error::Error>> {
+        // Shared by both listeners, since a peer's packets arrive on each
+        let guards = Arc::new(ListenerGuards {
+            rate_limiter: RateLimiter::new(self.config.max_packets_per_source),
+            // Anything older than a peer could stay registered, allowing for skew
+            replay_guard: ReplayGuard::new(Duration::from_secs(
+                self.config.peer_timeout + super::MAX_CLOCK_SKEW_SECS.unsigned_abs(),
+            )),
+            packet_stats,
+        });
+        let mut handles =
+            vec![self.spawn_listener(self.bind_v4().await?, peer_registry.clone(), guards.clone())];
+
+        match self.bind_v6() {
+            Ok(socket) => handles.push(self.spawn_listener(socket, peer_registry, guards)),
+            Err(e) => tracing::warn!("IPv6 multicast discovery unavailable: {}", e),
+        }
+
+        Ok(handles)
+    }
+
+    /// Bind the discovery port and join the IPv4 group on every interface
+    async fn bind_v4(&self) -> Result<UdpSocket, Box<dyn std::error::Error>> {
+        let group = self.config.multicast_group;
         let socket = UdpSocket::bind(SocketAddr::new(
             IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-            MULTICAST_PORT,
+            self.config.multicast_port,
         ))
         .await?;
 
-        // Join multicast group
-        socket.join_multicast_v4(MULTICAST_ADDR, Ipv4Addr::UNSPECIFIED)?;
+        let mut joined = 0;
+        for interface in interfaces::multicast_interfaces_v4() {
+            match socket.join_multicast_v4(group, interface) {
+                Ok(()) => joined += 1,
+                Err(e) => tracing::debug!("Could not join {} on {}: {}", group, interface, e),
+            }
+        }
+        // No usable interface yet; let the kernel pick one
+        if joined == 0 {
+            socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)?;
+        }
+
+        Ok(socket)
+    }
+
+    /// Bind the discovery port for IPv6 only (the IPv4 socket already holds it
+    /// for IPv4) and join the link-scoped group on every interface with IPv6
+    fn bind_v6(&self) -> Result<UdpSocket, Box<dyn std::error::Error>> {
+        let group = self.config.multicast_group_v6;
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(true)?;
+        socket.bind(
+            &SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                self.config.multicast_port,
+            )
+            .into(),
+        )?;
+
+        let mut joined = 0;
+        for index in interfaces::multicast_interfaces_v6() {
+            match socket.join_multicast_v6(&group, index) {
+                Ok(()) => joined += 1,
+                Err(e) => tracing::debug!("Could not join {} on interface {}: {}", group, index, e),
+            }
+        }
+        if joined == 0 {
+            return Err("no interface with IPv6 multicast".into());
+        }
+
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
 
+    fn spawn_listener(
+        &self,
+        socket: UdpSocket,
+        peer_registry: Arc<super::PeerRegistry>,
+        guards: Arc<ListenerGuards>,
+    ) -> JoinHandle<()> {
         let local_node_id = peer_registry.local_node_id().to_string();
+        let trust_store = self.trust_store.clone();
+        let mut shutdown = self.shutdown.clone();
 
         tokio::spawn(async move {
-            let mut buf = vec![0u8; 4096];
+            // Read whole datagrams so oversized ones can be counted, not truncated
+            let mut buf = vec![0u8; 65536];
 
             loop {
-                match socket.recv_from(&mut buf).await {
-                    Ok((len, _addr)) => {
-                        if let Some(slice) = buf.get(..len) {
-                            if let Ok(json_str) = std::str::from_utf8(slice) {
-                                if let Ok(msg) = serde_json::from_str::<DiscoveryMessage>(json_str)
-                                {
-                                    match msg {
-                                        DiscoveryMessage::Announce {
-                                            node_id,
-                                            hostname,
-                                            addresses,
-                                            port,
-                                            timestamp,
-                                            ..
-                                        } => {
-                                            // Ignore announcements from self
-                                            if node_id != local_node_id {
-                                                let node = super::NodeInfo {
-                                                    id: node_id.clone(),
-                                                    hostname,
-                                                    addresses,
-                                                    port,
-                                                    status: super::NodeStatus::Online,
-                                                    last_seen: timestamp,
-                                                    discovered_via: "broadcast".to_string(),
-                                                };
-                                                peer_registry.add_node(node).await;
-                                                tracing::info!("Discovered node: {}", node_id);
-                                            }
-                                        }
-                                        DiscoveryMessage::Goodbye { node_id, .. } => {
-                                            peer_registry.remove_node(&node_id).await;
-                                            tracing::info!("Node left: {}", node_id);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+                let received = tokio::select! {
+                    received = socket.recv_from(&mut buf) => received,
+                    _ = shutdown.wait_for(|stopping| *stopping) => break,
+                };
+
+                let (len, addr) = match received {
+                    Ok(received) => received,
                     Err(e) => {
                         tracing::error!("Error receiving discovery message: {}", e);
+                        continue;
+                    }
+                };
+                let received_at = chrono::Utc::now();
+
+                let result = guards.admit(&trust_store, buf.get(..len), addr.ip()).await;
+                guards.packet_stats.record(&result);
+
+                match result {
+                    Ok(message) => {
+                        handle_message(
+                            message,
+                            addr.ip(),
+                            received_at,
+                            &local_node_id,
+                            &peer_registry,
+                        )
+                        .await
                     }
+                    Err(e) => tracing::debug!("Dropped discovery packet from {}: {}", addr, e),
                 }
             }
-        });
+        })
+    }
+}
+
+/// Checks every received datagram passes before it reaches the registry
+struct ListenerGuards {
+    rate_limiter: RateLimiter,
+    replay_guard: ReplayGuard,
+    packet_stats: Arc<PacketStats>,
+}
+
+impl ListenerGuards {
+    /// Cheapest checks first, so a flood is dropped before signatures are verified
+    async fn admit(
+        &self,
+        trust_store: &TrustStore,
+        data: Option<&[u8]>,
+        source: IpAddr,
+    ) -> Result<DiscoveryMessage, PacketError> {
+        self.rate_limiter.check(source)?;
+        let data = match data {
+            Some(data) if data.len() <= wire::MAX_RECEIVE_SIZE => data,
+            Some(data) => return Err(PacketError::Oversized(data.len())),
+            None => return Err(PacketError::Oversized(0)),
+        };
+        let message = trust_store.accept(data, source).await?;
+        self.replay_guard
+            .check(message.node_id(), message.timestamp())?;
+        Ok(message)
+    }
+}
+
+async fn handle_message(
+    message: DiscoveryMessage,
+    source: IpAddr,
+    received_at: chrono::DateTime<chrono::Utc>,
+    local_node_id: &str,
+    peer_registry: &super::PeerRegistry,
+) {
+    match message {
+        DiscoveryMessage::Announce {
+            node_id,
+            hostname,
+            addresses,
+            port,
+            timestamp,
+            peers,
+            interfaces,
+            ..
+        } => {
+            // Ignore announcements from self
+            if node_id == local_node_id {
+                return;
+            }
+
+            let clock_offset_ms = super::clock_offset_ms(&timestamp, received_at);
+            let clock_skewed = super::is_clock_skewed(clock_offset_ms);
+            let was_skewed = peer_registry
+                .get_node(&node_id)
+                .await
+                .is_some_and(|n| n.clock_skewed);
+            if clock_skewed && !was_skewed {
+                tracing::warn!(
+                    "Clock of node {} is off by {}s; TLS and Kerberos to it may fail",
+                    node_id,
+                    clock_offset_ms.unwrap_or_default() / 1000
+                );
+            }
+
+            let node = super::NodeInfo {
+                id: node_id.clone(),
+                hostname,
+                addresses,
+                port,
+                status: super::NodeStatus::Online,
+                last_seen: timestamp,
+                received_at,
+                clock_offset_ms,
+                clock_skewed,
+                last_ping_ok: None,
+                last_api_ok: None,
+                interfaces,
+                discovered_via: "broadcast".to_string(),
+                wireguard_path: wireguard::classify_source(source, &wireguard::snapshot()),
+                learned_from: None,
+            };
+            if peer_registry.add_node(node).await {
+                tracing::info!("Discovered node: {}", node_id);
+            } else {
+                tracing::debug!("Node re-announced: {}", node_id);
+            }
+
+            merge_peers(&node_id, peers, peer_registry).await;
+        }
+        DiscoveryMessage::Peers { node_id, peers, .. } => {
+            if node_id != local_node_id {
+                merge_peers(&node_id, peers, peer_registry).await;
+            }
+        }
+        DiscoveryMessage::Goodbye { node_id, .. } => {
+            peer_registry.remove_node(&node_id, "goodbye").await;
+            tracing::info!("Node left: {}", node_id);
+        }
+    }
+}
 
-        Ok(())
+async fn merge_peers(from: &str, peers: Vec<PeerSummary>, peer_registry: &super::PeerRegistry) {
+    for learned in peer_registry.merge_gossip(from, peers).await {
+        tracing::info!("Learned of node {} via {}", learned, from);
     }
 }
 
-fn get_local_addresses() -> Vec<IpAddr> {
-    use std::net::UdpSocket as StdUdpSocket;
+/// Multicast to the IPv4 and IPv6 discovery groups on every interface, for
+/// peers on the same LAN
+pub struct MulticastSource {
+    config: Arc<Config>,
+    /// Separate socket for the IPv6 group, None if the host has no IPv6
+    socket_v6: Option<UdpSocket>,
+}
+
+impl MulticastSource {
+    pub fn new(config: Arc<Config>) -> Self {
+        let socket_v6 = match bind_sender_v6() {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                tracing::debug!("Not announcing over IPv6 multicast: {}", e);
+                None
+            }
+        };
+        MulticastSource { config, socket_v6 }
+    }
+
+    /// Send to the IPv4 group out of each interface; returns datagrams sent
+    async fn announce_v4(&self, socket: &UdpSocket, message: &[u8]) -> usize {
+        let group = SocketAddr::new(
+            IpAddr::V4(self.config.multicast_group),
+            self.config.multicast_port,
+        );
+        let interfaces = interfaces::multicast_interfaces_v4();
+
+        // No usable interface yet; let the kernel pick one
+        if interfaces.is_empty() {
+            return send_logged(socket, message, group).await;
+        }
+
+        let mut sent = 0;
+        for interface in interfaces {
+            if let Err(e) = SockRef::from(socket).set_multicast_if_v4(&interface) {
+                tracing::debug!("Could not send multicast from {}: {}", interface, e);
+                continue;
+            }
+            sent += send_logged(socket, message, group).await;
+        }
+        sent
+    }
 
-    // Try to get local IP by connecting to a public address
-    let mut addresses = Vec::new();
+    /// Send to the link-scoped IPv6 group on each interface with IPv6
+    async fn announce_v6(&self, message: &[u8]) -> usize {
+        let Some(socket) = &self.socket_v6 else {
+            return 0;
+        };
 
-    if let Ok(socket) = StdUdpSocket::bind("0.0.0.0:0") {
-        if socket.connect("8.8.8.8:80").is_ok() {
-            if let Ok(addr) = socket.local_addr() {
-                addresses.push(addr.ip());
+        let mut sent = 0;
+        for index in interfaces::multicast_interfaces_v6() {
+            if let Err(e) = SockRef::from(socket).set_multicast_if_v6(index) {
+                tracing::debug!("Could not send multicast on interface {}: {}", index, e);
+                continue;
             }
+            let group = SocketAddrV6::new(
+                self.config.multicast_group_v6,
+                self.config.multicast_port,
+                0,
+                index,
+            );
+            sent += send_logged(socket, message, group.into()).await;
         }
+        sent
+    }
+}
+
+impl DiscoverySource for MulticastSource {
+    fn name(&self) -> &'static str {
+        "multicast"
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        // Replies arrive through the listener, there is nothing to poll
+        None
+    }
+
+    fn discover<'a>(
+        &'a self,
+        _peer_registry: &'a super::PeerRegistry,
+    ) -> BoxFuture<'a, Result<Vec<super::NodeInfo>, String>> {
+        Box::pin(async { Ok(Vec::new()) })
     }
 
-    // Fallback to localhost if we couldn't determine the real IP
-    if addresses.is_empty() {
-        addresses.push(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
+    fn announce<'a>(&'a self, socket: &'a UdpSocket, message: &'a [u8]) -> BoxFuture<'a, usize> {
+        Box::pin(async move {
+            self.announce_v4(socket, message).await + self.announce_v6(message).await
+        })
     }
+}
+
+/// IPv6 socket for sending to the discovery group
+fn bind_sender_v6() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(true)?;
+    socket.bind(&SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0).into())?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
 
-    addresses
+async fn send_logged(socket: &UdpSocket, message: &[u8], group: SocketAddr) -> usize {
+    match socket.send_to(message, group).await {
+        Ok(_) => {
+            tracing::debug!("Sent discovery message via multicast to {}", group);
+            1
+        }
+        Err(e) => {
+            tracing::debug!("Failed to send discovery multicast to {}: {}", group, e);
+            0
+        }
+    }
 }
@@ -1,32 +1,238 @@
 // Gossip protocol for sharing peer information
+// Announcements carry a summary of the peers each node knows, so nodes that
+// can only see each other through a third node (a routed VPN where multicast
+// and subnet scans don't reach) still learn about each other and can then
+// talk directly
 
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use tokio::time::{interval, Duration};
 
-use super::PeerRegistry;
+use super::source::{self, DiscoverySource};
+use super::{NodeInfo, PeerRegistry};
+use crate::config::Config;
+
+/// Cap on summaries per announcement, keeps the datagram well under the UDP limit
+const MAX_GOSSIP_PEERS: usize = 32;
+
+/// What a node passes on about a peer it knows
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerSummary {
+    pub id: String,
+    pub hostname: String,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+    pub last_seen: String,
+}
+
+/// Summaries of peers we hear from directly, most recently seen first
+///
+/// Nodes learned through gossip aren't passed on: two nodes re-gossiping a
+/// departed peer to each other would keep refreshing it and it would never
+/// time out.
+pub fn peer_summaries(nodes: &[NodeInfo]) -> Vec<PeerSummary> {
+    let mut summaries: Vec<PeerSummary> = nodes
+        .iter()
+        .filter(|node| node.learned_from.is_none())
+        .map(|node| PeerSummary {
+            id: node.id.clone(),
+            hostname: node.hostname.clone(),
+            addresses: node.addresses.clone(),
+            port: node.port,
+            last_seen: node.last_seen.clone(),
+        })
+        .collect();
+
+    summaries.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+    summaries.truncate(MAX_GOSSIP_PEERS);
+    summaries
+}
 
 pub struct GossipService {
+    config: Arc<Config>,
     peer_registry: Arc<PeerRegistry>,
+    shutdown: watch::Receiver<bool>,
 }
 
 impl GossipService {
-    pub fn new(peer_registry: Arc<PeerRegistry>) -> Self {
-        GossipService { peer_registry }
+    pub fn new(
+        config: Arc<Config>,
+        peer_registry: Arc<PeerRegistry>,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
+        GossipService {
+            config,
+            peer_registry,
+            shutdown,
+        }
     }
 
-    pub async fn start_cleanup_task(&self) {
+    pub async fn start_cleanup_task(&self) -> JoinHandle<()> {
         let peer_registry = self.peer_registry.clone();
+        let peer_timeout = self.config.peer_timeout;
+        // Two missed announcements mark a node offline
+        let offline_after = (self.config.discovery_interval * 2).min(peer_timeout);
+        let mut shutdown = self.shutdown.clone();
 
         tokio::spawn(async move {
-            let mut cleanup_interval = interval(Duration::from_secs(60));
+            // Check a few times per timeout so stale nodes don't linger much past it
+            let mut cleanup_interval = interval(Duration::from_secs((peer_timeout / 3).max(1)));
 
             loop {
-                cleanup_interval.tick().await;
+                tokio::select! {
+                    _ = cleanup_interval.tick() => {}
+                    _ = shutdown.wait_for(|stopping| *stopping) => break,
+                }
 
-                // Clean up nodes that haven't been seen in 90 seconds
-                peer_registry.cleanup_stale_nodes(90).await;
+                // Mark silent nodes offline and drop those past the peer timeout
+                peer_registry
+                    .update_statuses(offline_after, peer_timeout)
+                    .await;
                 tracing::debug!("Cleaned up stale nodes");
             }
-        });
+        })
+    }
+}
+
+/// Unicast announcements to nodes learned through gossip, which may sit
+/// somewhere multicast never reaches
+pub struct GossipSource {
+    config: Arc<Config>,
+    peer_registry: Arc<PeerRegistry>,
+}
+
+impl GossipSource {
+    pub fn new(config: Arc<Config>, peer_registry: Arc<PeerRegistry>) -> Self {
+        GossipSource {
+            config,
+            peer_registry,
+        }
+    }
+}
+
+impl DiscoverySource for GossipSource {
+    fn name(&self) -> &'static str {
+        "gossip"
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        // Peer summaries arrive inside announcements
+        None
+    }
+
+    fn discover<'a>(
+        &'a self,
+        _peer_registry: &'a PeerRegistry,
+    ) -> BoxFuture<'a, Result<Vec<NodeInfo>, String>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    fn announce<'a>(&'a self, socket: &'a UdpSocket, message: &'a [u8]) -> BoxFuture<'a, usize> {
+        Box::pin(async move {
+            let mut peers: Vec<IpAddr> = self
+                .peer_registry
+                .get_all_nodes()
+                .await
+                .iter()
+                .filter(|node| node.learned_from.is_some())
+                .flat_map(|node| node.addresses.iter().copied().filter(IpAddr::is_ipv4))
+                .collect();
+            peers.sort();
+            peers.dedup();
+
+            if !peers.is_empty() {
+                tracing::debug!("Sending unicast discovery to {} gossip peers", peers.len());
+            }
+            source::send_unicast(socket, message, &peers, self.config.multicast_port).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::test_support::node;
+
+    fn summary(id: &str, last_seen: &str) -> PeerSummary {
+        PeerSummary {
+            id: id.to_string(),
+            hostname: id.to_string(),
+            addresses: vec!["10.8.0.15".parse().unwrap()],
+            port: 8080,
+            last_seen: last_seen.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_gossip() {
+        let registry = PeerRegistry::new("local".to_string());
+        registry
+            .add_node(node("b", "2025-11-22T10:30:00Z", chrono::Utc::now()))
+            .await;
+
+        let learned = registry
+            .merge_gossip(
+                "b",
+                vec![
+                    summary("local", "2025-11-22T10:30:00Z"),
+                    summary("k", "2025-11-22T10:29:00Z"),
+                ],
+            )
+            .await;
+        assert_eq!(learned, vec!["k".to_string()]);
+
+        let k = registry.get_node("k").await.unwrap();
+        assert_eq!(k.discovered_via, "gossip");
+        assert_eq!(k.learned_from.as_deref(), Some("b"));
+
+        // Stale gossip doesn't roll back a newer sighting
+        registry
+            .merge_gossip("c", vec![summary("k", "2025-11-22T10:00:00Z")])
+            .await;
+        let k = registry.get_node("k").await.unwrap();
+        assert_eq!(k.learned_from.as_deref(), Some("b"));
+
+        // Gossip never overrides a node we hear from directly
+        registry
+            .merge_gossip("k", vec![summary("b", "2025-11-22T11:00:00Z")])
+            .await;
+        let b = registry.get_node("b").await.unwrap();
+        assert_eq!(b.discovered_via, "broadcast");
+        assert!(b.learned_from.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_departed_node_expires() {
+        let a = PeerRegistry::new("a".to_string());
+        let c = PeerRegistry::new("c".to_string());
+        let now = chrono::Utc::now();
+        a.add_node(node("c", &now.to_rfc3339(), now)).await;
+        c.add_node(node("a", &now.to_rfc3339(), now)).await;
+
+        // a last heard from b five minutes ago and passes it on to c
+        let departed = now - chrono::Duration::minutes(5);
+        a.add_node(node("b", &departed.to_rfc3339(), departed))
+            .await;
+        c.merge_gossip("a", peer_summaries(&a.get_all_nodes().await))
+            .await;
+        assert!(c.get_node("b").await.is_some());
+
+        // c doesn't hand b back, so once a drops it, it stays gone
+        a.update_statuses(60, 90).await;
+        for _ in 0..3 {
+            a.merge_gossip("c", peer_summaries(&c.get_all_nodes().await))
+                .await;
+            c.merge_gossip("a", peer_summaries(&a.get_all_nodes().await))
+                .await;
+        }
+        assert!(a.get_node("b").await.is_none());
+        assert!(peer_summaries(&c.get_all_nodes().await)
+            .iter()
+            .all(|peer| peer.id != "b"));
     }
 }
@@ -0,0 +1,164 @@
+// Persistent node identity
+// The node ID and signing key live in the state directory so a restarted node
+// announces the same ID with the same key and peers recognise it instead of
+// seeing a duplicate (or an impostor)
+
+use ed25519_dalek::SigningKey;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+const NODE_ID_FILE: &str = "node_id";
+const NODE_KEY_FILE: &str = "node_key";
+
+/// Default state directory: $XDG_STATE_HOME, ~/.local/state or %LOCALAPPDATA%.
+/// None when none of those is set; the signing key must not fall back to a
+/// shared directory such as /tmp
+pub fn default_state_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .map(|base| base.join("network-route-visualizer"))
+}
+
+/// Resolve the node ID: an explicit override wins, then the persisted ID,
+/// otherwise a new ID is generated and saved for the next start
+pub fn load_or_create_node_id(state_dir: &Path, override_id: Option<&str>) -> String {
+    if let Some(id) = override_id.map(str::trim).filter(|id| !id.is_empty()) {
+        return id.to_string();
+    }
+
+    match read_node_id(state_dir) {
+        Ok(Some(id)) => return id,
+        Ok(None) => {}
+        Err(e) => tracing::warn!("Ignoring unreadable node ID: {}", e),
+    }
+
+    let id = Uuid::new_v4().to_string();
+    if let Err(e) = write_node_id(state_dir, &id) {
+        tracing::warn!(
+            "Could not persist node ID ({}); this node will get a new ID on restart",
+            e
+        );
+    }
+    id
+}
+
+/// Load the node's ed25519 signing key, generating and saving one on first start
+pub fn load_or_create_signing_key(state_dir: &Path) -> SigningKey {
+    let path = state_dir.join(NODE_KEY_FILE);
+
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            let secret: Option<[u8; 32]> = hex::decode(content.trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok());
+            match secret {
+                Some(secret) => return SigningKey::from_bytes(&secret),
+                None => tracing::warn!("Ignoring malformed signing key in {}", path.display()),
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => tracing::warn!("Failed to read {}: {}", path.display(), e),
+    }
+
+    let key = SigningKey::generate(&mut rand::rngs::OsRng);
+    if let Err(e) = write_secret(state_dir, &path, &hex::encode(key.to_bytes())) {
+        tracing::warn!(
+            "Could not persist signing key ({}); peers will see a new key on restart",
+            e
+        );
+    }
+    key
+}
+
+/// Create the state directory (and any missing parents) readable only by us
+fn create_state_dir(state_dir: &Path) -> Result<(), String> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+
+    builder
+        .create(state_dir)
+        .map_err(|e| format!("{}: {}", state_dir.display(), e))
+}
+
+fn write_secret(state_dir: &Path, path: &Path, content: &str) -> Result<(), String> {
+    create_state_dir(state_dir)?;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut file, format!("{}\n", content).as_bytes())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn read_node_id(state_dir: &Path) -> Result<Option<String>, String> {
+    let path = state_dir.join(NODE_ID_FILE);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+
+    let id = content.trim();
+    Uuid::parse_str(id)
+        .map(|uuid| Some(uuid.to_string()))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn write_node_id(state_dir: &Path, id: &str) -> Result<(), String> {
+    create_state_dir(state_dir)?;
+    let path = state_dir.join(NODE_ID_FILE);
+    std::fs::write(&path, format!("{}\n", id)).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_id_survives_restart() {
+        let dir = std::env::temp_dir().join(format!("nrv-identity-{}", Uuid::new_v4()));
+
+        let first = load_or_create_node_id(&dir, None);
+        let second = load_or_create_node_id(&dir, None);
+        assert_eq!(first, second);
+
+        let overridden = load_or_create_node_id(&dir, Some("machine-a"));
+        assert_eq!(overridden, "machine-a");
+        assert_eq!(load_or_create_node_id(&dir, None), first);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_signing_key_survives_restart() {
+        let dir = std::env::temp_dir().join(format!("nrv-identity-{}", Uuid::new_v4()));
+
+        let first = load_or_create_signing_key(&dir);
+        let second = load_or_create_signing_key(&dir);
+        assert_eq!(first.to_bytes(), second.to_bytes());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
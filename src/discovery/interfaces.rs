@@ -0,0 +1,205 @@
+// Local interface addresses
+// Every usable address on this host, tagged with the interface it's on and
+// the kind of VPN that interface belongs to. Announcements carry these so a
+// peer can pick an address it can actually reach (one on a shared subnet, or
+// on the same VPN) instead of whichever address happens to come first
+
+use if_addrs::{IfAddr, IfOperStatus, Interface};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr};
+
+use super::vpn_scan::{vpn_kind, VpnKind};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InterfaceAddress {
+    pub address: IpAddr,
+    pub interface: String,
+    pub prefix_len: u8,
+    /// VPN the interface belongs to, None for ordinary LAN interfaces
+    pub vpn: Option<VpnKind>,
+}
+
+impl InterfaceAddress {
+    /// Whether `other` is on the same subnet as this address
+    pub fn contains(&self, other: IpAddr) -> bool {
+        match (self.address, other) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len.min(32)))
+                    .unwrap_or(0);
+                u32::from(a) & mask == u32::from(b) & mask
+            }
+            (IpAddr::V6(a), IpAddr::V6(b)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len.min(128)))
+                    .unwrap_or(0);
+                u128::from(a) & mask == u128::from(b) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(_)) | (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+/// Every address on this host worth announcing, LAN IPv4 first, then VPN
+/// IPv4, then IPv6. Loopback and link-local addresses are left out: peers
+/// can't reach the former and would need a scope id for the latter.
+pub fn local_addresses() -> Vec<InterfaceAddress> {
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            tracing::warn!("Failed to list network interfaces: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut addresses: Vec<InterfaceAddress> = interfaces
+        .into_iter()
+        .filter(|iface| !iface.is_loopback() && !iface.is_link_local())
+        .map(|iface| {
+            let prefix_len = match &iface.addr {
+                IfAddr::V4(v4) => v4.prefixlen,
+                IfAddr::V6(v6) => v6.prefixlen,
+            };
+            InterfaceAddress {
+                address: iface.ip(),
+                vpn: vpn_kind(&iface.name),
+                interface: iface.name,
+                prefix_len,
+            }
+        })
+        .collect();
+
+    addresses.sort_by_key(|a| (a.address.is_ipv6(), a.vpn.is_some()));
+    addresses
+}
+
+/// IPv4 addresses of the interfaces to join and send the discovery group on
+pub fn multicast_interfaces_v4() -> Vec<Ipv4Addr> {
+    let mut addresses: Vec<Ipv4Addr> = multicast_capable()
+        .into_iter()
+        .filter_map(|iface| match iface.addr {
+            IfAddr::V4(v4) => Some(v4.ip),
+            IfAddr::V6(_) => None,
+        })
+        .collect();
+    addresses.sort();
+    addresses.dedup();
+    addresses
+}
+
+/// Indexes of the interfaces with IPv6 (a link-local address is enough) for
+/// the link-scoped IPv6 discovery group, which has to be joined per interface
+pub fn multicast_interfaces_v6() -> Vec<u32> {
+    let mut indexes: Vec<u32> = multicast_capable()
+        .into_iter()
+        .filter(|iface| iface.addr.ip().is_ipv6())
+        .filter_map(|iface| iface.index)
+        .collect();
+    indexes.sort();
+    indexes.dedup();
+    indexes
+}
+
+fn multicast_capable() -> Vec<Interface> {
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces
+            .into_iter()
+            .filter(|iface| !iface.is_loopback() && iface.oper_status != IfOperStatus::Down)
+            .collect(),
+        Err(e) => {
+            tracing::warn!("Failed to list network interfaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Pick the address of a peer to use from here
+///
+/// An address on a subnet we're directly attached to wins, then one on a VPN
+/// we're also on, then the first IPv4 address, then whatever comes first.
+pub fn preferred_address(
+    peer: &[IpAddr],
+    peer_interfaces: &[InterfaceAddress],
+    local: &[InterfaceAddress],
+) -> Option<IpAddr> {
+    let on_shared_subnet = peer
+        .iter()
+        .copied()
+        .find(|&address| local.iter().any(|l| l.contains(address)));
+
+    let on_shared_vpn = || {
+        peer_interfaces
+            .iter()
+            .find(|p| {
+                p.vpn
+                    .is_some_and(|kind| local.iter().any(|l| l.vpn == Some(kind)))
+            })
+            .map(|p| p.address)
+    };
+
+    on_shared_subnet
+        .or_else(on_shared_vpn)
+        .or_else(|| peer.iter().copied().find(IpAddr::is_ipv4))
+        .or_else(|| peer.first().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iface(address: &str, prefix_len: u8, vpn: Option<VpnKind>) -> InterfaceAddress {
+        InterfaceAddress {
+            address: address.parse().unwrap(),
+            interface: "test0".to_string(),
+            prefix_len,
+            vpn,
+        }
+    }
+
+    #[test]
+    fn test_preferred_address() {
+        let local = vec![
+            iface("192.168.1.10", 24, None),
+            iface("10.8.0.2", 24, Some(VpnKind::WireGuard)),
+        ];
+
+        // A LAN address we can't route to loses to one on our WireGuard subnet
+        let peer: Vec<IpAddr> = vec!["172.16.5.4".parse().unwrap(), "10.8.0.9".parse().unwrap()];
+        assert_eq!(
+            preferred_address(&peer, &[], &local),
+            Some("10.8.0.9".parse().unwrap())
+        );
+
+        // Same VPN kind on a different subnet still beats the first address
+        let peer_interfaces = vec![
+            iface("172.16.5.4", 24, None),
+            iface("10.99.0.3", 24, Some(VpnKind::WireGuard)),
+        ];
+        let peer: Vec<IpAddr> = peer_interfaces.iter().map(|i| i.address).collect();
+        assert_eq!(
+            preferred_address(&peer, &peer_interfaces, &local),
+            Some("10.99.0.3".parse().unwrap())
+        );
+
+        // Nothing in common: first IPv4
+        let peer: Vec<IpAddr> = vec![
+            "2001:db8::1".parse().unwrap(),
+            "172.16.5.4".parse().unwrap(),
+        ];
+        assert_eq!(
+            preferred_address(&peer, &[], &local),
+            Some("172.16.5.4".parse().unwrap())
+        );
+        assert_eq!(preferred_address(&[], &[], &local), None);
+    }
+
+    #[test]
+    fn test_contains() {
+        let lan = iface("192.168.1.10", 24, None);
+        assert!(lan.contains("192.168.1.200".parse().unwrap()));
+        assert!(!lan.contains("192.168.2.1".parse().unwrap()));
+        assert!(!lan.contains("2001:db8::1".parse().unwrap()));
+        assert!(iface("10.0.0.1", 0, None).contains("8.8.8.8".parse().unwrap()));
+        assert!(iface("2001:db8::5", 64, None).contains("2001:db8::9".parse().unwrap()));
+    }
+}
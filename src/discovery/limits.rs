@@ -0,0 +1,266 @@
+// Flood protection for the discovery listener
+// A per-source token bucket drops datagrams before any signature is checked,
+// and a replay guard drops signed messages that were already seen, are older
+// than the sender's recent ones or are too far from our own clock. Both keep
+// bounded state, so a flood
+// from spoofed addresses or node IDs can't grow memory without limit
+
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::wire::PacketError;
+
+/// Most sources (or nodes) tracked at once; the stalest is forgotten first
+const MAX_TRACKED: usize = 4096;
+/// How far behind a node's newest message a timestamp may lag, for packets
+/// that arrive out of order over different interfaces
+const REPLAY_WINDOW_MS: i64 = 30_000;
+/// A node silent this long is forgotten, so one whose clock stepped back
+/// isn't shut out for good
+pub const REPLAY_MEMORY: Duration = Duration::from_secs(3600);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket per source address
+pub struct RateLimiter {
+    per_second: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Allow `per_second` datagrams a second from each source, in bursts of as many
+    pub fn new(per_second: u32) -> Self {
+        RateLimiter {
+            per_second: f64::from(per_second),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, source: IpAddr) -> Result<(), PacketError> {
+        self.check_at(source, Instant::now())
+    }
+
+    fn check_at(&self, source: IpAddr, now: Instant) -> Result<(), PacketError> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if !buckets.contains_key(&source) && buckets.len() >= MAX_TRACKED {
+            // Buckets that have refilled carry no state worth keeping
+            let per_second = self.per_second;
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second
+                    < per_second
+            });
+            if buckets.len() >= MAX_TRACKED {
+                return Err(PacketError::RateLimited);
+            }
+        }
+
+        let bucket = buckets.entry(source).or_insert(Bucket {
+            tokens: self.per_second,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.per_second);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(PacketError::RateLimited);
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+struct SeenTimes {
+    /// Timestamps (ms) accepted within the window behind the newest
+    times: BTreeSet<i64>,
+    last_accepted: Instant,
+}
+
+/// Rejects signed messages a node already sent
+///
+/// Every message carries its send time, and senders never reuse one. A
+/// timestamp seen before is a duplicate (the same packet over another
+/// interface or source); one older than the window is a replay. A message
+/// sent further from our clock than `max_age` is dropped too, so a captured
+/// message can't be replayed once its sender has been forgotten.
+pub struct ReplayGuard {
+    max_age_ms: i64,
+    /// How long a silent node is remembered, never shorter than `max_age`
+    memory: Duration,
+    nodes: Mutex<HashMap<String, SeenTimes>>,
+}
+
+impl ReplayGuard {
+    pub fn new(max_age: Duration) -> Self {
+        ReplayGuard {
+            max_age_ms: i64::try_from(max_age.as_millis()).unwrap_or(i64::MAX),
+            memory: REPLAY_MEMORY.max(max_age),
+            nodes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, node_id: &str, timestamp: &str) -> Result<(), PacketError> {
+        self.check_at(node_id, timestamp, Instant::now(), Utc::now())
+    }
+
+    fn check_at(
+        &self,
+        node_id: &str,
+        timestamp: &str,
+        now: Instant,
+        clock: DateTime<Utc>,
+    ) -> Result<(), PacketError> {
+        let sent_ms = DateTime::parse_from_rfc3339(timestamp)
+            .map_err(|_| PacketError::Malformed("invalid timestamp".to_string()))?
+            .timestamp_millis();
+        if sent_ms.abs_diff(clock.timestamp_millis()) > self.max_age_ms.unsigned_abs() {
+            return Err(PacketError::Replayed);
+        }
+
+        let mut nodes = self.nodes.lock().unwrap_or_else(|e| e.into_inner());
+        nodes.retain(|_, seen| now.duration_since(seen.last_accepted) < self.memory);
+        if !nodes.contains_key(node_id) && nodes.len() >= MAX_TRACKED {
+            let stalest = nodes
+                .iter()
+                .min_by_key(|(_, seen)| seen.last_accepted)
+                .map(|(id, _)| id.clone());
+            if let Some(stalest) = stalest {
+                nodes.remove(&stalest);
+            }
+        }
+
+        let seen = nodes.entry(node_id.to_string()).or_insert(SeenTimes {
+            times: BTreeSet::new(),
+            last_accepted: now,
+        });
+        let newest = seen.times.last().copied().unwrap_or(i64::MIN);
+
+        if sent_ms < newest.saturating_sub(REPLAY_WINDOW_MS) {
+            return Err(PacketError::Replayed);
+        }
+        if !seen.times.insert(sent_ms) {
+            return Err(PacketError::Duplicate);
+        }
+        seen.last_accepted = now;
+
+        let cutoff = seen
+            .times
+            .last()
+            .copied()
+            .unwrap_or(sent_ms)
+            .saturating_sub(REPLAY_WINDOW_MS);
+        seen.times = seen.times.split_off(&cutoff);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(5);
+        let source: IpAddr = "192.168.1.20".parse().unwrap();
+        let other: IpAddr = "192.168.1.21".parse().unwrap();
+        let start = Instant::now();
+
+        for _ in 0..5 {
+            assert!(limiter.check_at(source, start).is_ok());
+        }
+        assert_eq!(
+            limiter.check_at(source, start),
+            Err(PacketError::RateLimited)
+        );
+        // Other sources have their own budget
+        assert!(limiter.check_at(other, start).is_ok());
+        // and it refills over time
+        assert!(limiter
+            .check_at(source, start + Duration::from_millis(200))
+            .is_ok());
+    }
+
+    fn clock(timestamp: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(timestamp).unwrap().into()
+    }
+
+    #[test]
+    fn test_replay_guard() {
+        let guard = ReplayGuard::new(REPLAY_MEMORY);
+        let now = Instant::now();
+        let clock = clock("2025-11-22T10:30:00Z");
+
+        assert!(guard
+            .check_at("a", "2025-11-22T10:30:00.000Z", now, clock)
+            .is_ok());
+        // A peers chunk from the same round, arriving after a later one
+        assert!(guard
+            .check_at("a", "2025-11-22T10:30:00.002Z", now, clock)
+            .is_ok());
+        assert!(guard
+            .check_at("a", "2025-11-22T10:30:00.001Z", now, clock)
+            .is_ok());
+        assert_eq!(
+            guard.check_at("a", "2025-11-22T10:30:00.001Z", now, clock),
+            Err(PacketError::Duplicate)
+        );
+
+        assert!(guard
+            .check_at("a", "2025-11-22T10:31:00.000Z", now, clock)
+            .is_ok());
+        assert_eq!(
+            guard.check_at("a", "2025-11-22T10:30:00.000Z", now, clock),
+            Err(PacketError::Replayed)
+        );
+        // Other nodes are tracked separately
+        assert!(guard
+            .check_at("b", "2025-11-22T10:30:00.000Z", now, clock)
+            .is_ok());
+        assert!(guard.check_at("a", "yesterday", now, clock).is_err());
+        // A message without a timestamp has nothing to check, so it's dropped
+        assert!(matches!(
+            guard.check_at("a", "", now, clock),
+            Err(PacketError::Malformed(_))
+        ));
+
+        // Long silence forgets the node, so a clock that stepped back recovers
+        assert!(guard
+            .check_at("a", "2025-11-22T10:00:00.000Z", now + REPLAY_MEMORY, clock)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_replay_guard_freshness() {
+        let guard = ReplayGuard::new(Duration::from_secs(390));
+        let now = Instant::now();
+        let sent = "2025-11-22T10:30:00.000Z";
+
+        // A receiver that never heard the node still drops an old capture
+        assert_eq!(
+            guard.check_at("a", sent, now, clock("2025-11-22T11:30:00Z")),
+            Err(PacketError::Replayed)
+        );
+        // and one dated too far ahead
+        assert_eq!(
+            guard.check_at("a", sent, now, clock("2025-11-22T10:20:00Z")),
+            Err(PacketError::Replayed)
+        );
+        assert!(guard
+            .check_at("a", sent, now, clock("2025-11-22T10:36:00Z"))
+            .is_ok());
+
+        // After forgetting the node, a replay of that message is still too old
+        let later = now + REPLAY_MEMORY;
+        assert_eq!(
+            guard.check_at("a", sent, later, clock("2025-11-22T11:30:00Z")),
+            Err(PacketError::Replayed)
+        );
+    }
+}
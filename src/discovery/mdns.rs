@@ -0,0 +1,197 @@
+// mDNS / DNS-SD discovery
+// Publishes this node as an _nrv._tcp.local service and browses for others.
+// Standard tools (avahi-browse, dns-sd) can see our nodes, and discovery keeps
+// working on networks that filter unknown multicast groups but allow mDNS.
+// Records aren't signed, so browsed nodes are only announced to by unicast and
+// join the registry once a signed announcement from them arrives
+
+use futures::future::BoxFuture;
+use mdns_sd::{Receiver, ResolvedService, ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+
+use super::source::{self, DiscoverySource};
+use super::{NodeInfo, PeerRegistry};
+use crate::config::Config;
+
+const SERVICE_TYPE: &str = "_nrv._tcp.local.";
+/// How often browse events are collected
+const POLL_INTERVAL_SECS: u64 = 5;
+
+pub struct MdnsSource {
+    config: Arc<Config>,
+    daemon: ServiceDaemon,
+    /// Our own registered instance, withdrawn on shutdown
+    fullname: String,
+    events: Receiver<ServiceEvent>,
+    local_node_id: String,
+    /// mDNS instance name -> addresses to announce to
+    instances: RwLock<HashMap<String, Vec<IpAddr>>>,
+}
+
+impl MdnsSource {
+    /// Register our service record and start browsing for other nodes
+    pub fn new(config: Arc<Config>, node_id: String, hostname: &str) -> Result<Self, String> {
+        let daemon = ServiceDaemon::new().map_err(|e| e.to_string())?;
+
+        // mDNS host names are single labels under .local
+        let host_label = hostname.split('.').next().unwrap_or("nrv");
+        let short_id: String = node_id.chars().take(8).collect();
+        let port = config.port;
+        let port_text = port.to_string();
+        let version = env!("CARGO_PKG_VERSION");
+        let properties = [
+            ("node_id", node_id.as_str()),
+            ("version", version),
+            ("port", port_text.as_str()),
+        ];
+
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &format!("{}-{}", host_label, short_id),
+            &format!("{}.local.", host_label),
+            (),
+            port,
+            &properties[..],
+        )
+        .map_err(|e| e.to_string())?
+        .enable_addr_auto();
+        let fullname = service.get_fullname().to_string();
+
+        daemon.register(service).map_err(|e| e.to_string())?;
+        let events = daemon.browse(SERVICE_TYPE).map_err(|e| e.to_string())?;
+
+        Ok(MdnsSource {
+            config,
+            daemon,
+            fullname,
+            events,
+            local_node_id: node_id,
+            instances: RwLock::new(HashMap::new()),
+        })
+    }
+}
+
+impl DiscoverySource for MdnsSource {
+    fn name(&self) -> &'static str {
+        "mdns"
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(POLL_INTERVAL_SECS))
+    }
+
+    fn discover<'a>(
+        &'a self,
+        _peer_registry: &'a PeerRegistry,
+    ) -> BoxFuture<'a, Result<Vec<NodeInfo>, String>> {
+        Box::pin(async move {
+            let mut instances = self.instances.write().await;
+
+            while let Ok(event) = self.events.try_recv() {
+                if let ServiceEvent::ServiceResolved(service) = event {
+                    if let Some(addresses) = resolved_addresses(&service, &self.local_node_id) {
+                        instances.insert(service.fullname.clone(), addresses);
+                    }
+                } else if let ServiceEvent::ServiceRemoved(_, instance) = event {
+                    // The node itself leaves with a goodbye or times out
+                    instances.remove(&instance);
+                }
+            }
+
+            // Browsed nodes only become nodes once their signed announcements arrive
+            Ok(Vec::new())
+        })
+    }
+
+    fn announce<'a>(&'a self, socket: &'a UdpSocket, message: &'a [u8]) -> BoxFuture<'a, usize> {
+        Box::pin(async move {
+            let mut targets: Vec<IpAddr> = self
+                .instances
+                .read()
+                .await
+                .values()
+                .flatten()
+                .copied()
+                .collect();
+            targets.sort();
+            targets.dedup();
+
+            if !targets.is_empty() {
+                tracing::debug!("Sending unicast discovery to {} mDNS peers", targets.len());
+            }
+            source::send_unicast(socket, message, &targets, self.config.multicast_port).await
+        })
+    }
+
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            // Withdraw our record so browsers see us leave right away
+            if let Ok(status) = self.daemon.unregister(&self.fullname) {
+                let _ = tokio::time::timeout(Duration::from_secs(1), status.recv_async()).await;
+            }
+            let _ = self.daemon.shutdown();
+        })
+    }
+}
+
+/// Addresses to announce to for a resolved service, None for our own record
+///
+/// Nothing in the record is trusted; whoever answers at these addresses still
+/// has to send a signed announcement.
+fn resolved_addresses(service: &ResolvedService, local_node_id: &str) -> Option<Vec<IpAddr>> {
+    let node_id = service.get_property_val_str("node_id")?;
+    if node_id == local_node_id {
+        return None;
+    }
+
+    let addresses: Vec<IpAddr> = service
+        .addresses
+        .iter()
+        .map(|a| a.to_ip_addr())
+        .filter(|a| a.is_ipv4() && !a.is_loopback())
+        .collect();
+    (!addresses.is_empty()).then_some(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(node_id: &str, addresses: &[&str]) -> ResolvedService {
+        let addresses: Vec<IpAddr> = addresses.iter().map(|a| a.parse().unwrap()).collect();
+        ServiceInfo::new(
+            SERVICE_TYPE,
+            "host-b",
+            "host-b.local.",
+            &addresses[..],
+            8080,
+            &[("node_id", node_id), ("port", "8080")][..],
+        )
+        .unwrap()
+        .as_resolved_service()
+    }
+
+    #[test]
+    fn test_resolved_addresses() {
+        let resolved = resolved_addresses(
+            &service("node-b", &["192.168.1.20", "127.0.0.1", "fe80::1"]),
+            "node-a",
+        );
+        assert_eq!(resolved, Some(vec!["192.168.1.20".parse().unwrap()]));
+
+        // Our own record, and records with nothing to announce to
+        assert_eq!(
+            resolved_addresses(&service("node-a", &["192.168.1.10"]), "node-a"),
+            None
+        );
+        assert_eq!(
+            resolved_addresses(&service("node-b", &["127.0.0.1"]), "node-a"),
+            None
+        );
+    }
+}
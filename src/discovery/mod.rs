@@ -3,17 +3,39 @@
 pub mod bandwidth;
 pub mod broadcast;
 pub mod gossip;
+pub mod identity;
+pub mod interfaces;
+pub mod limits;
+pub mod mdns;
+pub mod openvpn;
 pub mod ping;
+pub mod seeds;
+pub mod source;
+pub mod tailscale;
 pub mod traceroute;
+pub mod trust;
+pub mod vpn_peers;
 pub mod vpn_scan;
+pub mod wire;
 pub mod wireguard;
+pub mod zerotier;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use uuid::Uuid;
+
+use crate::api::websocket::ServerMessage;
+
+/// Clock skew beyond which Kerberos rejects tickets (its default tolerance)
+/// and fresh TLS certificates look not-yet-valid to the slower side
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// Default cap on registry size (`max_peers`)
+pub const DEFAULT_MAX_NODES: usize = 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeInfo {
@@ -22,11 +44,48 @@ pub struct NodeInfo {
     pub addresses: Vec<IpAddr>,
     pub port: u16,
     pub status: NodeStatus,
+    /// Timestamp the node itself reported (for gossip, the original sighting)
     pub last_seen: String,
+    /// When we last heard of the node by our own clock; liveness is judged on this
+    #[serde(default = "Utc::now")]
+    pub received_at: DateTime<Utc>,
+    /// How far the node's clock is ahead of ours (negative if behind), measured
+    /// from its direct announcements
+    #[serde(default)]
+    pub clock_offset_ms: Option<i64>,
+    /// The offset exceeds MAX_CLOCK_SKEW_SECS
+    #[serde(default)]
+    pub clock_skewed: bool,
+    /// Whether the last ping got a reply, None until one is tried
+    #[serde(default)]
+    pub last_ping_ok: Option<bool>,
+    /// Whether the last API request to the node succeeded, None until one is tried
+    #[serde(default)]
+    pub last_api_ok: Option<bool>,
+    /// The node's addresses with their interface and VPN kind, as it announced them
+    #[serde(default)]
+    pub interfaces: Vec<interfaces::InterfaceAddress>,
     pub discovered_via: String,
+    /// For WireGuard peers, whether their announcements reach us through the
+    /// tunnel or only over the underlay
+    #[serde(default)]
+    pub wireguard_path: Option<wireguard::WireGuardPath>,
+    /// Node that told us about this one, when it was learned through gossip
+    #[serde(default)]
+    pub learned_from: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+impl NodeInfo {
+    /// The address to reach this node on from a host with the `local` addresses
+    pub fn preferred_address(&self, local: &[interfaces::InterfaceAddress]) -> Option<IpAddr> {
+        interfaces::preferred_address(&self.addresses, &self.interfaces, local)
+    }
+}
+
+/// Node lifecycle: Online while it announces and answers, Unreachable while
+/// it announces but neither ping nor its API answer, Offline once it falls
+/// silent, then removed at the peer timeout
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum NodeStatus {
     Online,
@@ -38,29 +97,123 @@ pub enum NodeStatus {
 pub struct PeerRegistry {
     nodes: Arc<RwLock<HashMap<String, NodeInfo>>>,
     local_node_id: String,
+    /// Updates for WebSocket clients; the registry owns the channel so every
+    /// discovery path can publish node events
+    updates: tokio::sync::broadcast::Sender<ServerMessage>,
+    max_nodes: usize,
+    /// Nodes dropped to make room for new ones
+    evicted: Arc<AtomicU64>,
+    /// New nodes turned away because nothing could be evicted
+    refused: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RegistryStats {
+    pub nodes: usize,
+    pub max_nodes: usize,
+    pub evicted: u64,
+    pub refused: u64,
 }
 
 impl PeerRegistry {
-    pub fn new() -> Self {
-        let local_node_id = Uuid::new_v4().to_string();
+    pub fn new(local_node_id: String) -> Self {
+        let (updates, _) = tokio::sync::broadcast::channel(100);
         PeerRegistry {
             nodes: Arc::new(RwLock::new(HashMap::new())),
             local_node_id,
+            updates,
+            max_nodes: DEFAULT_MAX_NODES,
+            evicted: Arc::new(AtomicU64::new(0)),
+            refused: Arc::new(AtomicU64::new(0)),
         }
     }
 
+    /// Cap the number of nodes kept, so a flood of node IDs can't grow it without bound
+    pub fn with_max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = max_nodes;
+        self
+    }
+
     pub fn local_node_id(&self) -> &str {
         &self.local_node_id
     }
 
-    pub async fn add_node(&self, node: NodeInfo) {
+    pub fn update_sender(&self) -> tokio::sync::broadcast::Sender<ServerMessage> {
+        self.updates.clone()
+    }
+
+    /// Insert or refresh a node; returns true if the ID was not known before
+    ///
+    /// Sources that can't measure clock offset keep the last measured one, and
+    /// probe results carry over so a refresh doesn't hide an unreachable node.
+    pub async fn add_node(&self, mut node: NodeInfo) -> bool {
         let mut nodes = self.nodes.write().await;
+
+        let Some(existing) = nodes.get(&node.id) else {
+            if !self.make_room(&mut nodes) {
+                tracing::debug!("Registry full, ignoring node {}", node.id);
+                return false;
+            }
+            node.status = NodeStatus::Online;
+            self.publish(ServerMessage::NodeDiscovered { node: node.clone() });
+            nodes.insert(node.id.clone(), node);
+            return true;
+        };
+
+        if node.clock_offset_ms.is_none() {
+            node.clock_offset_ms = existing.clock_offset_ms;
+            node.clock_skewed = existing.clock_skewed;
+        }
+        if node.wireguard_path.is_none() {
+            node.wireguard_path = existing.wireguard_path;
+        }
+        node.last_ping_ok = existing.last_ping_ok;
+        node.last_api_ok = existing.last_api_ok;
+        node.status = existing.status;
+
+        let status = probed_status(&node);
+        self.transition(&mut node, status, "announcement");
         nodes.insert(node.id.clone(), node);
+        false
+    }
+
+    /// Remove a node that left, reporting it offline
+    pub async fn remove_node(&self, node_id: &str, reason: &str) {
+        let mut nodes = self.nodes.write().await;
+        if let Some(mut node) = nodes.remove(node_id) {
+            self.transition(&mut node, NodeStatus::Offline, reason);
+        }
+    }
+
+    /// Record the outcome of a ping to a node
+    pub async fn record_ping(&self, node_id: &str, ok: bool) {
+        self.record_probe(node_id, |node| node.last_ping_ok = Some(ok))
+            .await;
+    }
+
+    /// Record the outcome of an API request to a node
+    pub async fn record_api(&self, node_id: &str, ok: bool) {
+        self.record_probe(node_id, |node| node.last_api_ok = Some(ok))
+            .await;
     }
 
-    pub async fn remove_node(&self, node_id: &str) {
+    async fn record_probe(&self, node_id: &str, update: impl FnOnce(&mut NodeInfo)) {
         let mut nodes = self.nodes.write().await;
-        nodes.remove(node_id);
+        let Some(node) = nodes.get_mut(node_id) else {
+            return;
+        };
+        update(node);
+
+        // A silent node stays offline whatever the probes say
+        if node.status != NodeStatus::Offline {
+            let status = probed_status(node);
+            let reason = if status == NodeStatus::Online {
+                "reachable"
+            } else {
+                "no ping or API response"
+            };
+            self.transition(node, status, reason);
+        }
     }
 
     pub async fn get_node(&self, node_id: &str) -> Option<NodeInfo> {
@@ -73,17 +226,339 @@ impl PeerRegistry {
         nodes.values().cloned().collect()
     }
 
-    pub async fn cleanup_stale_nodes(&self, timeout_seconds: u64) {
+    /// Merge peer summaries gossiped by `from`; returns the IDs of newly learned nodes
+    ///
+    /// Nodes we hear from directly are left alone. A gossiped entry is only
+    /// refreshed by a newer sighting, and peers only gossip nodes they hear
+    /// from directly, so it expires once nobody has heard from the node itself.
+    pub async fn merge_gossip(&self, from: &str, peers: Vec<gossip::PeerSummary>) -> Vec<String> {
         let mut nodes = self.nodes.write().await;
-        let now = chrono::Utc::now();
+        let mut learned = Vec::new();
+
+        for peer in peers {
+            if peer.id == self.local_node_id || peer.id == from {
+                continue;
+            }
+
+            let newer = match nodes.get(&peer.id) {
+                None => true,
+                Some(existing) if existing.learned_from.is_none() => false,
+                Some(existing) => is_newer(&peer.last_seen, &existing.last_seen),
+            };
+            if !newer || (!nodes.contains_key(&peer.id) && !self.make_room(&mut nodes)) {
+                continue;
+            }
+
+            let mut node = NodeInfo {
+                id: peer.id.clone(),
+                hostname: peer.hostname,
+                addresses: peer.addresses,
+                port: peer.port,
+                status: NodeStatus::Online,
+                last_seen: peer.last_seen,
+                received_at: Utc::now(),
+                clock_offset_ms: None,
+                clock_skewed: false,
+                last_ping_ok: None,
+                last_api_ok: None,
+                interfaces: Vec::new(),
+                discovered_via: "gossip".to_string(),
+                wireguard_path: None,
+                learned_from: Some(from.to_string()),
+            };
+
+            match nodes.get(&peer.id) {
+                None => {
+                    self.publish(ServerMessage::NodeDiscovered { node: node.clone() });
+                    learned.push(peer.id.clone());
+                }
+                Some(existing) => {
+                    node.last_ping_ok = existing.last_ping_ok;
+                    node.last_api_ok = existing.last_api_ok;
+                    node.status = existing.status;
+                    let status = probed_status(&node);
+                    self.transition(&mut node, status, "gossip");
+                }
+            }
+            nodes.insert(peer.id, node);
+        }
+
+        learned
+    }
+
+    /// Mark nodes silent for `offline_after` seconds offline, and drop those
+    /// silent for `timeout_seconds`. Silence is measured by our own clock so a
+    /// peer whose clock runs behind isn't evicted early.
+    pub async fn update_statuses(&self, offline_after: u64, timeout_seconds: u64) {
+        let mut nodes = self.nodes.write().await;
+        let now = Utc::now();
 
         nodes.retain(|_, node| {
-            if let Ok(last_seen) = chrono::DateTime::parse_from_rfc3339(&node.last_seen) {
-                let elapsed = now.signed_duration_since(last_seen.with_timezone(&chrono::Utc));
-                elapsed.num_seconds() < timeout_seconds as i64
+            let silent_for = now.signed_duration_since(node.received_at).num_seconds();
+            if silent_for >= timeout_seconds as i64 {
+                self.transition(node, NodeStatus::Offline, "timeout");
+                false
             } else {
+                if silent_for >= offline_after as i64 {
+                    self.transition(node, NodeStatus::Offline, "silent");
+                }
+                true
+            }
+        });
+    }
+
+    pub async fn stats(&self) -> RegistryStats {
+        RegistryStats {
+            nodes: self.nodes.read().await.len(),
+            max_nodes: self.max_nodes,
+            evicted: self.evicted.load(Ordering::Relaxed),
+            refused: self.refused.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Free a slot for a new node if the registry is full; returns false if
+    /// there is none to give
+    ///
+    /// Only nodes that are offline or known just through gossip are evicted,
+    /// the one heard of longest ago first, so a flood can't push out live
+    /// peers we hear from directly.
+    fn make_room(&self, nodes: &mut HashMap<String, NodeInfo>) -> bool {
+        if nodes.len() < self.max_nodes {
+            return true;
+        }
+
+        let stalest = nodes
+            .values()
+            .filter(|n| n.status == NodeStatus::Offline || n.learned_from.is_some())
+            .min_by_key(|n| n.received_at)
+            .map(|n| n.id.clone());
+
+        match stalest.and_then(|id| nodes.remove(&id)) {
+            Some(mut node) => {
+                self.evicted.fetch_add(1, Ordering::Relaxed);
+                self.transition(&mut node, NodeStatus::Offline, "evicted");
+                true
+            }
+            None => {
+                self.refused.fetch_add(1, Ordering::Relaxed);
                 false
             }
+        }
+    }
+
+    /// Move a node to `status`, telling WebSocket clients if it changed
+    fn transition(&self, node: &mut NodeInfo, status: NodeStatus, reason: &str) {
+        if node.status == status {
+            return;
+        }
+        tracing::info!("Node {} is now {:?} ({})", node.id, status, reason);
+        node.status = status;
+        self.publish(ServerMessage::NodeStatusChanged {
+            node_id: node.id.clone(),
+            status,
+            reason: reason.to_string(),
         });
     }
+
+    fn publish(&self, msg: ServerMessage) {
+        // No subscribers just means nobody is watching
+        let _ = self.updates.send(msg);
+    }
+}
+
+/// Status of a node that is still being heard from, going by the probes
+fn probed_status(node: &NodeInfo) -> NodeStatus {
+    let probed = node.last_ping_ok.is_some() || node.last_api_ok.is_some();
+    let answered = node.last_ping_ok == Some(true) || node.last_api_ok == Some(true);
+    if probed && !answered {
+        NodeStatus::Unreachable
+    } else {
+        NodeStatus::Online
+    }
+}
+
+/// Offset of a sender's clock from ours in milliseconds, from the timestamp it
+/// put in a message and when that message arrived
+pub fn clock_offset_ms(sent: &str, received_at: DateTime<Utc>) -> Option<i64> {
+    let sent = DateTime::parse_from_rfc3339(sent).ok()?;
+    Some(
+        sent.with_timezone(&Utc)
+            .signed_duration_since(received_at)
+            .num_milliseconds(),
+    )
+}
+
+/// Whether an offset is large enough to break TLS or Kerberos on the path
+pub fn is_clock_skewed(offset_ms: Option<i64>) -> bool {
+    offset_ms.is_some_and(|offset| offset.abs() > MAX_CLOCK_SKEW_SECS * 1000)
+}
+
+/// Compare two RFC 3339 timestamps, treating unparseable ones as oldest
+fn is_newer(candidate: &str, current: &str) -> bool {
+    let parse = |t: &str| chrono::DateTime::parse_from_rfc3339(t).ok();
+    match (parse(candidate), parse(current)) {
+        (Some(candidate), Some(current)) => candidate > current,
+        (Some(_), None) => true,
+        (None, Some(_)) | (None, None) => false,
+    }
+}
+
+/// Node factory shared by the discovery test modules
+#[cfg(test)]
+pub mod test_support {
+    use super::{NodeInfo, NodeStatus};
+    use chrono::{DateTime, Utc};
+
+    pub fn node(id: &str, last_seen: &str, received_at: DateTime<Utc>) -> NodeInfo {
+        NodeInfo {
+            id: id.to_string(),
+            hostname: id.to_string(),
+            addresses: vec!["10.8.0.15".parse().unwrap()],
+            port: 8080,
+            status: NodeStatus::Online,
+            last_seen: last_seen.to_string(),
+            received_at,
+            clock_offset_ms: None,
+            clock_skewed: false,
+            last_ping_ok: None,
+            last_api_ok: None,
+            interfaces: Vec::new(),
+            discovered_via: "broadcast".to_string(),
+            wireguard_path: None,
+            learned_from: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::node;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cleanup_uses_receive_time() {
+        let registry = PeerRegistry::new("local".to_string());
+        let now = Utc::now();
+
+        // Clock two minutes behind, but we just heard from it
+        let behind = (now - chrono::Duration::minutes(2)).to_rfc3339();
+        registry.add_node(node("behind", &behind, now)).await;
+        // Clock fine, but silent for too long
+        let silent_since = now - chrono::Duration::minutes(5);
+        registry
+            .add_node(node("silent", &silent_since.to_rfc3339(), silent_since))
+            .await;
+
+        registry.update_statuses(60, 90).await;
+        assert!(registry.get_node("behind").await.is_some());
+        assert!(registry.get_node("silent").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_status_lifecycle() {
+        let registry = PeerRegistry::new("local".to_string());
+        let mut updates = registry.update_sender().subscribe();
+        let now = Utc::now();
+        let last_seen = now.to_rfc3339();
+
+        assert!(registry.add_node(node("k", &last_seen, now)).await);
+        assert!(matches!(
+            updates.try_recv(),
+            Ok(ServerMessage::NodeDiscovered { .. })
+        ));
+
+        // Ping failing alone isn't enough while the API still answers
+        registry.record_api("k", true).await;
+        registry.record_ping("k", false).await;
+        assert_eq!(
+            registry.get_node("k").await.unwrap().status,
+            NodeStatus::Online
+        );
+        assert!(updates.try_recv().is_err());
+
+        registry.record_api("k", false).await;
+        assert_eq!(
+            registry.get_node("k").await.unwrap().status,
+            NodeStatus::Unreachable
+        );
+        assert!(matches!(
+            updates.try_recv(),
+            Ok(ServerMessage::NodeStatusChanged {
+                status: NodeStatus::Unreachable,
+                ..
+            })
+        ));
+
+        // Still announcing, still unreachable
+        registry.add_node(node("k", &last_seen, now)).await;
+        assert_eq!(
+            registry.get_node("k").await.unwrap().status,
+            NodeStatus::Unreachable
+        );
+
+        // Falls silent
+        let earlier = now - chrono::Duration::seconds(70);
+        registry.add_node(node("k", &last_seen, earlier)).await;
+        registry.update_statuses(60, 90).await;
+        assert_eq!(
+            registry.get_node("k").await.unwrap().status,
+            NodeStatus::Offline
+        );
+        assert!(matches!(
+            updates.try_recv(),
+            Ok(ServerMessage::NodeStatusChanged {
+                status: NodeStatus::Offline,
+                ..
+            })
+        ));
+
+        // Speaks up and answers again
+        registry.add_node(node("k", &last_seen, now)).await;
+        registry.record_ping("k", true).await;
+        assert_eq!(
+            registry.get_node("k").await.unwrap().status,
+            NodeStatus::Online
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registry_cap() {
+        let registry = PeerRegistry::new("local".to_string()).with_max_nodes(2);
+        let now = Utc::now();
+        let last_seen = now.to_rfc3339();
+
+        assert!(registry.add_node(node("a", &last_seen, now)).await);
+        let mut gossiped = node("b", &last_seen, now - chrono::Duration::seconds(10));
+        gossiped.learned_from = Some("a".to_string());
+        assert!(registry.add_node(gossiped).await);
+
+        // The gossip-learned node makes way for one heard directly
+        assert!(registry.add_node(node("c", &last_seen, now)).await);
+        assert!(registry.get_node("b").await.is_none());
+
+        // Only live, directly heard nodes left: newcomers are refused
+        assert!(!registry.add_node(node("d", &last_seen, now)).await);
+        assert!(registry.get_node("d").await.is_none());
+
+        let stats = registry.stats().await;
+        assert_eq!((stats.nodes, stats.evicted, stats.refused), (2, 1, 1));
+    }
+
+    #[test]
+    fn test_clock_offset() {
+        let received_at = DateTime::parse_from_rfc3339("2025-11-22T10:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let offset = clock_offset_ms("2025-11-22T10:28:00Z", received_at);
+        assert_eq!(offset, Some(-120_000));
+        assert!(!is_clock_skewed(offset));
+
+        let offset = clock_offset_ms("2025-11-22T10:37:30+00:00", received_at);
+        assert_eq!(offset, Some(450_000));
+        assert!(is_clock_skewed(offset));
+
+        assert_eq!(clock_offset_ms("yesterday", received_at), None);
+        assert!(!is_clock_skewed(None));
+    }
 }
@@ -0,0 +1,331 @@
+// OpenVPN server clients from the status file or management interface
+// A server keeps a list of connected clients: in the file named by its
+// `status` option (in any status-version), and as the answer to `status 3` on
+// its management interface. A client's virtual addresses, from the client list
+// and the routing table, are its tunnel addresses; iroute subnets behind it
+// are not. Clients only appear while connected, so they are all online
+
+use chrono::{TimeZone, Utc};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::vpn_peers::VpnPeer;
+use super::wireguard::parse_endpoint;
+
+/// Where distribution packages and the sample server config write the status
+/// file, tried in order when `openvpn_status` isn't set
+const DEFAULT_STATUS_FILES: &[&str] = &[
+    "/run/openvpn-server/status-server.log",
+    "/var/log/openvpn/openvpn-status.log",
+    "/etc/openvpn/openvpn-status.log",
+];
+
+/// The management interface answers at once; it only stays silent when it
+/// waits for a password, which isn't supported
+const MANAGEMENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CLIENT_LIST: &str = "CLIENT_LIST";
+const ROUTING_TABLE: &str = "ROUTING_TABLE";
+
+/// A client list or routing table row, by column name
+type Row<'a> = HashMap<&'a str, &'a str>;
+
+/// Read the server's clients, from the management interface if one is
+/// configured and otherwise from the status file
+pub async fn read_peers(
+    status_file: Option<&Path>,
+    management: Option<&str>,
+) -> Result<Vec<VpnPeer>, String> {
+    let status = match (management, status_file) {
+        (Some(address), _) => read_management(address).await?,
+        (None, Some(path)) => read_status_file(path).await?,
+        (None, None) => {
+            let path = DEFAULT_STATUS_FILES
+                .iter()
+                .map(Path::new)
+                .find(|path| path.exists())
+                .ok_or_else(|| {
+                    "No OpenVPN status file found; set openvpn_status or openvpn_management"
+                        .to_string()
+                })?;
+            read_status_file(path).await?
+        }
+    };
+    parse_status(&status)
+}
+
+async fn read_status_file(path: &Path) -> Result<String, String> {
+    tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+/// Ask the management interface ("host:port", or a unix socket path) for `status 3`
+async fn read_management(address: &str) -> Result<String, String> {
+    tokio::time::timeout(MANAGEMENT_TIMEOUT, connect_management(address))
+        .await
+        .map_err(|_| {
+            format!(
+                "OpenVPN management interface at {} didn't answer (password protected?)",
+                address
+            )
+        })?
+}
+
+async fn connect_management(address: &str) -> Result<String, String> {
+    let error = |e: std::io::Error| {
+        format!(
+            "Failed to connect to OpenVPN management interface at {}: {}",
+            address, e
+        )
+    };
+
+    #[cfg(unix)]
+    if address.starts_with('/') {
+        let stream = tokio::net::UnixStream::connect(address)
+            .await
+            .map_err(error)?;
+        return query_management(stream).await;
+    }
+
+    let stream = TcpStream::connect(address).await.map_err(error)?;
+    query_management(stream).await
+}
+
+/// Send `status 3` and collect the reply up to END
+///
+/// Lines starting with '>' are notifications (the greeting among them) that
+/// can arrive at any time, and are skipped.
+async fn query_management<S>(stream: S) -> Result<String, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let io_error = |e: std::io::Error| format!("OpenVPN management interface: {}", e);
+    let mut stream = BufReader::new(stream);
+    stream
+        .get_mut()
+        .write_all(b"status 3\n")
+        .await
+        .map_err(io_error)?;
+
+    let mut status = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await.map_err(io_error)? == 0 {
+            return Err("OpenVPN management interface closed the connection".to_string());
+        }
+        let line = line.trim_end();
+        if let Some(error) = line.strip_prefix("ERROR:") {
+            return Err(format!("OpenVPN management interface: {}", error.trim()));
+        }
+        if line == "END" {
+            break;
+        }
+        if !line.starts_with('>') {
+            status.push_str(line);
+            status.push('\n');
+        }
+    }
+
+    // Leave politely; the answer is complete either way
+    let _ = stream.get_mut().write_all(b"quit\n").await;
+    Ok(status)
+}
+
+/// Parse an OpenVPN server status
+///
+/// Version 1 has titled sections ("OpenVPN CLIENT LIST", "ROUTING TABLE"),
+/// each starting with a line of column names. Versions 2 and 3 (comma and tab
+/// separated) start every line with its kind and name the columns of each
+/// kind in a HEADER line. Columns are looked up by name, since OpenVPN
+/// releases keep adding them.
+fn parse_status(status: &str) -> Result<Vec<VpnPeer>, String> {
+    let mut columns: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut section: Option<&str> = None;
+    let mut clients: Vec<Row> = Vec::new();
+    let mut routes: Vec<Row> = Vec::new();
+
+    for line in status.lines() {
+        let line = line.trim_end_matches('\r');
+        let separator = if line.contains('\t') { '\t' } else { ',' };
+        let fields: Vec<&str> = line.split(separator).collect();
+
+        let (kind, values) = match fields.as_slice() {
+            ["OpenVPN CLIENT LIST"] => {
+                section = Some(CLIENT_LIST);
+                continue;
+            }
+            ["ROUTING TABLE"] => {
+                section = Some(ROUTING_TABLE);
+                continue;
+            }
+            ["GLOBAL STATS"] | ["END"] => {
+                section = None;
+                continue;
+            }
+            ["HEADER", kind, names @ ..] => {
+                columns.insert(kind, names.to_vec());
+                continue;
+            }
+            [kind @ (CLIENT_LIST | ROUTING_TABLE), values @ ..] => (*kind, values),
+            ["Updated", ..] => continue,
+            values => match section {
+                // The first line of a version 1 section names its columns
+                Some(kind) if !columns.contains_key(kind) => {
+                    columns.insert(kind, values.to_vec());
+                    continue;
+                }
+                Some(kind) => (kind, values),
+                None => continue,
+            },
+        };
+
+        let Some(names) = columns.get(kind) else {
+            continue;
+        };
+        let row: Row = names.iter().copied().zip(values.iter().copied()).collect();
+        if kind == CLIENT_LIST {
+            clients.push(row);
+        } else {
+            routes.push(row);
+        }
+    }
+
+    if !columns.contains_key(CLIENT_LIST) {
+        return Err("No client list in the OpenVPN status (only servers keep one)".to_string());
+    }
+
+    Ok(clients
+        .iter()
+        .map(|client| {
+            let field = |name: &str| client.get(name).copied().unwrap_or_default();
+            let real_address = field("Real Address");
+            let client_routes: Vec<&Row> = routes
+                .iter()
+                .filter(|route| route.get("Real Address") == Some(&real_address))
+                .collect();
+
+            let mut tunnel_addresses: Vec<IpAddr> = Vec::new();
+            let virtual_addresses = [field("Virtual Address"), field("Virtual IPv6 Address")];
+            let routed = client_routes
+                .iter()
+                .filter_map(|route| route.get("Virtual Address").copied());
+            // Subnets, MAC addresses (tap) and cached entries don't parse
+            for ip in virtual_addresses
+                .into_iter()
+                .chain(routed)
+                .filter_map(|address| address.parse::<IpAddr>().ok())
+            {
+                if !tunnel_addresses.contains(&ip) {
+                    tunnel_addresses.push(ip);
+                }
+            }
+
+            let last_ref = client_routes
+                .iter()
+                .filter_map(|route| route.get("Last Ref (time_t)")?.parse::<i64>().ok())
+                .max();
+
+            VpnPeer {
+                id: field("Common Name").to_string(),
+                hostname: None,
+                tunnel_addresses,
+                endpoint: parse_real_address(real_address),
+                relay: None,
+                online: true,
+                last_seen: last_ref
+                    .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+                    .map(|at| at.to_rfc3339()),
+                latency_ms: None,
+                rx_bytes: field("Bytes Received").parse().ok(),
+                tx_bytes: field("Bytes Sent").parse().ok(),
+            }
+        })
+        .collect())
+}
+
+/// Parse a client's real address, which newer servers prefix with the
+/// protocol ("udp4:198.51.100.22:1194")
+fn parse_real_address(address: &str) -> Option<SocketAddr> {
+    let address = match address.split_once(':') {
+        Some((protocol, rest)) if protocol.starts_with("udp") || protocol.starts_with("tcp") => {
+            rest
+        }
+        _ => address,
+    };
+    parse_endpoint(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS_V1: &str = include_str!("fixtures/openvpn_status_v1.txt");
+    const STATUS_V2: &str = include_str!("fixtures/openvpn_status_v2.txt");
+    const STATUS_V3: &str = include_str!("fixtures/openvpn_status_v3.txt");
+
+    fn addresses(peer: &VpnPeer) -> Vec<String> {
+        peer.tunnel_addresses
+            .iter()
+            .map(|ip| ip.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_status_v1() {
+        let peers = parse_status(STATUS_V1).unwrap();
+        let [alice, runner] = peers.as_slice() else {
+            panic!("expected two clients");
+        };
+
+        assert_eq!(alice.id, "alice-laptop");
+        assert_eq!(addresses(alice), vec!["10.8.0.6"]);
+        assert_eq!(alice.endpoint, parse_endpoint("203.0.113.7:51234"));
+        assert_eq!((alice.rx_bytes, alice.tx_bytes), (Some(1024), Some(2048)));
+        // Version 1 only has local times, which aren't trusted
+        assert_eq!(alice.last_seen, None);
+
+        // The iroute subnet behind the runner is not one of its addresses
+        assert_eq!(addresses(runner), vec!["10.8.0.10"]);
+    }
+
+    #[test]
+    fn test_parse_status_v2_v3() {
+        for status in [STATUS_V2, STATUS_V3] {
+            let peers = parse_status(status).unwrap();
+            let [alice, runner] = peers.as_slice() else {
+                panic!("expected two clients");
+            };
+
+            assert_eq!(addresses(alice), vec!["10.8.0.6", "fd00:8::1000"]);
+            assert_eq!(
+                alice.last_seen.as_deref(),
+                Some("2025-11-22T10:29:50+00:00")
+            );
+            assert_eq!(runner.endpoint, parse_endpoint("198.51.100.22:1194"));
+            assert_eq!(addresses(runner), vec!["10.8.0.10"]);
+        }
+
+        let client_mode = "OpenVPN STATISTICS\nUpdated,2025-11-22 10:30:00\nEND\n";
+        assert!(parse_status(client_mode).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_query_management() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let reply = format!(
+            ">INFO:OpenVPN Management Interface Version 5 -- type 'help' for more info\n{}",
+            STATUS_V3
+        );
+        server.write_all(reply.as_bytes()).await.unwrap();
+
+        let status = query_management(client).await.unwrap();
+        assert!(!status.contains(">INFO"));
+        assert_eq!(parse_status(&status).unwrap().len(), 2);
+    }
+}
@@ -4,19 +4,20 @@ use std::collections::VecDeque;
 use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::Duration;
-use surge_ping::{Client, Config, PingIdentifier, PingSequence};
+use surge_ping::{Client, PingIdentifier, PingSequence};
 use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
 use tokio::time::interval;
 
-use super::PeerRegistry;
+use super::{interfaces, PeerRegistry};
 use crate::api::rest::AppState;
 use crate::api::websocket::{Connection, ServerMessage};
+use crate::config::Config;
 
 const MAX_LATENCY_SAMPLES: usize = 100;
-const PING_INTERVAL_SECS: u64 = 60;
 const PING_TIMEOUT_SECS: u64 = 5;
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct LatencyData {
     pub node_id: String,
     pub address: IpAddr,
@@ -31,6 +32,38 @@ pub struct LatencyHistory {
     pub avg_latency_ms: Option<f64>,
     pub min_latency_ms: Option<f64>,
     pub max_latency_ms: Option<f64>,
+    pub loss_percent: Option<f64>,
+}
+
+/// Latency statistics for one (from, to) pair
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PairLatency {
+    pub from: String,
+    pub to: String,
+    pub min_latency_ms: Option<f64>,
+    pub avg_latency_ms: Option<f64>,
+    pub max_latency_ms: Option<f64>,
+    pub loss_percent: Option<f64>,
+    pub last_latency_ms: Option<f64>,
+    pub samples: usize,
+}
+
+/// A node's view of its own latency to every peer it pings
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LatencyReport {
+    pub node_id: String,
+    pub pairs: Vec<PairLatency>,
+    pub timestamp: String,
+}
+
+/// Full-mesh latency; `matrix[i][j]` is measured from `nodes[i]` to `nodes[j]`
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LatencyMatrix {
+    pub nodes: Vec<String>,
+    pub matrix: Vec<Vec<Option<PairLatency>>>,
+    /// Nodes whose latency report could not be fetched
+    pub unavailable: Vec<String>,
+    pub timestamp: String,
 }
 
 impl LatencyHistory {
@@ -41,6 +74,7 @@ impl LatencyHistory {
             avg_latency_ms: None,
             min_latency_ms: None,
             max_latency_ms: None,
+            loss_percent: None,
         }
     }
 
@@ -52,9 +86,29 @@ impl LatencyHistory {
         self.update_stats();
     }
 
+    pub fn pair_latency(&self, from: &str) -> PairLatency {
+        PairLatency {
+            from: from.to_string(),
+            to: self.node_id.clone(),
+            min_latency_ms: self.min_latency_ms,
+            avg_latency_ms: self.avg_latency_ms,
+            max_latency_ms: self.max_latency_ms,
+            loss_percent: self.loss_percent,
+            last_latency_ms: self.samples.back().and_then(|s| s.latency_ms),
+            samples: self.samples.len(),
+        }
+    }
+
     fn update_stats(&mut self) {
         let latencies: Vec<f64> = self.samples.iter().filter_map(|s| s.latency_ms).collect();
 
+        self.loss_percent = if self.samples.is_empty() {
+            None
+        } else {
+            let lost = self.samples.len() - latencies.len();
+            Some(lost as f64 * 100.0 / self.samples.len() as f64)
+        };
+
         if latencies.is_empty() {
             self.avg_latency_ms = None;
             self.min_latency_ms = None;
@@ -76,33 +130,45 @@ impl LatencyHistory {
 }
 
 pub struct PingService {
+    config: Arc<Config>,
     peer_registry: Arc<PeerRegistry>,
     latency_histories: Arc<RwLock<std::collections::HashMap<String, LatencyHistory>>>,
     state: Arc<AppState>,
 }
 
 impl PingService {
-    pub fn new(peer_registry: Arc<PeerRegistry>, state: Arc<AppState>) -> Self {
+    pub fn new(
+        config: Arc<Config>,
+        peer_registry: Arc<PeerRegistry>,
+        state: Arc<AppState>,
+    ) -> Self {
         PingService {
+            config,
             peer_registry,
-            latency_histories: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            latency_histories: state.latency_histories.clone(),
             state,
         }
     }
 
-    pub async fn start_ping_task(&self) {
+    pub async fn start_ping_task(&self) -> JoinHandle<()> {
         let peer_registry = self.peer_registry.clone();
         let latency_histories = self.latency_histories.clone();
         let state = self.state.clone();
+        let period = Duration::from_secs(self.config.ping_interval);
+        let mut shutdown = state.shutdown_signal();
 
         tokio::spawn(async move {
-            let mut ping_interval = interval(Duration::from_secs(PING_INTERVAL_SECS));
+            let mut ping_interval = interval(period);
 
             loop {
-                ping_interval.tick().await;
+                tokio::select! {
+                    _ = ping_interval.tick() => {}
+                    _ = shutdown.wait_for(|stopping| *stopping) => break,
+                }
 
                 let nodes = peer_registry.get_all_nodes().await;
                 let local_id = peer_registry.local_node_id().to_string();
+                let local_addresses = interfaces::local_addresses();
 
                 for node in nodes {
                     // Don't ping ourselves
@@ -110,15 +176,20 @@ impl PingService {
                         continue;
                     }
 
-                    // Ping the first address
-                    if let Some(&address) = node.addresses.first() {
+                    // Ping the address we'd route to it on
+                    if let Some(address) = node.preferred_address(&local_addresses) {
                         let node_id = node.id.clone();
+                        let local_id_clone = local_id.clone();
                         let latency_histories_clone = latency_histories.clone();
                         let state_clone = state.clone();
 
                         // Spawn a separate task for each ping to avoid blocking
                         tokio::spawn(async move {
                             let latency = Self::ping_address(address).await;
+                            state_clone
+                                .peer_registry
+                                .record_ping(&node_id, latency.is_some())
+                                .await;
                             let timestamp = chrono::Utc::now().to_rfc3339();
 
                             let data = LatencyData {
@@ -138,7 +209,7 @@ impl PingService {
                             // Send WebSocket update
                             if let Some(latency_val) = latency {
                                 let connections = vec![Connection {
-                                    from: "local".to_string(),
+                                    from: local_id_clone,
                                     to: node_id,
                                     latency_ms: latency_val.round() as u32,
                                     timestamp: data.timestamp,
@@ -160,13 +231,51 @@ impl PingService {
                     }
                 }
             }
-        });
+        })
+    }
+
+    /// Periodically gather every peer's latency report and broadcast the
+    /// remote pairs, so the UI can draw edges between remote nodes too
+    pub async fn start_matrix_task(&self) -> JoinHandle<()> {
+        let state = self.state.clone();
+        let period = Duration::from_secs(self.config.ping_interval);
+        let mut shutdown = state.shutdown_signal();
+
+        tokio::spawn(async move {
+            let mut matrix_interval = interval(period);
+
+            loop {
+                tokio::select! {
+                    _ = matrix_interval.tick() => {}
+                    _ = shutdown.wait_for(|stopping| *stopping) => break,
+                }
+
+                let local_id = state.peer_registry.local_node_id().to_string();
+                let (reports, _) = collect_remote_reports(&state).await;
 
-        tracing::info!("Ping service started (interval: {}s)", PING_INTERVAL_SECS);
+                let connections: Vec<Connection> = reports
+                    .iter()
+                    .flat_map(|report| report.pairs.iter())
+                    .filter(|pair| pair.to != local_id)
+                    .filter_map(|pair| {
+                        pair.last_latency_ms.map(|latency| Connection {
+                            from: pair.from.clone(),
+                            to: pair.to.clone(),
+                            latency_ms: latency.round() as u32,
+                            timestamp: chrono::Utc::now().to_rfc3339(),
+                        })
+                    })
+                    .collect();
+
+                if !connections.is_empty() {
+                    state.send_update(ServerMessage::LatencyUpdate { connections });
+                }
+            }
+        })
     }
 
-    async fn ping_address(address: IpAddr) -> Option<f64> {
-        let config = Config::default();
+    pub async fn ping_address(address: IpAddr) -> Option<f64> {
+        let config = surge_ping::Config::default();
 
         let client = match Client::new(&config) {
             Ok(c) => c,
@@ -198,3 +307,208 @@ impl PingService {
         }
     }
 }
+
+/// This node's latency to each peer it pings
+pub async fn local_latency_report(state: &AppState) -> LatencyReport {
+    let local_id = state.peer_registry.local_node_id().to_string();
+    let histories = state.latency_histories.read().await;
+
+    let mut pairs: Vec<PairLatency> = histories
+        .values()
+        .map(|history| history.pair_latency(&local_id))
+        .collect();
+    pairs.sort_by(|a, b| a.to.cmp(&b.to));
+
+    LatencyReport {
+        node_id: local_id,
+        pairs,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+/// Fetch the latency report of every peer, returning the ids that failed
+async fn collect_remote_reports(state: &AppState) -> (Vec<LatencyReport>, Vec<String>) {
+    let local_id = state.peer_registry.local_node_id().to_string();
+    let peers: Vec<_> = state
+        .peer_registry
+        .get_all_nodes()
+        .await
+        .into_iter()
+        .filter(|node| node.id != local_id)
+        .collect();
+
+    let results = futures::future::join_all(
+        peers
+            .iter()
+            .map(|node| state.node_client.fetch_latency_report(node)),
+    )
+    .await;
+
+    let mut reports = Vec::new();
+    let mut unavailable = Vec::new();
+    for (node, result) in peers.into_iter().zip(results) {
+        state
+            .peer_registry
+            .record_api(&node.id, result.is_ok())
+            .await;
+        match result {
+            Ok(report) => reports.push(own_measurements(report, &node.id)),
+            Err(e) => {
+                tracing::debug!("No latency report from {}: {}", node.hostname, e);
+                unavailable.push(node.id);
+            }
+        }
+    }
+
+    (reports, unavailable)
+}
+
+/// Keep only what `node_id` measured itself
+///
+/// A node's report can claim pairs (or a node ID) for any node, so everything
+/// not measured from the node it was fetched from is dropped.
+fn own_measurements(mut report: LatencyReport, node_id: &str) -> LatencyReport {
+    let claimed = report.pairs.len();
+    report.pairs.retain(|pair| pair.from == node_id);
+    if report.pairs.len() != claimed || report.node_id != node_id {
+        tracing::debug!(
+            "Ignoring {} latency pairs that {} reported for other nodes",
+            claimed - report.pairs.len(),
+            node_id
+        );
+    }
+    report.node_id = node_id.to_string();
+    report
+}
+
+/// Gather the local and remote reports into a full-mesh matrix
+pub async fn gather_latency_matrix(state: &AppState) -> LatencyMatrix {
+    let mut reports = vec![local_latency_report(state).await];
+    let (remote, unavailable) = collect_remote_reports(state).await;
+    reports.extend(remote);
+
+    let mut nodes = vec![state.peer_registry.local_node_id().to_string()];
+    let mut peers: Vec<String> = state
+        .peer_registry
+        .get_all_nodes()
+        .await
+        .into_iter()
+        .map(|node| node.id)
+        .filter(|id| !nodes.contains(id))
+        .collect();
+    peers.sort();
+    nodes.extend(peers);
+
+    build_latency_matrix(nodes, &reports, unavailable)
+}
+
+/// Arrange per-node reports into an N×N matrix over `nodes`
+pub fn build_latency_matrix(
+    nodes: Vec<String>,
+    reports: &[LatencyReport],
+    unavailable: Vec<String>,
+) -> LatencyMatrix {
+    let matrix = nodes
+        .iter()
+        .map(|from| {
+            let report = reports.iter().find(|r| &r.node_id == from);
+            nodes
+                .iter()
+                .map(|to| {
+                    report
+                        .and_then(|r| r.pairs.iter().find(|p| &p.to == to))
+                        .cloned()
+                })
+                .collect()
+        })
+        .collect();
+
+    LatencyMatrix {
+        nodes,
+        matrix,
+        unavailable,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(latency_ms: Option<f64>) -> LatencyData {
+        LatencyData {
+            node_id: "b".to_string(),
+            address: "10.0.0.2".parse().unwrap(),
+            latency_ms,
+            timestamp: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_history_stats_with_loss() {
+        let mut history = LatencyHistory::new("b".to_string());
+        history.add_sample(sample(Some(10.0)));
+        history.add_sample(sample(None));
+        history.add_sample(sample(Some(30.0)));
+        history.add_sample(sample(None));
+
+        let pair = history.pair_latency("a");
+        assert_eq!(pair.from, "a");
+        assert_eq!(pair.avg_latency_ms, Some(20.0));
+        assert_eq!(pair.min_latency_ms, Some(10.0));
+        assert_eq!(pair.max_latency_ms, Some(30.0));
+        assert_eq!(pair.loss_percent, Some(50.0));
+        assert_eq!(pair.last_latency_ms, None);
+        assert_eq!(pair.samples, 4);
+    }
+
+    #[test]
+    fn test_build_latency_matrix() {
+        let mut history = LatencyHistory::new("b".to_string());
+        history.add_sample(sample(Some(12.0)));
+        let reports = vec![
+            LatencyReport {
+                node_id: "a".to_string(),
+                pairs: vec![history.pair_latency("a")],
+                timestamp: String::new(),
+            },
+            LatencyReport {
+                node_id: "b".to_string(),
+                pairs: vec![],
+                timestamp: String::new(),
+            },
+        ];
+
+        let nodes = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let matrix = build_latency_matrix(nodes, &reports, vec!["c".to_string()]);
+
+        assert_eq!(matrix.matrix.len(), 3);
+        let a_to_b = matrix
+            .matrix
+            .first()
+            .and_then(|row| row.get(1))
+            .cloned()
+            .flatten();
+        assert_eq!(a_to_b.and_then(|p| p.avg_latency_ms), Some(12.0));
+        assert!(matrix
+            .matrix
+            .get(1)
+            .is_some_and(|row| row.iter().all(|cell| cell.is_none())));
+    }
+
+    #[test]
+    fn test_own_measurements() {
+        let history = LatencyHistory::new("c".to_string());
+        let report = LatencyReport {
+            node_id: "a".to_string(),
+            pairs: vec![history.pair_latency("b"), history.pair_latency("a")],
+            timestamp: String::new(),
+        };
+
+        // Fetched from b: only b's own pair survives, under b's ID
+        let report = own_measurements(report, "b");
+        assert_eq!(report.node_id, "b");
+        let from: Vec<&str> = report.pairs.iter().map(|p| p.from.as_str()).collect();
+        assert_eq!(from, vec!["b"]);
+    }
+}
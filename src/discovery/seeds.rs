@@ -0,0 +1,228 @@
+// Static seed peers
+// For sites multicast can't reach, nodes can be pointed at a list of seeds
+// (host:port, or a DNS name resolving to several nodes). Each seed is asked
+// for its node list over HTTP; the seed itself is registered as a static peer
+// and the nodes it knows are merged in as gossip. Announcements also go to the
+// seeds by unicast so they learn about us in return
+
+use futures::future::BoxFuture;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+use tokio::time::Duration;
+
+use super::source::{self, DiscoverySource};
+use super::{NodeInfo, NodeStatus, PeerRegistry};
+use crate::api::client::NodeClient;
+use crate::config::Config;
+
+/// API port assumed when a seed doesn't give one
+const DEFAULT_SEED_PORT: u16 = 8080;
+
+pub struct StaticSource {
+    config: Arc<Config>,
+    node_client: Arc<NodeClient>,
+    /// Addresses the seeds resolved to last time, announced to by unicast so
+    /// the seeds learn about us too
+    resolved: RwLock<Vec<IpAddr>>,
+}
+
+impl StaticSource {
+    pub fn new(config: Arc<Config>, node_client: Arc<NodeClient>) -> Self {
+        StaticSource {
+            config,
+            node_client,
+            resolved: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl DiscoverySource for StaticSource {
+    fn name(&self) -> &'static str {
+        "static"
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        // Seeds are polled as often as nodes announce themselves
+        Some(Duration::from_secs(self.config.discovery_interval))
+    }
+
+    fn discover<'a>(
+        &'a self,
+        peer_registry: &'a PeerRegistry,
+    ) -> BoxFuture<'a, Result<Vec<NodeInfo>, String>> {
+        Box::pin(async move {
+            let mut nodes = Vec::new();
+            let mut resolved = Vec::new();
+            let mut errors = Vec::new();
+
+            for seed in &self.config.seed_peers {
+                let addresses = match resolve_seed(seed).await {
+                    Ok(addresses) => addresses,
+                    Err(e) => {
+                        tracing::warn!("Could not resolve seed peer {}: {}", seed, e);
+                        errors.push(format!("{}: {}", seed, e));
+                        continue;
+                    }
+                };
+
+                for address in addresses {
+                    resolved.push(address.ip());
+                    match contact_seed(peer_registry, &self.node_client, address).await {
+                        Ok(Some(node)) => nodes.push(node),
+                        Ok(None) => {}
+                        Err(e) => {
+                            tracing::debug!("Seed peer {} ({}) failed: {}", seed, address, e);
+                            errors.push(format!("{} ({}): {}", seed, address, e));
+                        }
+                    }
+                }
+            }
+
+            resolved.sort();
+            resolved.dedup();
+            *self.resolved.write().await = resolved;
+
+            if nodes.is_empty() && !errors.is_empty() {
+                return Err(errors.join("; "));
+            }
+            Ok(nodes)
+        })
+    }
+
+    fn announce<'a>(&'a self, socket: &'a UdpSocket, message: &'a [u8]) -> BoxFuture<'a, usize> {
+        Box::pin(async move {
+            let peers: Vec<IpAddr> = self
+                .resolved
+                .read()
+                .await
+                .iter()
+                .copied()
+                .filter(IpAddr::is_ipv4)
+                .collect();
+            source::send_unicast(socket, message, &peers, self.config.multicast_port).await
+        })
+    }
+}
+
+/// Fetch a seed's node list; returns the seed itself (None if it is us) and
+/// merges the nodes it knows into the registry as gossip
+async fn contact_seed(
+    peer_registry: &PeerRegistry,
+    node_client: &NodeClient,
+    address: SocketAddr,
+) -> Result<Option<NodeInfo>, String> {
+    let response = node_client.fetch_nodes(address).await?;
+
+    if response.local_node_id == peer_registry.local_node_id() {
+        return Ok(None);
+    }
+
+    let peers = super::gossip::peer_summaries(&response.nodes);
+    for learned in peer_registry
+        .merge_gossip(&response.local_node_id, peers)
+        .await
+    {
+        tracing::info!("Learned of node {} via seed {}", learned, address);
+    }
+
+    Ok(Some(NodeInfo {
+        id: response.local_node_id,
+        hostname: response.local_hostname,
+        addresses: vec![address.ip()],
+        port: address.port(),
+        status: NodeStatus::Online,
+        last_seen: chrono::Utc::now().to_rfc3339(),
+        received_at: chrono::Utc::now(),
+        clock_offset_ms: None,
+        clock_skewed: false,
+        last_ping_ok: None,
+        last_api_ok: None,
+        interfaces: Vec::new(),
+        discovered_via: "static".to_string(),
+        wireguard_path: None,
+        learned_from: None,
+    }))
+}
+
+/// Resolve a seed spec to every address it names
+async fn resolve_seed(seed: &str) -> Result<Vec<SocketAddr>, String> {
+    let (host, port) = parse_seed(seed)?;
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .map_err(|e| e.to_string())?
+        .collect();
+
+    if addresses.is_empty() {
+        return Err("no addresses".to_string());
+    }
+    Ok(addresses)
+}
+
+/// Split "host", "host:port", "[v6]:port" or a bare IPv6 address
+fn parse_seed(seed: &str) -> Result<(String, u16), String> {
+    let seed = seed.trim();
+    if seed.is_empty() {
+        return Err("empty seed".to_string());
+    }
+
+    if let Some(rest) = seed.strip_prefix('[') {
+        let (host, after) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("Invalid seed {}: missing ']'", seed))?;
+        let port = match after.strip_prefix(':') {
+            Some(port) => parse_port(seed, port)?,
+            None if after.is_empty() => DEFAULT_SEED_PORT,
+            None => return Err(format!("Invalid seed {}", seed)),
+        };
+        return Ok((host.to_string(), port));
+    }
+
+    // More than one colon without brackets can only be a bare IPv6 address
+    if seed.parse::<IpAddr>().is_ok() {
+        return Ok((seed.to_string(), DEFAULT_SEED_PORT));
+    }
+
+    match seed.split_once(':') {
+        Some((host, port)) => Ok((host.to_string(), parse_port(seed, port)?)),
+        None => Ok((seed.to_string(), DEFAULT_SEED_PORT)),
+    }
+}
+
+fn parse_port(seed: &str, port: &str) -> Result<u16, String> {
+    port.parse()
+        .map_err(|_| format!("Invalid port in seed {}", seed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_seed() {
+        assert_eq!(
+            parse_seed("seeds.example.net").unwrap(),
+            ("seeds.example.net".to_string(), DEFAULT_SEED_PORT)
+        );
+        assert_eq!(
+            parse_seed("10.20.0.5:9000").unwrap(),
+            ("10.20.0.5".to_string(), 9000)
+        );
+        assert_eq!(
+            parse_seed("[2001:db8::5]:9000").unwrap(),
+            ("2001:db8::5".to_string(), 9000)
+        );
+        assert_eq!(
+            parse_seed("2001:db8::5").unwrap(),
+            ("2001:db8::5".to_string(), DEFAULT_SEED_PORT)
+        );
+        assert!(parse_seed("host:notaport").is_err());
+        assert!(parse_seed("").is_err());
+    }
+}
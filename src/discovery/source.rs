@@ -0,0 +1,263 @@
+// Pluggable discovery sources
+// Each way of finding peers (multicast, WireGuard, VPN sweep, VPN client peer
+// lists, gossip, static seeds, mDNS) implements DiscoverySource. The announcer
+// hands every sealed message to each registered source's announce(), and each
+// source's discover() runs on its own interval. What every source did last is
+// kept so /api/nodes can show which mechanisms are actually finding peers
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
+
+use super::{NodeInfo, PeerRegistry};
+
+pub trait DiscoverySource: Send + Sync {
+    /// Short name used in config and as `discovered_via`
+    fn name(&self) -> &'static str;
+
+    /// How often discover() runs; None for sources that only announce
+    fn interval(&self) -> Option<Duration>;
+
+    /// Look for peers. Returned nodes are added to the registry as found by this
+    /// source; sources that only learn addresses to announce to keep those
+    /// themselves and return nothing
+    fn discover<'a>(
+        &'a self,
+        peer_registry: &'a PeerRegistry,
+    ) -> BoxFuture<'a, Result<Vec<NodeInfo>, String>>;
+
+    /// Deliver a sealed discovery message; returns how many datagrams were sent
+    fn announce<'a>(&'a self, socket: &'a UdpSocket, message: &'a [u8]) -> BoxFuture<'a, usize>;
+
+    /// Withdraw anything the source published, called once on shutdown
+    fn shutdown(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SourceStatus {
+    pub name: String,
+    pub last_discover: Option<String>,
+    /// Nodes returned by the last discover()
+    pub nodes_found: usize,
+    /// Datagrams sent by the last announce()
+    pub last_announce_sent: usize,
+    pub last_error: Option<String>,
+}
+
+/// The enabled discovery sources and what each did last
+pub struct SourceRegistry {
+    sources: Vec<Arc<dyn DiscoverySource>>,
+    status: RwLock<HashMap<&'static str, SourceStatus>>,
+}
+
+impl SourceRegistry {
+    pub fn new() -> Self {
+        SourceRegistry {
+            sources: Vec::new(),
+            status: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn register(&mut self, source: Arc<dyn DiscoverySource>) {
+        self.sources.push(source);
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.sources.iter().map(|s| s.name()).collect()
+    }
+
+    /// Send sealed packets through every source
+    pub async fn announce(&self, socket: &UdpSocket, packets: &[Vec<u8>]) {
+        for source in &self.sources {
+            let mut sent = 0;
+            for packet in packets {
+                sent += source.announce(socket, packet).await;
+            }
+            self.status
+                .write()
+                .await
+                .entry(source.name())
+                .or_default()
+                .last_announce_sent = sent;
+        }
+    }
+
+    /// Run discover() once and record the outcome
+    pub async fn run_discover(&self, source: &dyn DiscoverySource, peer_registry: &PeerRegistry) {
+        let result = source.discover(peer_registry).await;
+
+        // Record the outcome first; the status lock isn't held while the
+        // peer registry is updated
+        let nodes = {
+            let mut status = self.status.write().await;
+            let status = status.entry(source.name()).or_default();
+            status.last_discover = Some(chrono::Utc::now().to_rfc3339());
+
+            match result {
+                Ok(nodes) => {
+                    let nodes: Vec<NodeInfo> = nodes
+                        .into_iter()
+                        .filter(|n| n.id != peer_registry.local_node_id())
+                        .collect();
+                    status.nodes_found = nodes.len();
+                    status.last_error = None;
+                    nodes
+                }
+                Err(e) => {
+                    tracing::debug!("Discovery source {} failed: {}", source.name(), e);
+                    status.last_error = Some(e);
+                    Vec::new()
+                }
+            }
+        };
+
+        for mut node in nodes {
+            node.discovered_via = source.name().to_string();
+            let id = node.id.clone();
+            if peer_registry.add_node(node).await {
+                tracing::info!("Discovered node {} via {}", id, source.name());
+            }
+        }
+    }
+
+    /// Spawn a discover loop for each source that has one
+    ///
+    /// Every source's shutdown hook runs once its loop (or, for announce-only
+    /// sources, the shutdown signal) ends.
+    pub fn start_discovery_tasks(
+        self: &Arc<Self>,
+        peer_registry: Arc<PeerRegistry>,
+        shutdown: watch::Receiver<bool>,
+    ) -> Vec<JoinHandle<()>> {
+        self.sources
+            .iter()
+            .map(|source| {
+                let registry = self.clone();
+                let source = source.clone();
+                let peer_registry = peer_registry.clone();
+                let mut shutdown = shutdown.clone();
+
+                tokio::spawn(async move {
+                    if let Some(period) = source.interval() {
+                        let mut discover_interval = tokio::time::interval(period);
+                        loop {
+                            tokio::select! {
+                                _ = discover_interval.tick() => {}
+                                // The watch guard isn't Send, so drop it before awaiting below
+                                _ = async { let _ = shutdown.wait_for(|stopping| *stopping).await; } => break,
+                            }
+                            registry.run_discover(source.as_ref(), &peer_registry).await;
+                        }
+                    } else {
+                        let _ = shutdown.wait_for(|stopping| *stopping).await;
+                    }
+
+                    source.shutdown().await;
+                })
+            })
+            .collect()
+    }
+
+    /// Status of every source, in registration order
+    pub async fn statuses(&self) -> Vec<SourceStatus> {
+        let status = self.status.read().await;
+        self.sources
+            .iter()
+            .map(|source| SourceStatus {
+                name: source.name().to_string(),
+                ..status.get(source.name()).cloned().unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+impl Default for SourceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Send a message to each peer's discovery port; returns how many went out
+pub async fn send_unicast(
+    socket: &UdpSocket,
+    message: &[u8],
+    peers: &[IpAddr],
+    port: u16,
+) -> usize {
+    let mut sent = 0;
+    for &peer_ip in peers {
+        let peer_addr = SocketAddr::new(peer_ip, port);
+        match socket.send_to(message, peer_addr).await {
+            Ok(_) => sent += 1,
+            Err(e) => tracing::trace!("Failed to send to peer {}: {}", peer_addr, e),
+        }
+    }
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::test_support::node;
+
+    struct FixedSource {
+        nodes: Vec<NodeInfo>,
+    }
+
+    impl DiscoverySource for FixedSource {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn interval(&self) -> Option<Duration> {
+            Some(Duration::from_secs(30))
+        }
+
+        fn discover<'a>(
+            &'a self,
+            _peer_registry: &'a PeerRegistry,
+        ) -> BoxFuture<'a, Result<Vec<NodeInfo>, String>> {
+            Box::pin(async move { Ok(self.nodes.clone()) })
+        }
+
+        fn announce<'a>(
+            &'a self,
+            _socket: &'a UdpSocket,
+            _message: &'a [u8],
+        ) -> BoxFuture<'a, usize> {
+            Box::pin(async { 0 })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_discover_records_source() {
+        let peer_registry = PeerRegistry::new("local".to_string());
+        let now = chrono::Utc::now();
+        let source = FixedSource {
+            nodes: vec![node("k", &now.to_rfc3339(), now), node("local", "", now)],
+        };
+
+        let mut registry = SourceRegistry::new();
+        registry.register(Arc::new(FixedSource { nodes: Vec::new() }));
+        registry.run_discover(&source, &peer_registry).await;
+
+        let k = peer_registry.get_node("k").await.unwrap();
+        assert_eq!(k.discovered_via, "fixed");
+        assert!(peer_registry.get_node("local").await.is_none());
+
+        let statuses = registry.statuses().await;
+        assert_eq!(statuses.len(), 1);
+        let status = statuses.first().unwrap();
+        assert_eq!(status.name, "fixed");
+        assert_eq!(status.nodes_found, 1);
+        assert!(status.last_discover.is_some());
+    }
+}
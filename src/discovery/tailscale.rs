@@ -0,0 +1,145 @@
+// Tailscale peers from `tailscale status --json`
+// Every node in the tailnet is listed with its Tailscale addresses and whether
+// the coordination server sees it online. CurAddr is the direct UDP path when
+// one has been found; otherwise traffic goes through the DERP relay in Relay
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use super::vpn_peers::{self, VpnPeer};
+use super::wireguard::parse_endpoint;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Status {
+    #[serde(default)]
+    backend_state: String,
+    #[serde(default)]
+    peer: Option<HashMap<String, PeerStatus>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PeerStatus {
+    #[serde(default)]
+    public_key: String,
+    #[serde(default)]
+    host_name: String,
+    #[serde(default, rename = "DNSName")]
+    dns_name: String,
+    #[serde(default, rename = "TailscaleIPs")]
+    tailscale_ips: Option<Vec<IpAddr>>,
+    #[serde(default)]
+    cur_addr: String,
+    #[serde(default)]
+    relay: String,
+    #[serde(default)]
+    online: bool,
+    #[serde(default)]
+    last_seen: Option<DateTime<Utc>>,
+    #[serde(default)]
+    rx_bytes: u64,
+    #[serde(default)]
+    tx_bytes: u64,
+}
+
+/// Read the tailnet's peers with `tailscale status --json`
+pub fn read_peers() -> Result<Vec<VpnPeer>, String> {
+    parse_status(&vpn_peers::command_output(
+        "tailscale",
+        &["status", "--json"],
+    )?)
+}
+
+/// Parse `tailscale status --json`
+fn parse_status(output: &str) -> Result<Vec<VpnPeer>, String> {
+    let status: Status = serde_json::from_str(output)
+        .map_err(|e| format!("Invalid tailscale status output: {}", e))?;
+    if status.backend_state != "Running" {
+        return Err(format!(
+            "Tailscale is not running ({})",
+            status.backend_state
+        ));
+    }
+
+    let mut peers: Vec<VpnPeer> = status
+        .peer
+        .unwrap_or_default()
+        .into_values()
+        .map(|peer| {
+            // Mobile clients often report "localhost"; the MagicDNS name is
+            // what the admin console shows
+            let hostname = peer
+                .dns_name
+                .split('.')
+                .next()
+                .filter(|name| !name.is_empty())
+                .unwrap_or(&peer.host_name)
+                .to_string();
+            let endpoint = parse_endpoint(&peer.cur_addr);
+
+            VpnPeer {
+                id: peer.public_key,
+                hostname: Some(hostname),
+                tunnel_addresses: peer.tailscale_ips.unwrap_or_default(),
+                endpoint,
+                relay: (endpoint.is_none() && !peer.relay.is_empty()).then_some(peer.relay),
+                online: peer.online,
+                // Online peers carry the zero time
+                last_seen: peer
+                    .last_seen
+                    .filter(|seen| seen.timestamp() > 0)
+                    .map(|seen| seen.to_rfc3339()),
+                latency_ms: None,
+                rx_bytes: Some(peer.rx_bytes),
+                tx_bytes: Some(peer.tx_bytes),
+            }
+        })
+        .collect();
+
+    peers.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+    Ok(peers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = include_str!("fixtures/tailscale_status.json");
+
+    #[test]
+    fn test_parse_status() {
+        let peers = parse_status(STATUS).unwrap();
+        let [server, nas, phone] = peers.as_slice() else {
+            panic!("expected three peers");
+        };
+
+        assert_eq!(server.hostname.as_deref(), Some("build-server"));
+        assert_eq!(
+            server.tunnel_addresses,
+            vec![
+                "100.64.0.2".parse::<IpAddr>().unwrap(),
+                "fd7a:115c:a1e0::2".parse().unwrap()
+            ]
+        );
+        assert_eq!(server.endpoint, parse_endpoint("203.0.113.9:41641"));
+        assert_eq!(server.relay, None);
+        assert!(server.online);
+        assert_eq!(server.last_seen, None);
+        assert_eq!(server.rx_bytes, Some(52640));
+
+        // No direct path yet, so it goes through DERP
+        assert_eq!(nas.endpoint, None);
+        assert_eq!(nas.relay.as_deref(), Some("ams"));
+
+        assert_eq!(phone.hostname.as_deref(), Some("pixel-7"));
+        assert!(!phone.online);
+        assert!(phone.last_seen.is_some());
+
+        let stopped = r#"{"BackendState": "NeedsLogin", "Peer": null}"#;
+        assert!(parse_status(stopped).unwrap_err().contains("NeedsLogin"));
+        assert!(parse_status("not json").is_err());
+    }
+}
@@ -337,14 +337,16 @@ mod tests {
         let result = TracerouteExecutor::parse_linux_traceroute(output, "8.8.8.8").unwrap();
 
         assert_eq!(result.hops.len(), 4);
-        assert_eq!(result.hops[0].hop_number, 1);
-        assert_eq!(result.hops[0].ip, Some("192.168.1.1".to_string()));
-        assert_eq!(result.hops[0].rtt_ms.len(), 3);
-        assert!(!result.hops[0].timed_out);
-
-        assert_eq!(result.hops[2].hop_number, 3);
-        assert!(result.hops[2].timed_out);
-        assert_eq!(result.hops[2].ip, None);
+        let first = result.hops.first().unwrap();
+        assert_eq!(first.hop_number, 1);
+        assert_eq!(first.ip, Some("192.168.1.1".to_string()));
+        assert_eq!(first.rtt_ms.len(), 3);
+        assert!(!first.timed_out);
+
+        let third = result.hops.get(2).unwrap();
+        assert_eq!(third.hop_number, 3);
+        assert!(third.timed_out);
+        assert_eq!(third.ip, None);
     }
 
     #[test]
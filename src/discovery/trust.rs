@@ -0,0 +1,667 @@
+// Signed discovery messages and peer trust
+// Every DiscoveryMessage is sent in a frame (see wire.rs) signed with the
+// node's ed25519 key. Receivers pin the first key seen for a node ID (trust on first
+// use) or only accept keys from a configured allowlist; anything else is
+// dropped and recorded so it shows up in the API.
+// Pins of nodes long gone from the registry are forgotten, and the pin file is
+// written from a background task rather than by the listener
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+
+use super::broadcast::DiscoveryMessage;
+use super::limits::REPLAY_MEMORY;
+use super::wire::{self, PacketError};
+use super::PeerRegistry;
+
+const PINNED_KEYS_FILE: &str = "pinned_peers.json";
+/// Rejected senders kept for the API; the least recently seen go first
+const MAX_REJECTED: usize = 256;
+/// Most keys pinned at once; new node IDs are turned away beyond this
+const MAX_PINNED: usize = 4096;
+/// How often pins are pruned and, if they changed, written out
+const PIN_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Protocol version 1 envelope, still accepted from older nodes
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedEnvelope {
+    /// JSON-encoded DiscoveryMessage, signed byte for byte
+    pub payload: String,
+    /// Hex-encoded ed25519 public key of the sender
+    pub public_key: String,
+    /// Hex-encoded ed25519 signature over `payload`
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrustMode {
+    /// Accept any key the first time a node ID is seen, then require that key
+    Tofu,
+    /// Only accept keys listed in `trusted_keys`
+    Allowlist,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedPeer {
+    /// Claimed node ID, if the message could be decoded
+    pub node_id: Option<String>,
+    pub hostname: Option<String>,
+    pub public_key: Option<String>,
+    pub source: IpAddr,
+    pub reason: String,
+    pub count: u64,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+struct Pin {
+    public_key: String,
+    /// When the node was last in the peer registry (or was pinned)
+    last_present: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrustSummary {
+    pub mode: TrustMode,
+    /// This node's public key, for adding to other nodes' allowlists
+    pub public_key: String,
+    /// Node ID -> pinned public key
+    pub pinned: HashMap<String, String>,
+    pub rejected: Vec<RejectedPeer>,
+}
+
+/// Sign a discovery message into a datagram ready to send
+pub fn seal(message: &DiscoveryMessage, key: &SigningKey) -> Result<Vec<u8>, String> {
+    let payload = wire::encode(message)?;
+
+    let mut signed = Vec::with_capacity(1 + payload.len());
+    signed.push(wire::PROTOCOL_VERSION);
+    signed.extend_from_slice(&payload);
+    let signature = key.sign(&signed);
+
+    let mut packet = Vec::with_capacity(wire::HEADER_LEN + payload.len());
+    packet.extend_from_slice(wire::MAGIC);
+    packet.push(wire::PROTOCOL_VERSION);
+    packet.extend_from_slice(key.verifying_key().as_bytes());
+    packet.extend_from_slice(&signature.to_bytes());
+    packet.extend_from_slice(&payload);
+
+    if packet.len() > wire::MAX_PACKET_SIZE {
+        return Err(format!(
+            "message is {} bytes, over the {} byte limit",
+            packet.len(),
+            wire::MAX_PACKET_SIZE
+        ));
+    }
+    Ok(packet)
+}
+
+/// Verify a datagram's signature and decode the message inside
+///
+/// Returns the message and the sender's hex public key.
+pub fn open(data: &[u8]) -> Result<(DiscoveryMessage, String), PacketError> {
+    let Some(rest) = data.strip_prefix(wire::MAGIC) else {
+        return open_json(data);
+    };
+    let malformed = || PacketError::Malformed("truncated header".to_string());
+
+    let (&version, rest) = rest.split_first().ok_or_else(malformed)?;
+    if version != wire::PROTOCOL_VERSION {
+        return Err(PacketError::UnsupportedVersion(version));
+    }
+    let (key_bytes, rest) = rest.split_first_chunk::<32>().ok_or_else(malformed)?;
+    let (signature_bytes, payload) = rest.split_first_chunk::<64>().ok_or_else(malformed)?;
+
+    let key = VerifyingKey::from_bytes(key_bytes)
+        .map_err(|_| PacketError::Malformed("invalid public key".to_string()))?;
+    let mut signed = Vec::with_capacity(1 + payload.len());
+    signed.push(version);
+    signed.extend_from_slice(payload);
+    key.verify(&signed, &Signature::from_bytes(signature_bytes))
+        .map_err(|_| PacketError::BadSignature)?;
+
+    Ok((wire::decode(payload)?, hex::encode(key_bytes)))
+}
+
+/// Verify and decode a version 1 JSON envelope
+fn open_json(data: &[u8]) -> Result<(DiscoveryMessage, String), PacketError> {
+    let envelope: SignedEnvelope = serde_json::from_slice(data)
+        .map_err(|_| PacketError::Malformed("unsigned or malformed message".to_string()))?;
+
+    let key_bytes: [u8; 32] = hex::decode(&envelope.public_key)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| PacketError::Malformed("invalid public key".to_string()))?;
+    let signature_bytes: [u8; 64] = hex::decode(&envelope.signature)
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| PacketError::Malformed("invalid signature encoding".to_string()))?;
+
+    let key = VerifyingKey::from_bytes(&key_bytes)
+        .map_err(|_| PacketError::Malformed("invalid public key".to_string()))?;
+    key.verify(
+        envelope.payload.as_bytes(),
+        &Signature::from_bytes(&signature_bytes),
+    )
+    .map_err(|_| PacketError::BadSignature)?;
+
+    let message = serde_json::from_str(&envelope.payload)
+        .map_err(|e| PacketError::Malformed(format!("malformed payload: {}", e)))?;
+
+    Ok((message, envelope.public_key))
+}
+
+/// Keys we trust and peers we turned away
+pub struct TrustStore {
+    mode: TrustMode,
+    /// This node's own ID and key, which no one else may use
+    node_id: String,
+    public_key: String,
+    allowlist: HashSet<String>,
+    pinned: RwLock<HashMap<String, Pin>>,
+    /// Where TOFU pins are persisted (None keeps them in memory only)
+    pins_path: Option<PathBuf>,
+    /// Pins changed since they were last written out
+    pins_dirty: AtomicBool,
+    rejected: RwLock<HashMap<String, RejectedPeer>>,
+}
+
+impl TrustStore {
+    pub fn new(
+        mode: TrustMode,
+        node_id: String,
+        public_key: String,
+        trusted_keys: &[String],
+        state_dir: Option<PathBuf>,
+    ) -> Self {
+        let pins_path = state_dir.map(|dir| dir.join(PINNED_KEYS_FILE));
+        let pinned: HashMap<String, String> = pins_path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        // Nodes pinned in an earlier run get the full grace period to return
+        let now = Instant::now();
+        let pinned = pinned
+            .into_iter()
+            .map(|(node_id, public_key)| {
+                let pin = Pin {
+                    public_key,
+                    last_present: now,
+                };
+                (node_id, pin)
+            })
+            .collect();
+
+        TrustStore {
+            mode,
+            node_id,
+            public_key,
+            allowlist: trusted_keys.iter().map(|k| k.to_lowercase()).collect(),
+            pinned: RwLock::new(pinned),
+            pins_path,
+            pins_dirty: AtomicBool::new(false),
+            rejected: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Decide whether `public_key` may speak for `node_id`, pinning it on first use
+    pub async fn check(&self, node_id: &str, public_key: &str) -> Result<(), String> {
+        let public_key = public_key.to_lowercase();
+
+        // Our own announcements loop back over multicast, but nobody else
+        // gets to speak for our ID or with our key
+        if node_id == self.node_id || public_key == self.public_key {
+            return if node_id == self.node_id && public_key == self.public_key {
+                Ok(())
+            } else {
+                Err("claims this node's own ID or public key".to_string())
+            };
+        }
+
+        if let Some(pin) = self.pinned.read().await.get(node_id) {
+            return if pin.public_key == public_key {
+                Ok(())
+            } else {
+                Err("public key does not match the key pinned for this node ID".to_string())
+            };
+        }
+
+        match self.mode {
+            TrustMode::Allowlist if !self.allowlist.contains(&public_key) => {
+                return Err("public key is not in trusted_keys".to_string());
+            }
+            TrustMode::Allowlist | TrustMode::Tofu => {}
+        }
+
+        let mut pinned = self.pinned.write().await;
+        if pinned.len() >= MAX_PINNED && !pinned.contains_key(node_id) {
+            return Err(format!("already {} pinned keys", MAX_PINNED));
+        }
+        let pin = Pin {
+            public_key,
+            last_present: Instant::now(),
+        };
+        // Another message from the node may have pinned it meanwhile
+        if let Some(existing) = pinned.get(node_id) {
+            return if existing.public_key == pin.public_key {
+                Ok(())
+            } else {
+                Err("public key does not match the key pinned for this node ID".to_string())
+            };
+        }
+        pinned.insert(node_id.to_string(), pin);
+        self.pins_dirty.store(true, Ordering::Relaxed);
+        tracing::info!("Pinned public key for node {}", node_id);
+
+        Ok(())
+    }
+
+    /// Forget the pins of nodes that have been out of the registry for
+    /// REPLAY_MEMORY, so a flood of made-up node IDs doesn't pin forever
+    pub async fn prune_pins(&self, peer_registry: &PeerRegistry) {
+        let present: HashSet<String> = peer_registry
+            .get_all_nodes()
+            .await
+            .into_iter()
+            .map(|node| node.id)
+            .collect();
+        self.prune_pins_at(&present, Instant::now()).await;
+    }
+
+    async fn prune_pins_at(&self, present: &HashSet<String>, now: Instant) {
+        let mut pinned = self.pinned.write().await;
+        let before = pinned.len();
+        pinned.retain(|node_id, pin| {
+            if present.contains(node_id) {
+                pin.last_present = now;
+            }
+            now.duration_since(pin.last_present) < REPLAY_MEMORY
+        });
+
+        let forgotten = before - pinned.len();
+        if forgotten > 0 {
+            tracing::debug!("Forgot the pinned keys of {} departed nodes", forgotten);
+            self.pins_dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Write the pinned keys out to the state directory
+    pub async fn flush(&self) -> Result<(), String> {
+        let Some(path) = self.pins_path.clone() else {
+            return Ok(());
+        };
+
+        let json = {
+            let pinned = self.pinned.read().await;
+            let keys: HashMap<&str, &str> = pinned
+                .iter()
+                .map(|(node_id, pin)| (node_id.as_str(), pin.public_key.as_str()))
+                .collect();
+            serde_json::to_string_pretty(&keys).map_err(|e| e.to_string())?
+        };
+        self.pins_dirty.store(false, Ordering::Relaxed);
+
+        let result = tokio::task::spawn_blocking(move || {
+            std::fs::write(&path, json).map_err(|e| format!("{}: {}", path.display(), e))
+        })
+        .await
+        .map_err(|e| format!("Task join error: {}", e))
+        .and_then(|r| r);
+        if result.is_err() {
+            self.pins_dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Prune the pins and write them out when they changed, until shutdown
+    pub fn start_maintenance_task(
+        self: &Arc<Self>,
+        peer_registry: Arc<PeerRegistry>,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> JoinHandle<()> {
+        let trust_store = self.clone();
+
+        tokio::spawn(async move {
+            let mut maintenance_interval = interval(PIN_MAINTENANCE_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = maintenance_interval.tick() => {}
+                    _ = shutdown.wait_for(|stopping| *stopping) => break,
+                }
+
+                trust_store.prune_pins(&peer_registry).await;
+                if trust_store.pins_dirty.load(Ordering::Relaxed) {
+                    if let Err(e) = trust_store.flush().await {
+                        tracing::warn!("Failed to persist pinned keys: {}", e);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Verify and authorise a received datagram
+    pub async fn accept(
+        &self,
+        data: &[u8],
+        source: IpAddr,
+    ) -> Result<DiscoveryMessage, PacketError> {
+        let (message, public_key) = match open(data) {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.reject(None, None, None, source, e.to_string()).await;
+                return Err(e);
+            }
+        };
+
+        let node_id = message.node_id().to_string();
+        let hostname = match &message {
+            DiscoveryMessage::Announce { hostname, .. } => Some(hostname.clone()),
+            DiscoveryMessage::Goodbye { .. } | DiscoveryMessage::Peers { .. } => None,
+        };
+
+        match self.check(&node_id, &public_key).await {
+            Ok(()) => Ok(message),
+            Err(reason) => {
+                self.reject(
+                    Some(node_id),
+                    hostname,
+                    Some(public_key),
+                    source,
+                    reason.clone(),
+                )
+                .await;
+                Err(PacketError::Untrusted(reason))
+            }
+        }
+    }
+
+    async fn reject(
+        &self,
+        node_id: Option<String>,
+        hostname: Option<String>,
+        public_key: Option<String>,
+        source: IpAddr,
+        reason: String,
+    ) {
+        tracing::debug!(
+            "Rejected discovery message from {} ({}): {}",
+            source,
+            node_id.as_deref().unwrap_or("unknown node"),
+            reason
+        );
+
+        let now = chrono::Utc::now().to_rfc3339();
+        let key = format!(
+            "{}|{}|{}",
+            source,
+            node_id.as_deref().unwrap_or_default(),
+            public_key.as_deref().unwrap_or_default()
+        );
+
+        let mut rejected = self.rejected.write().await;
+        if !rejected.contains_key(&key) && rejected.len() >= MAX_REJECTED {
+            let oldest = rejected
+                .iter()
+                .min_by(|a, b| a.1.last_seen.cmp(&b.1.last_seen))
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                rejected.remove(&oldest);
+            }
+        }
+        let entry = rejected.entry(key).or_insert_with(|| RejectedPeer {
+            node_id,
+            hostname: hostname.clone(),
+            public_key,
+            source,
+            reason: reason.clone(),
+            count: 0,
+            first_seen: now.clone(),
+            last_seen: now.clone(),
+        });
+        entry.count += 1;
+        entry.reason = reason;
+        entry.last_seen = now;
+        if hostname.is_some() {
+            entry.hostname = hostname;
+        }
+    }
+
+    pub async fn summary(&self) -> TrustSummary {
+        let mut rejected: Vec<RejectedPeer> =
+            self.rejected.read().await.values().cloned().collect();
+        rejected.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+
+        TrustSummary {
+            mode: self.mode,
+            public_key: self.public_key.clone(),
+            pinned: self
+                .pinned
+                .read()
+                .await
+                .iter()
+                .map(|(node_id, pin)| (node_id.clone(), pin.public_key.clone()))
+                .collect(),
+            rejected,
+        }
+    }
+}
+
+pub fn parse_trust_mode(mode: &str) -> Result<TrustMode, String> {
+    match mode.to_lowercase().as_str() {
+        "tofu" => Ok(TrustMode::Tofu),
+        "allowlist" => Ok(TrustMode::Allowlist),
+        _ => Err(format!("Invalid trust mode: {}", mode)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> SigningKey {
+        SigningKey::generate(&mut rand::rngs::OsRng)
+    }
+
+    fn goodbye(node_id: &str) -> DiscoveryMessage {
+        DiscoveryMessage::Goodbye {
+            node_id: node_id.to_string(),
+            reason: "shutdown".to_string(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = key();
+        let sealed = seal(&goodbye("node-a"), &key).unwrap();
+
+        let (message, public_key) = open(&sealed).unwrap();
+        assert!(
+            matches!(message, DiscoveryMessage::Goodbye { node_id, .. } if node_id == "node-a")
+        );
+        assert_eq!(public_key, hex::encode(key.verifying_key().as_bytes()));
+
+        let mut tampered = sealed.clone();
+        if let Some(last) = tampered.last_mut() {
+            *last ^= 1;
+        }
+        assert_eq!(open(&tampered).err(), Some(PacketError::BadSignature));
+
+        let mut future = sealed.clone();
+        if let Some(version) = future.get_mut(3) {
+            *version = wire::PROTOCOL_VERSION + 1;
+        }
+        assert_eq!(
+            open(&future).err(),
+            Some(PacketError::UnsupportedVersion(wire::PROTOCOL_VERSION + 1))
+        );
+
+        let unsigned = serde_json::to_vec(&goodbye("node-a")).unwrap();
+        assert!(matches!(open(&unsigned), Err(PacketError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_open_legacy_json() {
+        let key = key();
+        let payload = serde_json::to_string(&goodbye("node-a")).unwrap();
+        let envelope = serde_json::to_vec(&SignedEnvelope {
+            signature: hex::encode(key.sign(payload.as_bytes()).to_bytes()),
+            public_key: hex::encode(key.verifying_key().as_bytes()),
+            payload,
+        })
+        .unwrap();
+
+        let (message, _) = open(&envelope).unwrap();
+        assert!(matches!(message, DiscoveryMessage::Goodbye { .. }));
+
+        // A goodbye without a timestamp can't be checked for replay
+        let payload = r#"{"type":"goodbye","node_id":"node-a","reason":"shutdown"}"#.to_string();
+        let envelope = serde_json::to_vec(&SignedEnvelope {
+            signature: hex::encode(key.sign(payload.as_bytes()).to_bytes()),
+            public_key: hex::encode(key.verifying_key().as_bytes()),
+            payload,
+        })
+        .unwrap();
+        assert!(matches!(open(&envelope), Err(PacketError::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_tofu_pins_first_key() {
+        let store = TrustStore::new(
+            TrustMode::Tofu,
+            "local".to_string(),
+            String::new(),
+            &[],
+            None,
+        );
+        let source: IpAddr = "192.168.1.20".parse().unwrap();
+        let real = key();
+        let impostor = key();
+
+        let first = seal(&goodbye("node-a"), &real).unwrap();
+        assert!(store.accept(&first, source).await.is_ok());
+
+        // Someone else claiming the same node ID can't evict it
+        let fake = seal(&goodbye("node-a"), &impostor).unwrap();
+        assert!(matches!(
+            store.accept(&fake, source).await,
+            Err(PacketError::Untrusted(_))
+        ));
+
+        let summary = store.summary().await;
+        assert_eq!(summary.rejected.len(), 1);
+        assert_eq!(
+            summary.rejected.first().and_then(|r| r.node_id.as_deref()),
+            Some("node-a")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_own_identity() {
+        let own = key();
+        let own_hex = hex::encode(own.verifying_key().as_bytes());
+        let store = TrustStore::new(
+            TrustMode::Tofu,
+            "local".to_string(),
+            own_hex.clone(),
+            &[],
+            None,
+        );
+        let other_hex = hex::encode(key().verifying_key().as_bytes());
+
+        // Our own looped-back messages pass without pinning anything
+        assert!(store.check("local", &own_hex).await.is_ok());
+        assert!(store.summary().await.pinned.is_empty());
+
+        // Nobody may borrow our key for another ID, or our ID with another key
+        assert!(store.check("node-a", &own_hex).await.is_err());
+        assert!(store.check("local", &other_hex).await.is_err());
+        assert!(store.summary().await.pinned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_prune_pins() {
+        let store = TrustStore::new(
+            TrustMode::Tofu,
+            "local".to_string(),
+            String::new(),
+            &[],
+            None,
+        );
+        let public_key = |key: &SigningKey| hex::encode(key.verifying_key().as_bytes());
+        let (a, b) = (key(), key());
+        assert!(store.check("node-a", &public_key(&a)).await.is_ok());
+        assert!(store.check("node-b", &public_key(&b)).await.is_ok());
+
+        // node-a stays in the registry; node-b left and is forgotten after a while
+        let present: HashSet<String> = ["node-a".to_string()].into();
+        let start = Instant::now();
+        store.prune_pins_at(&present, start).await;
+        store
+            .prune_pins_at(&present, start + REPLAY_MEMORY / 2)
+            .await;
+        assert_eq!(store.summary().await.pinned.len(), 2);
+
+        store.prune_pins_at(&present, start + REPLAY_MEMORY).await;
+        let pinned = store.summary().await.pinned;
+        assert!(pinned.contains_key("node-a"));
+        assert!(!pinned.contains_key("node-b"));
+        assert!(store.check("node-b", &public_key(&key())).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pin_limit() {
+        let store = TrustStore::new(
+            TrustMode::Tofu,
+            "local".to_string(),
+            String::new(),
+            &[],
+            None,
+        );
+        let public_key = hex::encode(key().verifying_key().as_bytes());
+        for n in 0..MAX_PINNED {
+            assert!(store
+                .check(&format!("node-{}", n), &public_key)
+                .await
+                .is_ok());
+        }
+
+        assert!(store.check("one-too-many", &public_key).await.is_err());
+        // Pinned nodes are still recognised
+        assert!(store.check("node-0", &public_key).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_allowlist() {
+        let trusted = key();
+        let trusted_hex = hex::encode(trusted.verifying_key().as_bytes());
+        let store = TrustStore::new(
+            TrustMode::Allowlist,
+            "local".to_string(),
+            String::new(),
+            &[trusted_hex],
+            None,
+        );
+
+        assert!(store
+            .check("node-a", &hex::encode(trusted.verifying_key().as_bytes()))
+            .await
+            .is_ok());
+        assert!(store
+            .check("node-b", &hex::encode(key().verifying_key().as_bytes()))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,242 @@
+// Peer lists kept by VPN clients
+// Tailscale, ZeroTier and an OpenVPN server already know their peers, so
+// reading `tailscale status --json`, `zerotier-cli listpeers -j` or the
+// OpenVPN status gives unicast discovery targets on those VPNs without the
+// vpn_scan ping sweep. The latest list from each is kept for /api/vpn-peers
+
+use chrono::Utc;
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::RwLock;
+
+use super::source::{self, DiscoverySource};
+use super::{openvpn, tailscale, zerotier, NodeInfo, PeerRegistry};
+use crate::config::Config;
+
+/// How often each VPN client is asked for its peers
+const PEER_LIST_INTERVAL_SECS: u64 = 60;
+
+/// Latest peer list from each VPN client, shared by the sources and the API
+pub type VpnPeerLists = Arc<RwLock<BTreeMap<VpnTool, VpnPeerList>>>;
+
+/// A VPN client that can list its peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VpnTool {
+    Tailscale,
+    ZeroTier,
+    OpenVpn,
+}
+
+impl VpnTool {
+    /// Source name, as used in config and `discovered_via`
+    pub fn name(self) -> &'static str {
+        match self {
+            VpnTool::Tailscale => "tailscale",
+            VpnTool::ZeroTier => "zerotier",
+            VpnTool::OpenVpn => "openvpn",
+        }
+    }
+}
+
+/// A peer as reported by its VPN client
+#[derive(Debug, Clone, Serialize)]
+pub struct VpnPeer {
+    /// Tailscale node key, ZeroTier node address or OpenVPN common name
+    pub id: String,
+    pub hostname: Option<String>,
+    /// The peer's own addresses inside the VPN
+    pub tunnel_addresses: Vec<IpAddr>,
+    /// Where the VPN reaches the peer directly, None if relayed or unknown
+    pub endpoint: Option<SocketAddr>,
+    /// Relay carrying the traffic instead (a Tailscale DERP region)
+    pub relay: Option<String>,
+    pub online: bool,
+    pub last_seen: Option<String>,
+    pub latency_ms: Option<u64>,
+    pub rx_bytes: Option<u64>,
+    pub tx_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VpnPeerList {
+    pub vpn: VpnTool,
+    pub peers: Vec<VpnPeer>,
+    /// Why the client couldn't be read last time; peers is empty then
+    pub error: Option<String>,
+    pub updated: String,
+}
+
+/// Ask a VPN client for its peers
+pub async fn read_peers(tool: VpnTool, config: &Config) -> Result<Vec<VpnPeer>, String> {
+    let blocking = match tool {
+        VpnTool::Tailscale => tokio::task::spawn_blocking(tailscale::read_peers),
+        VpnTool::ZeroTier => tokio::task::spawn_blocking(zerotier::read_peers),
+        VpnTool::OpenVpn => {
+            return openvpn::read_peers(
+                config.openvpn_status.as_deref(),
+                config.openvpn_management.as_deref(),
+            )
+            .await
+        }
+    };
+    blocking
+        .await
+        .map_err(|e| format!("{} peer list task failed: {}", tool.name(), e))?
+}
+
+/// Run a VPN client's CLI and return what it printed
+pub fn command_output(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to execute {}: {}", program, e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Addresses to announce to: each online peer's tunnel addresses, or its
+/// endpoint when the client doesn't know which addresses the peer holds
+pub fn discovery_targets(peers: &[VpnPeer]) -> Vec<IpAddr> {
+    let mut targets = Vec::new();
+    for peer in peers.iter().filter(|peer| peer.online) {
+        let addresses = if peer.tunnel_addresses.is_empty() {
+            peer.endpoint.iter().map(|e| e.ip()).collect()
+        } else {
+            peer.tunnel_addresses.clone()
+        };
+        for ip in addresses {
+            if !targets.contains(&ip) {
+                targets.push(ip);
+            }
+        }
+    }
+    targets
+}
+
+/// Unicast announcements to the peers a VPN client lists
+pub struct VpnPeerSource {
+    tool: VpnTool,
+    config: Arc<Config>,
+    lists: VpnPeerLists,
+    targets: RwLock<Vec<IpAddr>>,
+}
+
+impl VpnPeerSource {
+    pub fn new(tool: VpnTool, config: Arc<Config>, lists: VpnPeerLists) -> Self {
+        VpnPeerSource {
+            tool,
+            config,
+            lists,
+            targets: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl DiscoverySource for VpnPeerSource {
+    fn name(&self) -> &'static str {
+        self.tool.name()
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(PEER_LIST_INTERVAL_SECS))
+    }
+
+    fn discover<'a>(
+        &'a self,
+        _peer_registry: &'a PeerRegistry,
+    ) -> BoxFuture<'a, Result<Vec<NodeInfo>, String>> {
+        Box::pin(async move {
+            let result = read_peers(self.tool, &self.config).await;
+
+            let (peers, error) = match &result {
+                Ok(peers) => (peers.clone(), None),
+                Err(e) => (Vec::new(), Some(e.clone())),
+            };
+            *self.targets.write().await = discovery_targets(&peers);
+            self.lists.write().await.insert(
+                self.tool,
+                VpnPeerList {
+                    vpn: self.tool,
+                    peers,
+                    error,
+                    updated: Utc::now().to_rfc3339(),
+                },
+            );
+
+            // Peers only become nodes once they answer our announcements
+            result.map(|_| Vec::new())
+        })
+    }
+
+    fn announce<'a>(&'a self, socket: &'a UdpSocket, message: &'a [u8]) -> BoxFuture<'a, usize> {
+        Box::pin(async move {
+            let targets = self.targets.read().await.clone();
+            if !targets.is_empty() {
+                tracing::debug!(
+                    "Sending unicast discovery to {} {} peers",
+                    targets.len(),
+                    self.tool.name()
+                );
+            }
+            source::send_unicast(socket, message, &targets, self.config.multicast_port).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(tunnel: &[&str], endpoint: Option<&str>, online: bool) -> VpnPeer {
+        VpnPeer {
+            id: "peer".to_string(),
+            hostname: None,
+            tunnel_addresses: tunnel.iter().map(|ip| ip.parse().unwrap()).collect(),
+            endpoint: endpoint.map(|e| e.parse().unwrap()),
+            relay: None,
+            online,
+            last_seen: None,
+            latency_ms: None,
+            rx_bytes: None,
+            tx_bytes: None,
+        }
+    }
+
+    #[test]
+    fn test_discovery_targets() {
+        let peers = vec![
+            peer(
+                &["100.64.0.2", "fd7a:115c:a1e0::2"],
+                Some("203.0.113.9:41641"),
+                true,
+            ),
+            peer(&["100.64.0.3"], None, false),
+            peer(&[], Some("203.0.113.20:9993"), true),
+            peer(&["100.64.0.2"], None, true),
+        ];
+
+        let targets: Vec<String> = discovery_targets(&peers)
+            .iter()
+            .map(|ip| ip.to_string())
+            .collect();
+        assert_eq!(
+            targets,
+            vec!["100.64.0.2", "fd7a:115c:a1e0::2", "203.0.113.20"]
+        );
+    }
+}
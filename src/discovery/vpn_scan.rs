@@ -1,13 +1,24 @@
 // VPN subnet discovery via ping sweep
 // For VPNs like OpenConnect where broadcast/multicast doesn't work
-// and we can't query the VPN tool for peer info (unlike WireGuard)
+// and we can't query the VPN tool for peer info (unlike WireGuard, Tailscale,
+// ZeroTier or an OpenVPN server)
 
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
 use std::net::{IpAddr, Ipv4Addr};
 use std::process::Command;
 use std::sync::Arc;
 use std::time::Duration;
-use surge_ping::{Client, Config, PingIdentifier, PingSequence};
-use tokio::sync::Semaphore;
+use surge_ping::{Client, PingIdentifier, PingSequence};
+use tokio::net::UdpSocket;
+use tokio::sync::{RwLock, Semaphore};
+
+use super::source::{self, DiscoverySource};
+use super::{NodeInfo, PeerRegistry};
+use crate::config::Config;
+
+/// The sweep is slow and noisy, so only rescan every 30 minutes
+const VPN_SCAN_INTERVAL_SECS: u64 = 30 * 60;
 
 /// Information about a VPN/tun interface
 #[derive(Debug, Clone)]
@@ -68,6 +79,46 @@ impl VpnInterface {
     }
 }
 
+/// What kind of VPN an interface belongs to, guessed from its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VpnKind {
+    WireGuard,
+    Tailscale,
+    ZeroTier,
+    /// Generic tun device (OpenVPN, OpenConnect, macOS utun, ...)
+    Tun,
+    Tap,
+    Ppp,
+    Ipsec,
+}
+
+/// Interface name prefixes used by common VPN clients
+const VPN_INTERFACE_PREFIXES: &[(&str, VpnKind)] = &[
+    ("tun", VpnKind::Tun),
+    ("tap", VpnKind::Tap),
+    ("wg", VpnKind::WireGuard),
+    ("ppp", VpnKind::Ppp),
+    ("utun", VpnKind::Tun),
+    ("ipsec", VpnKind::Ipsec),
+    ("tailscale", VpnKind::Tailscale),
+    ("zt", VpnKind::ZeroTier),
+    ("nordlynx", VpnKind::WireGuard),
+];
+
+/// Classify an interface by name, None if it doesn't look like a VPN tunnel
+pub fn vpn_kind(name: &str) -> Option<VpnKind> {
+    VPN_INTERFACE_PREFIXES
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|&(_, kind)| kind)
+}
+
+/// Check whether an interface name looks like a VPN tunnel
+pub fn is_vpn_interface_name(name: &str) -> bool {
+    vpn_kind(name).is_some()
+}
+
 /// Detect tun/tap interfaces that might be VPN tunnels
 pub fn get_vpn_interfaces() -> Vec<VpnInterface> {
     let mut interfaces = Vec::new();
@@ -246,7 +297,7 @@ async fn ping_sweep(hosts: &[Ipv4Addr]) -> Vec<IpAddr> {
 
 /// Ping a single host and return true if it responds
 async fn ping_host(address: IpAddr, timeout_ms: u64) -> bool {
-    let config = Config::default();
+    let config = surge_ping::Config::default();
 
     let client = match Client::new(&config) {
         Ok(c) => c,
@@ -274,6 +325,54 @@ pub async fn get_vpn_peer_ips() -> Vec<IpAddr> {
     scan_vpn_subnets().await
 }
 
+/// Ping-sweeps VPN subnets and unicasts announcements to whatever answered
+pub struct VpnScanSource {
+    config: Arc<Config>,
+    peers: RwLock<Vec<IpAddr>>,
+}
+
+impl VpnScanSource {
+    pub fn new(config: Arc<Config>) -> Self {
+        VpnScanSource {
+            config,
+            peers: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl DiscoverySource for VpnScanSource {
+    fn name(&self) -> &'static str {
+        "vpn_scan"
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(VPN_SCAN_INTERVAL_SECS))
+    }
+
+    fn discover<'a>(
+        &'a self,
+        _peer_registry: &'a PeerRegistry,
+    ) -> BoxFuture<'a, Result<Vec<NodeInfo>, String>> {
+        Box::pin(async move {
+            tracing::debug!("Starting VPN subnet scan for peer discovery");
+            let peers = get_vpn_peer_ips().await;
+            *self.peers.write().await = peers;
+            // Responders only become nodes once they answer our announcements
+            Ok(Vec::new())
+        })
+    }
+
+    fn announce<'a>(&'a self, socket: &'a UdpSocket, message: &'a [u8]) -> BoxFuture<'a, usize> {
+        Box::pin(async move {
+            let peers = self.peers.read().await.clone();
+            if !peers.is_empty() {
+                tracing::debug!("Sending unicast discovery to {} VPN peers", peers.len());
+            }
+            source::send_unicast(socket, message, &peers, self.config.multicast_port).await
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -309,6 +408,18 @@ mod tests {
         assert_eq!(iface.host_count(), 1); // /32 single host
     }
 
+    #[test]
+    fn test_is_vpn_interface_name() {
+        assert!(is_vpn_interface_name("tun0"));
+        assert!(is_vpn_interface_name("wg-office"));
+        assert!(is_vpn_interface_name("tailscale0"));
+        assert!(!is_vpn_interface_name("eth0"));
+        assert!(!is_vpn_interface_name("wlp2s0"));
+        assert_eq!(vpn_kind("nordlynx"), Some(VpnKind::WireGuard));
+        assert_eq!(vpn_kind("utun3"), Some(VpnKind::Tun));
+        assert_eq!(vpn_kind("enp3s0"), None);
+    }
+
     #[test]
     fn test_vpn_interface_host_ips() {
         let iface = VpnInterface {
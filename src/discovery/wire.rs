@@ -0,0 +1,639 @@
+// Discovery wire format
+// Discovery datagrams are a compact binary frame rather than JSON, so an
+// announcement fits in a single unfragmented packet:
+//
+//   "NRV" | version (u8) | public key (32) | signature (64) | payload
+//
+// The signature covers the version byte and the payload. Inside the payload
+// strings and lists carry a u8 length prefix, addresses a family tag and
+// timestamps are i64 milliseconds. Peer summaries that don't fit next to an
+// announcement follow in `peers` messages.
+//
+// Compatibility: binary frames are only decoded at this PROTOCOL_VERSION;
+// besides those, version 1 JSON envelopes from older nodes are accepted but
+// no longer sent. Fields added without a version bump go at the end of a
+// message, and readers ignore trailing bytes they don't know. Every message
+// must carry its timestamp; one without it can't be checked for replay and
+// is rejected as malformed
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serialize;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::broadcast::DiscoveryMessage;
+use super::gossip::PeerSummary;
+use super::interfaces::InterfaceAddress;
+use super::vpn_scan::VpnKind;
+
+pub const MAGIC: &[u8; 3] = b"NRV";
+pub const PROTOCOL_VERSION: u8 = 2;
+/// Magic, version, public key and signature
+pub const HEADER_LEN: usize = 3 + 1 + 32 + 64;
+/// Largest datagram we send; fits the IPv6 minimum MTU of 1280 with IP and UDP headers
+pub const MAX_PACKET_SIZE: usize = 1200;
+/// Larger datagrams are dropped unread (version 1 JSON announcements fit)
+pub const MAX_RECEIVE_SIZE: usize = 8192;
+
+const TYPE_ANNOUNCE: u8 = 1;
+const TYPE_GOODBYE: u8 = 2;
+const TYPE_PEERS: u8 = 3;
+
+/// Why a received datagram was dropped
+#[derive(Debug, Clone, PartialEq)]
+pub enum PacketError {
+    Oversized(usize),
+    Malformed(String),
+    UnsupportedVersion(u8),
+    BadSignature,
+    /// Signed correctly, but not by a key we trust for the node
+    Untrusted(String),
+    /// The source sent more than its share
+    RateLimited,
+    /// Older than the sender's recent messages, or sent too far from our clock
+    Replayed,
+    /// Already received, e.g. over another interface
+    Duplicate,
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::Oversized(len) => write!(f, "oversized packet ({} bytes)", len),
+            PacketError::Malformed(reason) => write!(f, "malformed packet: {}", reason),
+            PacketError::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version {}", version)
+            }
+            PacketError::BadSignature => write!(f, "bad signature"),
+            PacketError::Untrusted(reason) => write!(f, "{}", reason),
+            PacketError::RateLimited => write!(f, "rate limited"),
+            PacketError::Replayed => write!(f, "replayed message"),
+            PacketError::Duplicate => write!(f, "duplicate message"),
+        }
+    }
+}
+
+/// Counters for received discovery datagrams
+#[derive(Default)]
+pub struct PacketStats {
+    received: AtomicU64,
+    accepted: AtomicU64,
+    oversized: AtomicU64,
+    malformed: AtomicU64,
+    unsupported_version: AtomicU64,
+    untrusted: AtomicU64,
+    rate_limited: AtomicU64,
+    replayed: AtomicU64,
+    duplicate: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketCounts {
+    pub protocol_version: u8,
+    pub received: u64,
+    pub accepted: u64,
+    pub oversized: u64,
+    pub malformed: u64,
+    pub unsupported_version: u64,
+    /// Bad signatures and keys the trust store turned away
+    pub untrusted: u64,
+    pub rate_limited: u64,
+    pub replayed: u64,
+    /// Copies of one packet arriving over several interfaces or sources
+    pub duplicate: u64,
+}
+
+impl PacketStats {
+    pub fn record<T>(&self, result: &Result<T, PacketError>) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        let counter = match result {
+            Ok(_) => &self.accepted,
+            Err(PacketError::Oversized(_)) => &self.oversized,
+            Err(PacketError::Malformed(_)) => &self.malformed,
+            Err(PacketError::UnsupportedVersion(_)) => &self.unsupported_version,
+            Err(PacketError::BadSignature) | Err(PacketError::Untrusted(_)) => &self.untrusted,
+            Err(PacketError::RateLimited) => &self.rate_limited,
+            Err(PacketError::Replayed) => &self.replayed,
+            Err(PacketError::Duplicate) => &self.duplicate,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> PacketCounts {
+        PacketCounts {
+            protocol_version: PROTOCOL_VERSION,
+            received: self.received.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            oversized: self.oversized.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            unsupported_version: self.unsupported_version.load(Ordering::Relaxed),
+            untrusted: self.untrusted.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            replayed: self.replayed.load(Ordering::Relaxed),
+            duplicate: self.duplicate.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Encode a message payload (everything after the header)
+pub fn encode(message: &DiscoveryMessage) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+
+    match message {
+        DiscoveryMessage::Announce {
+            node_id,
+            hostname,
+            addresses,
+            port,
+            timestamp,
+            version,
+            peers,
+            interfaces,
+        } => {
+            buf.push(TYPE_ANNOUNCE);
+            put_str(&mut buf, node_id);
+            put_str(&mut buf, hostname);
+            buf.extend_from_slice(&port.to_be_bytes());
+            put_time(&mut buf, timestamp)?;
+            put_str(&mut buf, version);
+            put_len(&mut buf, interfaces.len())?;
+            for interface in interfaces {
+                put_interface(&mut buf, interface);
+            }
+            // Addresses already listed with their interface aren't repeated
+            let extra: Vec<&IpAddr> = addresses
+                .iter()
+                .filter(|a| !interfaces.iter().any(|i| i.address == **a))
+                .collect();
+            put_len(&mut buf, extra.len())?;
+            for address in extra {
+                put_addr(&mut buf, address);
+            }
+            put_peers(&mut buf, peers)?;
+        }
+        DiscoveryMessage::Goodbye {
+            node_id,
+            reason,
+            timestamp,
+        } => {
+            buf.push(TYPE_GOODBYE);
+            put_str(&mut buf, node_id);
+            put_str(&mut buf, reason);
+            put_time(&mut buf, timestamp)?;
+        }
+        DiscoveryMessage::Peers {
+            node_id,
+            peers,
+            timestamp,
+        } => {
+            buf.push(TYPE_PEERS);
+            put_str(&mut buf, node_id);
+            put_peers(&mut buf, peers)?;
+            put_time(&mut buf, timestamp)?;
+        }
+    }
+
+    Ok(buf)
+}
+
+/// Decode a message payload, ignoring any trailing fields from newer senders
+pub fn decode(payload: &[u8]) -> Result<DiscoveryMessage, PacketError> {
+    let mut reader = Reader { data: payload };
+
+    match reader.u8()? {
+        TYPE_ANNOUNCE => {
+            let node_id = reader.str()?;
+            let hostname = reader.str()?;
+            let port = reader.u16()?;
+            let timestamp = reader.time()?;
+            let version = reader.str()?;
+            let interfaces = (0..reader.u8()?)
+                .map(|_| reader.interface())
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut addresses: Vec<IpAddr> = interfaces.iter().map(|i| i.address).collect();
+            for _ in 0..reader.u8()? {
+                addresses.push(reader.addr()?);
+            }
+            let peers = reader.peers()?;
+
+            Ok(DiscoveryMessage::Announce {
+                node_id,
+                hostname,
+                addresses,
+                port,
+                timestamp,
+                version,
+                peers,
+                interfaces,
+            })
+        }
+        TYPE_GOODBYE => Ok(DiscoveryMessage::Goodbye {
+            node_id: reader.str()?,
+            reason: reader.str()?,
+            timestamp: reader.time()?,
+        }),
+        TYPE_PEERS => Ok(DiscoveryMessage::Peers {
+            node_id: reader.str()?,
+            peers: reader.peers()?,
+            timestamp: reader.time()?,
+        }),
+        other => Err(PacketError::Malformed(format!(
+            "unknown message type {}",
+            other
+        ))),
+    }
+}
+
+/// Split an announcement into messages that each seal to at most MAX_PACKET_SIZE
+///
+/// Interfaces that don't fit are dropped from the end (IPv6 sorts last), and
+/// peer summaries that don't fit go out in follow-up `Peers` messages. Each
+/// follow-up is stamped a millisecond after the one before, so no two
+/// messages share a send time.
+pub fn split_announcement(message: DiscoveryMessage) -> Result<Vec<DiscoveryMessage>, String> {
+    let DiscoveryMessage::Announce {
+        node_id,
+        hostname,
+        mut addresses,
+        port,
+        timestamp,
+        version,
+        peers,
+        mut interfaces,
+    } = message
+    else {
+        return Ok(vec![message]);
+    };
+
+    let budget = MAX_PACKET_SIZE - HEADER_LEN;
+    let mut announce = loop {
+        let announce = DiscoveryMessage::Announce {
+            node_id: node_id.clone(),
+            hostname: hostname.clone(),
+            addresses: addresses.clone(),
+            port,
+            timestamp: timestamp.clone(),
+            version: version.clone(),
+            peers: Vec::new(),
+            interfaces: interfaces.clone(),
+        };
+        if encode(&announce)?.len() <= budget {
+            break announce;
+        }
+        let Some(dropped) = interfaces.pop() else {
+            return Err("announcement does not fit in a packet".to_string());
+        };
+        addresses.retain(|a| *a != dropped.address);
+        tracing::debug!(
+            "Leaving {} out of announcements to stay under {} bytes",
+            dropped.address,
+            MAX_PACKET_SIZE
+        );
+    };
+
+    let empty_peers_len = encode(&DiscoveryMessage::Peers {
+        node_id: node_id.clone(),
+        peers: Vec::new(),
+        timestamp: timestamp.clone(),
+    })?
+    .len();
+    let mut used = encode(&announce)?.len();
+    let mut chunks: Vec<Vec<PeerSummary>> = vec![Vec::new()];
+
+    for peer in peers {
+        let mut encoded = Vec::new();
+        put_peer(&mut encoded, &peer)?;
+        if empty_peers_len + encoded.len() > budget {
+            tracing::debug!("Peer summary for {} is too large to gossip", peer.id);
+            continue;
+        }
+        if used + encoded.len() > budget {
+            chunks.push(Vec::new());
+            used = empty_peers_len;
+        }
+        used += encoded.len();
+        if let Some(chunk) = chunks.last_mut() {
+            chunk.push(peer);
+        }
+    }
+
+    let mut chunks = chunks.into_iter();
+    if let DiscoveryMessage::Announce { peers, .. } = &mut announce {
+        *peers = chunks.next().unwrap_or_default();
+    }
+
+    let sent = DateTime::parse_from_rfc3339(&timestamp)
+        .map_err(|e| format!("invalid timestamp {}: {}", timestamp, e))?
+        .with_timezone(&Utc);
+    let mut messages = vec![announce];
+    for (offset, peers) in (1..).zip(chunks) {
+        messages.push(DiscoveryMessage::Peers {
+            node_id: node_id.clone(),
+            peers,
+            timestamp: (sent + chrono::Duration::milliseconds(offset)).to_rfc3339(),
+        });
+    }
+    Ok(messages)
+}
+
+/// Strings longer than 255 bytes are cut at a character boundary
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    let mut end = s.len().min(u8::MAX as usize);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    let bytes = s.as_bytes().get(..end).unwrap_or_default();
+    buf.push(bytes.len() as u8);
+    buf.extend_from_slice(bytes);
+}
+
+fn put_len(buf: &mut Vec<u8>, len: usize) -> Result<(), String> {
+    let len = u8::try_from(len).map_err(|_| format!("list of {} entries is too long", len))?;
+    buf.push(len);
+    Ok(())
+}
+
+fn put_addr(buf: &mut Vec<u8>, address: &IpAddr) {
+    match address {
+        IpAddr::V4(v4) => {
+            buf.push(4);
+            buf.extend_from_slice(&v4.octets());
+        }
+        IpAddr::V6(v6) => {
+            buf.push(6);
+            buf.extend_from_slice(&v6.octets());
+        }
+    }
+}
+
+fn put_time(buf: &mut Vec<u8>, timestamp: &str) -> Result<(), String> {
+    let millis = DateTime::parse_from_rfc3339(timestamp)
+        .map_err(|e| format!("invalid timestamp {}: {}", timestamp, e))?
+        .timestamp_millis();
+    buf.extend_from_slice(&millis.to_be_bytes());
+    Ok(())
+}
+
+fn put_interface(buf: &mut Vec<u8>, interface: &InterfaceAddress) {
+    put_addr(buf, &interface.address);
+    buf.push(interface.prefix_len);
+    put_str(buf, &interface.interface);
+    buf.push(vpn_code(interface.vpn));
+}
+
+fn put_peer(buf: &mut Vec<u8>, peer: &PeerSummary) -> Result<(), String> {
+    put_str(buf, &peer.id);
+    put_str(buf, &peer.hostname);
+    buf.extend_from_slice(&peer.port.to_be_bytes());
+    put_time(buf, &peer.last_seen)?;
+    put_len(buf, peer.addresses.len())?;
+    for address in &peer.addresses {
+        put_addr(buf, address);
+    }
+    Ok(())
+}
+
+fn put_peers(buf: &mut Vec<u8>, peers: &[PeerSummary]) -> Result<(), String> {
+    put_len(buf, peers.len())?;
+    for peer in peers {
+        put_peer(buf, peer)?;
+    }
+    Ok(())
+}
+
+fn vpn_code(vpn: Option<VpnKind>) -> u8 {
+    match vpn {
+        None => 0,
+        Some(VpnKind::WireGuard) => 1,
+        Some(VpnKind::Tailscale) => 2,
+        Some(VpnKind::ZeroTier) => 3,
+        Some(VpnKind::Tun) => 4,
+        Some(VpnKind::Tap) => 5,
+        Some(VpnKind::Ppp) => 6,
+        Some(VpnKind::Ipsec) => 7,
+    }
+}
+
+/// Unknown codes (VPN kinds added later) read as no VPN
+fn vpn_kind(code: u8) -> Option<VpnKind> {
+    match code {
+        1 => Some(VpnKind::WireGuard),
+        2 => Some(VpnKind::Tailscale),
+        3 => Some(VpnKind::ZeroTier),
+        4 => Some(VpnKind::Tun),
+        5 => Some(VpnKind::Tap),
+        6 => Some(VpnKind::Ppp),
+        7 => Some(VpnKind::Ipsec),
+        _ => None,
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PacketError> {
+        let (head, rest) = self
+            .data
+            .split_at_checked(len)
+            .ok_or_else(|| PacketError::Malformed("truncated payload".to_string()))?;
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], PacketError> {
+        self.take(N)?
+            .try_into()
+            .map_err(|_| PacketError::Malformed("truncated payload".to_string()))
+    }
+
+    fn u8(&mut self) -> Result<u8, PacketError> {
+        Ok(u8::from_be_bytes(self.array()?))
+    }
+
+    fn u16(&mut self) -> Result<u16, PacketError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn str(&mut self) -> Result<String, PacketError> {
+        let len = self.u8()?;
+        String::from_utf8(self.take(len as usize)?.to_vec())
+            .map_err(|_| PacketError::Malformed("invalid UTF-8 string".to_string()))
+    }
+
+    fn time(&mut self) -> Result<String, PacketError> {
+        let millis = i64::from_be_bytes(self.array()?);
+        Utc.timestamp_millis_opt(millis)
+            .single()
+            .map(|time| time.to_rfc3339())
+            .ok_or_else(|| PacketError::Malformed(format!("invalid timestamp {}", millis)))
+    }
+
+    fn addr(&mut self) -> Result<IpAddr, PacketError> {
+        match self.u8()? {
+            4 => Ok(IpAddr::V4(Ipv4Addr::from(self.array::<4>()?))),
+            6 => Ok(IpAddr::V6(Ipv6Addr::from(self.array::<16>()?))),
+            family => Err(PacketError::Malformed(format!(
+                "unknown address family {}",
+                family
+            ))),
+        }
+    }
+
+    fn interface(&mut self) -> Result<InterfaceAddress, PacketError> {
+        Ok(InterfaceAddress {
+            address: self.addr()?,
+            prefix_len: self.u8()?,
+            interface: self.str()?,
+            vpn: vpn_kind(self.u8()?),
+        })
+    }
+
+    fn peers(&mut self) -> Result<Vec<PeerSummary>, PacketError> {
+        (0..self.u8()?)
+            .map(|_| {
+                let id = self.str()?;
+                let hostname = self.str()?;
+                let port = self.u16()?;
+                let last_seen = self.time()?;
+                let addresses = (0..self.u8()?)
+                    .map(|_| self.addr())
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(PeerSummary {
+                    id,
+                    hostname,
+                    addresses,
+                    port,
+                    last_seen,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(n: usize) -> PeerSummary {
+        PeerSummary {
+            id: format!("{:08}-e29b-41d4-a716-446655440000", n),
+            hostname: format!("machine-{}", n),
+            addresses: vec!["10.20.0.10".parse().unwrap(), "fd00::10".parse().unwrap()],
+            port: 8080,
+            last_seen: "2025-11-22T10:29:00.500+00:00".to_string(),
+        }
+    }
+
+    fn announce(peers: Vec<PeerSummary>) -> DiscoveryMessage {
+        DiscoveryMessage::Announce {
+            node_id: "450e8400-e29b-41d4-a716-446655440000".to_string(),
+            hostname: "machine-a".to_string(),
+            addresses: vec![
+                "192.168.1.100".parse().unwrap(),
+                "10.1.1.1".parse().unwrap(),
+            ],
+            port: 8080,
+            timestamp: "2025-11-22T10:30:00.250+00:00".to_string(),
+            version: "0.1.0".to_string(),
+            peers,
+            interfaces: vec![InterfaceAddress {
+                address: "192.168.1.100".parse().unwrap(),
+                interface: "eth0".to_string(),
+                prefix_len: 24,
+                vpn: Some(VpnKind::WireGuard),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let payload = encode(&announce(vec![peer(1)])).unwrap();
+        let DiscoveryMessage::Announce {
+            addresses,
+            timestamp,
+            peers,
+            interfaces,
+            ..
+        } = decode(&payload).unwrap()
+        else {
+            panic!("expected an announcement");
+        };
+
+        assert_eq!(addresses.len(), 2);
+        assert_eq!(timestamp, "2025-11-22T10:30:00.250+00:00");
+        assert_eq!(
+            interfaces.first().and_then(|i| i.vpn),
+            Some(VpnKind::WireGuard)
+        );
+        assert_eq!(peers.first().map(|p| p.addresses.len()), Some(2));
+
+        // Fields appended by a newer sender are ignored
+        let mut extended = payload.clone();
+        extended.extend_from_slice(b"future");
+        assert!(decode(&extended).is_ok());
+
+        for len in 0..payload.len() {
+            assert!(decode(payload.get(..len).unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_goodbye_without_timestamp() {
+        // Nothing to check for replay, so it's not accepted
+        let mut payload = vec![TYPE_GOODBYE];
+        put_str(&mut payload, "node-a");
+        put_str(&mut payload, "shutdown");
+
+        assert!(matches!(decode(&payload), Err(PacketError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_split_announcement() {
+        let peers: Vec<PeerSummary> = (0..32).map(peer).collect();
+        let messages = split_announcement(announce(peers)).unwrap();
+
+        assert!(messages.len() > 1);
+        let mut gossiped = 0;
+        for message in &messages {
+            assert!(HEADER_LEN + encode(message).unwrap().len() <= MAX_PACKET_SIZE);
+            gossiped += match message {
+                DiscoveryMessage::Announce { peers, .. }
+                | DiscoveryMessage::Peers { peers, .. } => peers.len(),
+                DiscoveryMessage::Goodbye { .. } => 0,
+            };
+        }
+        assert_eq!(gossiped, 32);
+
+        let mut times: Vec<i64> = messages
+            .iter()
+            .map(|m| {
+                DateTime::parse_from_rfc3339(m.timestamp())
+                    .unwrap()
+                    .timestamp_millis()
+            })
+            .collect();
+        times.dedup();
+        assert_eq!(times.len(), messages.len());
+        assert!(matches!(
+            messages.first(),
+            Some(DiscoveryMessage::Announce { .. })
+        ));
+    }
+
+    #[test]
+    fn test_packet_stats() {
+        let stats = PacketStats::default();
+        stats.record(&Ok::<(), PacketError>(()));
+        stats.record(&Err::<(), _>(PacketError::Oversized(9000)));
+        stats.record(&Err::<(), _>(PacketError::UnsupportedVersion(3)));
+
+        let counts = stats.counts();
+        assert_eq!(counts.received, 3);
+        assert_eq!(counts.accepted, 1);
+        assert_eq!(counts.oversized, 1);
+        assert_eq!(counts.unsupported_version, 1);
+    }
+}
@@ -1,175 +1,453 @@
-// WireGuard peer discovery
-// Parses `wg show` output to discover WireGuard peers for unicast discovery
-// (since multicast doesn't work over WireGuard tunnels)
-
+// WireGuard peer discovery and health
+// Parses `wg show all dump` for every interface and peer: endpoints and
+// tunnel addresses (host allowed IPs) for unicast discovery, since multicast
+// doesn't cross WireGuard tunnels, and handshake times and transfer counters
+// for /api/wireguard.
+// Allowed IPs are also matched against the routing table so each route over a
+// WireGuard interface shows which peers it actually reaches
+
+use chrono::{DateTime, TimeZone, Utc};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
 use std::process::Command;
 use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+
+use super::source::{self, DiscoverySource};
+use super::{NodeInfo, PeerRegistry};
+use crate::config::Config;
+use crate::diagnostics::firewall::{self, PolicyRules};
+use crate::routes::lookup::{matches_cidr, RouteEngine};
+use crate::routes::{parser, Route, RoutingTable, WireGuardTarget};
+
+/// WireGuard re-handshakes every 2 minutes while traffic flows and drops a
+/// session after 3, so an older handshake means the tunnel is down (or idle
+/// without persistent keepalive)
+pub const STALE_HANDSHAKE_SECS: i64 = 180;
+
+/// How often the snapshot discovery works from is taken again
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+
+static SNAPSHOT: RwLock<Option<Arc<WireGuardSnapshot>>> = RwLock::new(None);
+
+/// WireGuard interfaces and the local routes, as discovery last saw them
+///
+/// Discovery consults this for every packet it sends and receives, so it is
+/// taken by a background task and only read on those paths.
+#[derive(Default)]
+pub struct WireGuardSnapshot {
+    pub interfaces: Vec<WireGuardInterface>,
+    /// The local routing table, None if it couldn't be read (or there is no
+    /// WireGuard interface to look it up for)
+    pub routes: Option<RouteEngine>,
+    /// `ip rule`, which wg-quick uses to send everything into a full tunnel
+    pub policy: PolicyRules,
+    /// The tables the policy rules name, main included
+    pub policy_tables: HashMap<String, RoutingTable>,
+}
+
+impl WireGuardSnapshot {
+    /// The route our own traffic to `destination` takes, following the policy
+    /// rules when there are any
+    fn route(&self, destination: IpAddr) -> Option<Route> {
+        let rules = self.policy.for_destination(destination);
+        if rules.is_empty() {
+            return self.routes.as_ref()?.lookup(destination).cloned();
+        }
+        firewall::select_policy_route(rules, 0, destination, |table| {
+            self.policy_tables.get(table).cloned()
+        })
+        .map(|(_, route)| route)
+    }
+}
+
+/// Which side of a WireGuard tunnel a peer's announcement arrived on
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireGuardPath {
+    /// From the peer's tunnel address (one of its allowed IPs)
+    Tunnel,
+    /// From the peer's endpoint, the UDP address the tunnel itself runs over
+    Underlay,
+}
 
 /// Information about a WireGuard peer
-#[derive(Debug, Clone)]
-#[allow(dead_code)] // Fields kept for debugging and future use
+#[derive(Debug, Clone, Serialize)]
 pub struct WireGuardPeer {
     /// The WireGuard interface this peer belongs to
     pub interface: String,
-    /// The peer's public key (truncated for logging)
     pub public_key: String,
     /// The peer's endpoint (IP:port) if known
     pub endpoint: Option<SocketAddr>,
     /// Allowed IPs for this peer
     pub allowed_ips: Vec<String>,
+    /// None if the peer never completed a handshake
+    pub latest_handshake: Option<String>,
+    pub handshake_age_secs: Option<i64>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Keepalive interval in seconds, None when off
+    pub persistent_keepalive: Option<u16>,
+    /// No handshake within STALE_HANDSHAKE_SECS
+    pub stale: bool,
 }
 
 /// Information about a WireGuard interface
-#[derive(Debug, Clone)]
-#[allow(dead_code)] // Fields kept for debugging and future use
+#[derive(Debug, Clone, Serialize)]
 pub struct WireGuardInterface {
     pub name: String,
+    pub public_key: String,
+    pub listen_port: Option<u16>,
     pub peers: Vec<WireGuardPeer>,
 }
 
-/// Get all WireGuard interfaces and their peers
-pub fn get_wireguard_interfaces() -> Vec<WireGuardInterface> {
-    let mut interfaces = Vec::new();
+#[derive(Debug, Clone, Serialize)]
+pub struct WireGuardReport {
+    pub interfaces: Vec<WireGuardInterface>,
+    /// Peers across all interfaces without a recent handshake
+    pub stale_peers: usize,
+    pub timestamp: String,
+}
+
+/// Interfaces and peer health for /api/wireguard
+pub fn wireguard_report() -> Result<WireGuardReport, String> {
+    let interfaces = read_wireguard_interfaces()?;
+    let stale_peers = interfaces
+        .iter()
+        .flat_map(|iface| &iface.peers)
+        .filter(|peer| peer.stale)
+        .count();
+
+    Ok(WireGuardReport {
+        interfaces,
+        stale_peers,
+        timestamp: Utc::now().to_rfc3339(),
+    })
+}
 
-    // First, get list of WireGuard interface names
-    let interface_names = get_wireguard_interface_names();
+/// Read every WireGuard interface and peer with `wg show all dump`
+pub fn read_wireguard_interfaces() -> Result<Vec<WireGuardInterface>, String> {
+    let output = Command::new("wg")
+        .args(["show", "all", "dump"])
+        .output()
+        .map_err(|e| format!("Failed to execute wg: {}", e))?;
 
-    for iface_name in interface_names {
-        if let Some(iface) = parse_wireguard_interface(&iface_name) {
-            interfaces.push(iface);
-        }
+    if !output.status.success() {
+        return Err(format!(
+            "wg show failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
     }
 
-    interfaces
+    Ok(parse_dump(
+        &String::from_utf8_lossy(&output.stdout),
+        Utc::now(),
+    ))
 }
 
-/// Get all known WireGuard peer endpoints for unicast discovery
-pub fn get_wireguard_peer_endpoints() -> Vec<SocketAddr> {
-    let interfaces = get_wireguard_interfaces();
-    let mut endpoints = Vec::new();
+/// The latest snapshot, empty until the snapshot task has taken one
+pub fn snapshot() -> Arc<WireGuardSnapshot> {
+    SNAPSHOT
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_default()
+}
 
-    for iface in interfaces {
-        for peer in iface.peers {
-            if let Some(endpoint) = peer.endpoint {
-                // Use the endpoint IP but with our discovery port
-                // The actual port will be set by the caller
-                endpoints.push(endpoint);
-            }
-        }
+/// Run `wg show` and read the routing table; blocking
+fn take_snapshot() -> WireGuardSnapshot {
+    let interfaces = read_wireguard_interfaces().unwrap_or_else(|e| {
+        tracing::debug!("No WireGuard information: {}", e);
+        Vec::new()
+    });
+    if interfaces.is_empty() {
+        return WireGuardSnapshot::default();
+    }
+
+    let Ok(main) = parser::get_routing_table()
+        .map_err(|e| tracing::debug!("No routing table for WireGuard sources: {}", e))
+    else {
+        return WireGuardSnapshot {
+            interfaces,
+            ..WireGuardSnapshot::default()
+        };
+    };
+    let policy = firewall::read_policy_rules();
+    let mut policy_tables: HashMap<String, RoutingTable> = policy
+        .v4
+        .iter()
+        .chain(&policy.v6)
+        .filter(|rule| rule.table != "main")
+        .filter_map(|rule| {
+            let table = parser::get_policy_routing_table(&rule.table).ok()?;
+            Some((rule.table.clone(), table))
+        })
+        .collect();
+    let routes = Some(RouteEngine::new(&main));
+    policy_tables.insert("main".to_string(), main);
+
+    WireGuardSnapshot {
+        interfaces,
+        routes,
+        policy,
+        policy_tables,
     }
+}
+
+/// Retake the snapshot every SNAPSHOT_INTERVAL until shutdown
+pub fn start_snapshot_task(mut shutdown: watch::Receiver<bool>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut snapshot_interval = interval(SNAPSHOT_INTERVAL);
 
-    endpoints
+        loop {
+            tokio::select! {
+                _ = snapshot_interval.tick() => {}
+                _ = shutdown.wait_for(|stopping| *stopping) => break,
+            }
+
+            match tokio::task::spawn_blocking(take_snapshot).await {
+                Ok(snapshot) => {
+                    *SNAPSHOT.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(snapshot));
+                }
+                Err(e) => tracing::debug!("WireGuard snapshot task failed: {}", e),
+            }
+        }
+    })
 }
 
-/// Get WireGuard peer IPs (without port) for discovery announcements
+/// Get WireGuard peer endpoint IPs (the underlay) for discovery announcements
 pub fn get_wireguard_peer_ips() -> Vec<IpAddr> {
-    get_wireguard_peer_endpoints()
-        .into_iter()
-        .map(|addr| addr.ip())
+    snapshot()
+        .interfaces
+        .iter()
+        .flat_map(|iface| &iface.peers)
+        .filter_map(|peer| peer.endpoint)
+        .map(|endpoint| endpoint.ip())
         .collect()
 }
 
-/// Get list of WireGuard interface names on the system
-fn get_wireguard_interface_names() -> Vec<String> {
-    // Try `wg show interfaces` first
-    if let Ok(output) = Command::new("wg").args(["show", "interfaces"]).output() {
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            return stdout.split_whitespace().map(String::from).collect();
-        }
-    }
+/// Get WireGuard peer tunnel addresses for discovery announcements
+pub fn get_wireguard_tunnel_ips() -> Vec<IpAddr> {
+    snapshot()
+        .interfaces
+        .iter()
+        .flat_map(|iface| &iface.peers)
+        .flat_map(tunnel_addresses)
+        .collect()
+}
 
-    // Fallback: look for wg* interfaces in /sys/class/net (Linux)
-    #[cfg(target_os = "linux")]
-    {
-        if let Ok(entries) = std::fs::read_dir("/sys/class/net") {
-            return entries
-                .filter_map(|e| e.ok())
-                .filter_map(|e| {
-                    let name = e.file_name().to_string_lossy().to_string();
-                    if name.starts_with("wg") {
-                        Some(name)
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-        }
+/// A peer's own tunnel addresses: its /32 and /128 allowed IPs
+///
+/// Wider allowed IPs are networks routed through the peer, not the peer itself.
+pub fn tunnel_addresses(peer: &WireGuardPeer) -> Vec<IpAddr> {
+    peer.allowed_ips
+        .iter()
+        .filter_map(|allowed| {
+            let (address, prefix) = allowed.split_once('/')?;
+            let address: IpAddr = address.parse().ok()?;
+            let host_prefix = match address {
+                IpAddr::V4(_) => "32",
+                IpAddr::V6(_) => "128",
+            };
+            (prefix == host_prefix).then_some(address)
+        })
+        .collect()
+}
+
+/// Tell whether a datagram from `source` came through a WireGuard tunnel or
+/// over a peer's underlay endpoint; None if it has nothing to do with WireGuard
+///
+/// Being inside a peer's allowed IPs isn't enough for the tunnel: site-to-site
+/// allowed IPs often cover the LAN this host sits on as well, and hosts on it
+/// talk to us directly. The local route to the source, through `ip rule` as
+/// wg-quick sets it up for a full tunnel, has to go out of the peer's
+/// interface too. A peer's endpoint is always the underlay, since WireGuard
+/// keeps its own packets out of the tunnel.
+pub fn classify_source(source: IpAddr, snapshot: &WireGuardSnapshot) -> Option<WireGuardPath> {
+    let peers = || snapshot.interfaces.iter().flat_map(|i| &i.peers);
+    if peers().any(|peer| peer.endpoint.is_some_and(|e| e.ip() == source)) {
+        return Some(WireGuardPath::Underlay);
     }
 
-    Vec::new()
+    let route = snapshot.route(source)?;
+    peers()
+        .any(|peer| {
+            route.interface == peer.interface
+                && peer
+                    .allowed_ips
+                    .iter()
+                    .any(|allowed| matches_cidr(allowed, source).is_some())
+        })
+        .then_some(WireGuardPath::Tunnel)
 }
 
-/// Parse WireGuard interface details using `wg show <interface>`
-fn parse_wireguard_interface(interface_name: &str) -> Option<WireGuardInterface> {
-    let output = Command::new("wg")
-        .args(["show", interface_name])
-        .output()
-        .ok()?;
-
-    if !output.status.success() {
-        tracing::debug!("Failed to get WireGuard info for {}", interface_name);
-        return None;
+/// Tag routes over WireGuard interfaces with the peers they reach
+///
+/// WireGuard hands a packet to the peer with the most specific allowed IP
+/// covering its destination, so a route reaches each peer on its interface
+/// whose allowed IPs overlap the route's destination.
+pub fn annotate_routes(routes: &mut [Route], interfaces: &[WireGuardInterface]) {
+    for route in routes {
+        let Some(iface) = interfaces.iter().find(|i| i.name == route.interface) else {
+            continue;
+        };
+
+        route.wireguard_peers = iface
+            .peers
+            .iter()
+            .filter_map(|peer| {
+                let allowed_ip = peer
+                    .allowed_ips
+                    .iter()
+                    .find(|allowed| cidrs_overlap(&route.destination, allowed))?;
+                Some(WireGuardTarget {
+                    public_key: peer.public_key.clone(),
+                    endpoint: peer.endpoint,
+                    allowed_ip: allowed_ip.clone(),
+                    stale: peer.stale,
+                })
+            })
+            .collect();
     }
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut peers = Vec::new();
-    let mut current_peer: Option<WireGuardPeer> = None;
-
-    for line in stdout.lines() {
-        let line = line.trim();
+/// Whether two CIDRs share any address: one must contain the other's network
+fn cidrs_overlap(a: &str, b: &str) -> bool {
+    let network = |cidr: &str| {
+        cidr.split('/')
+            .next()
+            .and_then(|n| n.parse::<IpAddr>().ok())
+    };
+    let contains = |outer: &str, inner: &str| {
+        network(inner).is_some_and(|ip| matches_cidr(outer, ip).is_some())
+    };
+    contains(a, b) || contains(b, a)
+}
 
-        if line.starts_with("peer:") {
-            // Save previous peer if exists
-            if let Some(peer) = current_peer.take() {
-                peers.push(peer);
+/// Parse `wg show all dump`
+///
+/// Each line is tab-separated and starts with the interface name. Interface
+/// lines carry the private key, public key, listen port and fwmark; peer lines
+/// the public key, preshared key, endpoint, allowed IPs, latest handshake (unix
+/// seconds, 0 for never), rx bytes, tx bytes and persistent keepalive. Keys
+/// other than public keys are never kept.
+fn parse_dump(output: &str, now: DateTime<Utc>) -> Vec<WireGuardInterface> {
+    let mut interfaces: Vec<WireGuardInterface> = Vec::new();
+
+    for line in output.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+
+        match fields.as_slice() {
+            [name, _private_key, public_key, listen_port, _fwmark] => {
+                interfaces.push(WireGuardInterface {
+                    name: name.to_string(),
+                    public_key: public_key.to_string(),
+                    listen_port: listen_port.parse().ok(),
+                    peers: Vec::new(),
+                });
             }
-
-            // Start new peer
-            let public_key = line
-                .strip_prefix("peer:")
-                .map(|s| s.trim().to_string())
-                .unwrap_or_default();
-
-            current_peer = Some(WireGuardPeer {
-                interface: interface_name.to_string(),
-                public_key,
-                endpoint: None,
-                allowed_ips: Vec::new(),
-            });
-        } else if let Some(ref mut peer) = current_peer {
-            if line.starts_with("endpoint:") {
-                if let Some(endpoint_str) = line.strip_prefix("endpoint:") {
-                    let endpoint_str = endpoint_str.trim();
-                    // Parse endpoint (format: IP:port or [IPv6]:port)
-                    peer.endpoint = parse_endpoint(endpoint_str);
-                }
-            } else if line.starts_with("allowed ips:") {
-                if let Some(ips_str) = line.strip_prefix("allowed ips:") {
-                    peer.allowed_ips = ips_str
+            [name, public_key, _preshared_key, endpoint, allowed_ips, handshake, rx, tx, keepalive] =>
+            {
+                let latest_handshake = handshake
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|&secs| secs > 0)
+                    .and_then(|secs| Utc.timestamp_opt(secs, 0).single());
+                let handshake_age_secs =
+                    latest_handshake.map(|at| now.signed_duration_since(at).num_seconds());
+
+                let peer = WireGuardPeer {
+                    interface: name.to_string(),
+                    public_key: public_key.to_string(),
+                    endpoint: parse_endpoint(endpoint),
+                    allowed_ips: allowed_ips
                         .split(',')
                         .map(|s| s.trim().to_string())
-                        .filter(|s| !s.is_empty())
-                        .collect();
+                        .filter(|s| !s.is_empty() && s != "(none)")
+                        .collect(),
+                    latest_handshake: latest_handshake.map(|at| at.to_rfc3339()),
+                    handshake_age_secs,
+                    rx_bytes: rx.parse().unwrap_or_default(),
+                    tx_bytes: tx.parse().unwrap_or_default(),
+                    persistent_keepalive: keepalive.parse().ok(),
+                    stale: handshake_age_secs.is_none_or(|age| age > STALE_HANDSHAKE_SECS),
+                };
+
+                match interfaces.iter_mut().find(|i| i.name == *name) {
+                    Some(iface) => iface.peers.push(peer),
+                    None => tracing::debug!("WireGuard peer for unknown interface {}", name),
+                }
+            }
+            _ => {
+                if !line.trim().is_empty() {
+                    tracing::debug!("Unrecognised wg dump line with {} fields", fields.len());
                 }
             }
         }
     }
 
-    // Don't forget the last peer
-    if let Some(peer) = current_peer {
-        peers.push(peer);
+    interfaces
+}
+
+/// Unicast announcements to WireGuard peers, through the tunnel and to their endpoints
+pub struct WireGuardSource {
+    config: Arc<Config>,
+}
+
+impl WireGuardSource {
+    pub fn new(config: Arc<Config>) -> Self {
+        WireGuardSource { config }
+    }
+}
+
+impl DiscoverySource for WireGuardSource {
+    fn name(&self) -> &'static str {
+        "wireguard"
     }
 
-    Some(WireGuardInterface {
-        name: interface_name.to_string(),
-        peers,
-    })
+    fn interval(&self) -> Option<Duration> {
+        // Peers come from the snapshot, which keeps itself up to date
+        None
+    }
+
+    fn discover<'a>(
+        &'a self,
+        _peer_registry: &'a PeerRegistry,
+    ) -> BoxFuture<'a, Result<Vec<NodeInfo>, String>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    fn announce<'a>(&'a self, socket: &'a UdpSocket, message: &'a [u8]) -> BoxFuture<'a, usize> {
+        Box::pin(async move {
+            // Tunnel addresses first: when both arrive, the copy over the
+            // underlay is dropped as a duplicate, so the node is marked as
+            // reached through the tunnel whenever the tunnel works
+            let mut targets = get_wireguard_tunnel_ips();
+            for ip in get_wireguard_peer_ips() {
+                if !targets.contains(&ip) {
+                    targets.push(ip);
+                }
+            }
+            if !targets.is_empty() {
+                tracing::debug!(
+                    "Sending unicast discovery to {} WireGuard peer addresses",
+                    targets.len()
+                );
+            }
+            source::send_unicast(socket, message, &targets, self.config.multicast_port).await
+        })
+    }
 }
 
 /// Parse a WireGuard endpoint string (IP:port or [IPv6]:port)
-fn parse_endpoint(endpoint_str: &str) -> Option<SocketAddr> {
+pub fn parse_endpoint(endpoint_str: &str) -> Option<SocketAddr> {
     // Try direct parsing first
     if let Ok(addr) = SocketAddr::from_str(endpoint_str) {
         return Some(addr);
@@ -205,6 +483,171 @@ fn parse_endpoint(endpoint_str: &str) -> Option<SocketAddr> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::routes::test_support::{route, table};
+
+    const IP_RULES: &str = include_str!("../diagnostics/fixtures/ip_rule.json");
+
+    const DUMP: &str = "wg0\tcHJpdmF0ZQ==\tc2VydmVy\t51820\toff
+wg0\tcGVlcmE=\t(none)\t203.0.113.5:51820\t10.8.0.2/32,10.20.0.0/24\t1763807400\t1024\t2048\t25
+wg0\tcGVlcmI=\t(none)\t(none)\t10.8.0.3/32\t0\t0\t0\toff
+";
+
+    fn now() -> DateTime<Utc> {
+        // 100 seconds after peer A's handshake
+        Utc.timestamp_opt(1763807500, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_dump() {
+        let interfaces = parse_dump(DUMP, now());
+        assert_eq!(interfaces.len(), 1);
+        let wg0 = interfaces.first().unwrap();
+        assert_eq!(wg0.listen_port, Some(51820));
+        assert_eq!(wg0.public_key, "c2VydmVy");
+
+        let [a, b] = wg0.peers.as_slice() else {
+            panic!("expected two peers");
+        };
+        assert_eq!(a.endpoint, parse_endpoint("203.0.113.5:51820"));
+        assert_eq!(a.allowed_ips, vec!["10.8.0.2/32", "10.20.0.0/24"]);
+        assert_eq!(a.handshake_age_secs, Some(100));
+        assert_eq!((a.rx_bytes, a.tx_bytes), (1024, 2048));
+        assert_eq!(a.persistent_keepalive, Some(25));
+        assert!(!a.stale);
+
+        assert!(b.endpoint.is_none());
+        assert!(b.latest_handshake.is_none());
+        assert_eq!(b.persistent_keepalive, None);
+        assert!(b.stale);
+    }
+
+    #[test]
+    fn test_annotate_routes() {
+        let mut routes = vec![
+            route("10.8.0.0/24", None, "wg0"),
+            route("10.20.0.0/16", None, "wg0"),
+            route("10.8.0.0/24", None, "eth0"),
+        ];
+        annotate_routes(&mut routes, &parse_dump(DUMP, now()));
+
+        let keys = |route: &Route| -> Vec<String> {
+            route
+                .wireguard_peers
+                .iter()
+                .map(|p| p.public_key.clone())
+                .collect()
+        };
+        let [subnet, lan, other] = routes.as_slice() else {
+            panic!("expected three routes");
+        };
+        assert_eq!(keys(subnet), vec!["cGVlcmE=", "cGVlcmI="]);
+        assert_eq!(keys(lan), vec!["cGVlcmE="]);
+        assert_eq!(
+            lan.wireguard_peers.first().map(|p| p.allowed_ip.as_str()),
+            Some("10.20.0.0/24")
+        );
+        assert!(other.wireguard_peers.is_empty());
+    }
+
+    #[test]
+    fn test_tunnel_addresses() {
+        let interfaces = parse_dump(DUMP, now());
+        let peers: Vec<&WireGuardPeer> = interfaces.iter().flat_map(|i| &i.peers).collect();
+        let [a, b] = peers.as_slice() else {
+            panic!("expected two peers");
+        };
+
+        // The /24 routed behind peer A is not an address of A itself
+        assert_eq!(
+            tunnel_addresses(a),
+            vec!["10.8.0.2".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            tunnel_addresses(b),
+            vec!["10.8.0.3".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_classify_source() {
+        // Peer A's allowed IPs also cover the LAN this host is on
+        let dump = DUMP.replace("10.20.0.0/24", "192.168.0.0/16");
+        let snapshot = WireGuardSnapshot {
+            interfaces: parse_dump(&dump, now()),
+            routes: Some(RouteEngine::new(&table(vec![
+                route("0.0.0.0/0", Some("192.168.1.1"), "eth0"),
+                route("192.168.1.0/24", None, "eth0"),
+                route("10.8.0.0/24", None, "wg0"),
+                route("192.168.0.0/16", None, "wg0"),
+            ]))),
+            ..WireGuardSnapshot::default()
+        };
+
+        let classify = |ip: &str| classify_source(ip.parse().unwrap(), &snapshot);
+        assert_eq!(classify("10.8.0.2"), Some(WireGuardPath::Tunnel));
+        assert_eq!(classify("192.168.7.20"), Some(WireGuardPath::Tunnel));
+        assert_eq!(classify("203.0.113.5"), Some(WireGuardPath::Underlay));
+        // A LAN neighbour is reached directly even though peer A covers it
+        assert_eq!(classify("192.168.1.20"), None);
+        assert_eq!(classify("198.51.100.7"), None);
+
+        // Without the routing table nothing can be confirmed as tunnelled
+        let unrouted = WireGuardSnapshot {
+            interfaces: parse_dump(&dump, now()),
+            ..WireGuardSnapshot::default()
+        };
+        assert_eq!(
+            classify_source("10.8.0.2".parse().unwrap(), &unrouted),
+            None
+        );
+    }
+
+    #[test]
+    fn test_classify_source_wg_quick_full_tunnel() {
+        // wg-quick: everything goes to the peer, and unmarked traffic is sent
+        // to table 51820 while main keeps the physical default route
+        let dump = "wg0\tcHJpdmF0ZQ==\tc2VydmVy\t51820\toff
+wg0\tcGVlcmE=\t(none)\t203.0.113.5:51820\t0.0.0.0/0\t1763807400\t1024\t2048\t25
+";
+        let main = table(vec![
+            route("0.0.0.0/0", Some("192.168.1.1"), "eth0"),
+            route("192.168.1.0/24", None, "eth0"),
+            route("10.8.0.0/24", None, "wg0"),
+        ]);
+        let snapshot = WireGuardSnapshot {
+            interfaces: parse_dump(dump, now()),
+            routes: Some(RouteEngine::new(&main)),
+            policy: PolicyRules {
+                v4: firewall::parse_ip_rules(IP_RULES).unwrap(),
+                v6: Vec::new(),
+            },
+            policy_tables: HashMap::from([
+                ("main".to_string(), main.clone()),
+                (
+                    "51820".to_string(),
+                    table(vec![route("0.0.0.0/0", None, "wg0")]),
+                ),
+            ]),
+        };
+
+        let classify = |ip: &str| classify_source(ip.parse().unwrap(), &snapshot);
+        assert_eq!(classify("10.8.0.1"), Some(WireGuardPath::Tunnel));
+        // A node on the far side of the peer, reached by the tunnel's default route
+        assert_eq!(classify("198.51.100.7"), Some(WireGuardPath::Tunnel));
+        assert_eq!(classify("203.0.113.5"), Some(WireGuardPath::Underlay));
+        assert_eq!(classify("192.168.1.20"), None);
+
+        // The main table alone doesn't show the tunnel
+        let main_only = WireGuardSnapshot {
+            interfaces: parse_dump(dump, now()),
+            routes: Some(RouteEngine::new(&main)),
+            ..WireGuardSnapshot::default()
+        };
+        assert_eq!(
+            classify_source("198.51.100.7".parse().unwrap(), &main_only),
+            None
+        );
+    }
 
     #[test]
     fn test_parse_endpoint_ipv4() {
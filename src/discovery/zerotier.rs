@@ -0,0 +1,127 @@
+// ZeroTier peers from `zerotier-cli listpeers -j`
+// The node only knows the physical paths to its peers, not the addresses they
+// hold on each network, so announcements go to the active path instead: the
+// same underlay address ZeroTier itself talks to. Roots (PLANET and MOON) are
+// infrastructure, not peers, and are left out
+
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+
+use super::vpn_peers::{self, VpnPeer};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Peer {
+    address: String,
+    #[serde(default)]
+    latency: i64,
+    #[serde(default)]
+    paths: Vec<Path>,
+    #[serde(default)]
+    role: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Path {
+    /// "IP/port"
+    address: String,
+    #[serde(default)]
+    active: bool,
+    #[serde(default)]
+    expired: bool,
+    #[serde(default)]
+    preferred: bool,
+    /// Unix milliseconds
+    #[serde(default)]
+    last_receive: i64,
+}
+
+/// Read this node's peers with `zerotier-cli listpeers -j`
+pub fn read_peers() -> Result<Vec<VpnPeer>, String> {
+    parse_peers(&vpn_peers::command_output(
+        "zerotier-cli",
+        &["listpeers", "-j"],
+    )?)
+}
+
+/// Parse `zerotier-cli listpeers -j`
+fn parse_peers(output: &str) -> Result<Vec<VpnPeer>, String> {
+    let peers: Vec<Peer> = serde_json::from_str(output)
+        .map_err(|e| format!("Invalid zerotier-cli listpeers output: {}", e))?;
+
+    Ok(peers
+        .into_iter()
+        .filter(|peer| peer.role == "LEAF")
+        .map(|peer| {
+            let live: Vec<&Path> = peer
+                .paths
+                .iter()
+                .filter(|path| path.active && !path.expired)
+                .collect();
+            let endpoint = live
+                .iter()
+                .find(|path| path.preferred)
+                .or(live.first())
+                .and_then(|path| parse_path(&path.address));
+            let last_receive = peer.paths.iter().map(|path| path.last_receive).max();
+
+            VpnPeer {
+                id: peer.address,
+                hostname: None,
+                tunnel_addresses: Vec::new(),
+                endpoint,
+                relay: None,
+                online: !live.is_empty(),
+                last_seen: last_receive
+                    .filter(|&ms| ms > 0)
+                    .and_then(|ms| Utc.timestamp_millis_opt(ms).single())
+                    .map(|at| at.to_rfc3339()),
+                // -1 until the peer has been measured
+                latency_ms: u64::try_from(peer.latency).ok(),
+                rx_bytes: None,
+                tx_bytes: None,
+            }
+        })
+        .collect())
+}
+
+/// Parse a ZeroTier path address, "IP/port"
+fn parse_path(address: &str) -> Option<SocketAddr> {
+    let (ip, port) = address.rsplit_once('/')?;
+    Some(SocketAddr::new(
+        ip.parse::<IpAddr>().ok()?,
+        port.parse().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTPEERS: &str = include_str!("fixtures/zerotier_listpeers.json");
+
+    #[test]
+    fn test_parse_peers() {
+        let peers = parse_peers(LISTPEERS).unwrap();
+        let [direct, offline, v6] = peers.as_slice() else {
+            panic!("expected three leaf peers");
+        };
+
+        assert_eq!(direct.id, "a1b2c3d4e5");
+        // The expired LAN path is skipped for the live one
+        assert_eq!(direct.endpoint, parse_path("203.0.113.20/9993"));
+        assert!(direct.online);
+        assert_eq!(direct.latency_ms, Some(12));
+        assert!(direct.last_seen.is_some());
+
+        assert!(!offline.online);
+        assert_eq!(offline.endpoint, None);
+        assert_eq!(offline.latency_ms, None);
+        assert_eq!(offline.last_seen, None);
+
+        assert_eq!(v6.endpoint, Some("[2001:db8:5::20]:21002".parse().unwrap()));
+        assert!(parse_peers("{}").is_err());
+    }
+}
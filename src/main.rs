@@ -6,25 +6,41 @@
 
 mod api;
 mod config;
+mod diagnostics;
 mod discovery;
 mod routes;
 
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tower_http::services::ServeDir;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use api::rest::AppState;
 use config::Config;
+use diagnostics::leak::LeakCheckService;
 use discovery::{
-    bandwidth::BandwidthService, broadcast::DiscoveryService, gossip::GossipService,
-    ping::PingService, PeerRegistry,
+    bandwidth::BandwidthService,
+    broadcast::{DiscoveryService, MulticastSource},
+    gossip::{GossipService, GossipSource},
+    mdns::MdnsSource,
+    ping::PingService,
+    seeds::StaticSource,
+    source::SourceRegistry,
+    trust::TrustStore,
+    vpn_peers::{VpnPeerSource, VpnTool},
+    vpn_scan::VpnScanSource,
+    wireguard::{self, WireGuardSource},
+    PeerRegistry,
 };
 
+/// How long to wait for background tasks once shutdown starts
+const SHUTDOWN_TIMEOUT_SECS: u64 = 3;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Load configuration from all sources
-    let config = Config::load()?;
+    let config = Arc::new(Config::load()?);
 
     // Initialize logging with configured level
     tracing_subscriber::registry()
@@ -58,12 +74,47 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Hostname: {}", hostname);
 
     // Initialize peer registry
-    let peer_registry = Arc::new(PeerRegistry::new());
+    let node_id =
+        discovery::identity::load_or_create_node_id(&config.state_dir, config.node_id.as_deref());
+    let peer_registry = Arc::new(PeerRegistry::new(node_id).with_max_nodes(config.max_peers));
     let local_node_id = peer_registry.local_node_id().to_string();
-    tracing::info!("Local node ID: {}", local_node_id);
+    tracing::info!(
+        "Local node ID: {} (state dir: {})",
+        local_node_id,
+        config.state_dir.display()
+    );
+
+    // Signing key for discovery messages, and the keys we trust from peers
+    let signing_key = discovery::identity::load_or_create_signing_key(&config.state_dir);
+    let public_key = hex::encode(signing_key.verifying_key().as_bytes());
+    tracing::info!("Discovery public key: {}", public_key);
+    let trust_store = Arc::new(TrustStore::new(
+        config.trust_mode,
+        local_node_id.clone(),
+        public_key.clone(),
+        &config.trusted_keys,
+        Some(config.state_dir.clone()),
+    ));
 
     // Initialize application state (without bandwidth service first)
-    let state = Arc::new(AppState::new(peer_registry.clone()));
+    let mut state = AppState::new(peer_registry.clone()).with_trust_store(trust_store.clone());
+
+    // Pick the discovery mechanisms to use
+    let sources = if config.discovery_enabled {
+        let sources = Arc::new(discovery_sources(
+            &config,
+            &local_node_id,
+            &hostname,
+            &peer_registry,
+            &state,
+        ));
+        tracing::info!("Discovery sources: {}", sources.names().join(", "));
+        state = state.with_discovery_sources(sources.clone());
+        Some(sources)
+    } else {
+        None
+    };
+    let state = Arc::new(state);
 
     // Configure server address
     let addr = SocketAddr::from((
@@ -78,35 +129,76 @@ async fn main() -> anyhow::Result<()> {
     ));
     tracing::info!("Server will bind to: {}", addr);
 
+    // Background tasks to wait for on shutdown
+    let mut tasks = Vec::new();
+
     // Start discovery service (if enabled)
-    if config.discovery_enabled {
+    if let Some(sources) = sources {
         tracing::info!("Starting node discovery service...");
-        let discovery = DiscoveryService::new(local_node_id.clone(), hostname.clone(), config.port);
-
-        if let Err(e) = discovery.start_announcing(peer_registry.clone()).await {
-            tracing::error!("Failed to start discovery announcements: {}", e);
-            tracing::warn!("Continuing without discovery announcements");
-        } else {
-            tracing::info!(
-                "Discovery announcements started (interval: {}s)",
-                config.discovery_interval
-            );
+        // WireGuard peers and routes for announcing and classifying sources
+        tasks.push(wireguard::start_snapshot_task(state.shutdown_signal()));
+        tasks.push(
+            trust_store.start_maintenance_task(peer_registry.clone(), state.shutdown_signal()),
+        );
+        let discovery = DiscoveryService::new(
+            config.clone(),
+            local_node_id.clone(),
+            hostname.clone(),
+            signing_key.clone(),
+            trust_store.clone(),
+            sources.clone(),
+            state.shutdown_signal(),
+        );
+
+        match discovery.start_announcing(peer_registry.clone()).await {
+            Ok(handle) => {
+                tasks.push(handle);
+                tracing::info!(
+                    "Discovery announcements started (interval: {}s)",
+                    config.discovery_interval
+                );
+            }
+            Err(e) => {
+                tracing::error!("Failed to start discovery announcements: {}", e);
+                tracing::warn!("Continuing without discovery announcements");
+            }
         }
 
-        if let Err(e) = discovery.start_listening(peer_registry.clone()).await {
-            tracing::error!("Failed to start discovery listener: {}", e);
-            tracing::warn!("Continuing without discovery listener");
-        } else {
-            tracing::info!(
-                "Discovery listener started on multicast {}:{}",
-                config.multicast_group,
-                config.multicast_port
-            );
+        match discovery
+            .start_listening(peer_registry.clone(), state.packet_stats.clone())
+            .await
+        {
+            Ok(handles) => {
+                tracing::info!(
+                    "Discovery listener started on multicast {}:{}{}",
+                    config.multicast_group,
+                    config.multicast_port,
+                    if handles.len() > 1 {
+                        format!(
+                            " and [{}]:{}",
+                            config.multicast_group_v6, config.multicast_port
+                        )
+                    } else {
+                        String::new()
+                    }
+                );
+                tasks.extend(handles);
+            }
+            Err(e) => {
+                tracing::error!("Failed to start discovery listener: {}", e);
+                tracing::warn!("Continuing without discovery listener");
+            }
         }
 
+        tasks.extend(sources.start_discovery_tasks(peer_registry.clone(), state.shutdown_signal()));
+
         // Start gossip service for cleanup
-        let gossip = GossipService::new(peer_registry.clone());
-        gossip.start_cleanup_task().await;
+        let gossip = GossipService::new(
+            config.clone(),
+            peer_registry.clone(),
+            state.shutdown_signal(),
+        );
+        tasks.push(gossip.start_cleanup_task().await);
         tracing::info!(
             "Gossip service started (peer timeout: {}s)",
             config.peer_timeout
@@ -118,20 +210,29 @@ async fn main() -> anyhow::Result<()> {
     // Start ping service for latency monitoring (if enabled)
     if config.ping_enabled {
         tracing::info!("Starting ping service...");
-        let ping_service = PingService::new(peer_registry.clone(), state.clone());
-        ping_service.start_ping_task().await;
+        let ping_service = PingService::new(config.clone(), peer_registry.clone(), state.clone());
+        tasks.push(ping_service.start_ping_task().await);
+        tasks.push(ping_service.start_matrix_task().await);
         tracing::info!("Ping service started (interval: {}s)", config.ping_interval);
     } else {
         tracing::info!("Ping service disabled by configuration");
     }
 
+    // Start periodic DNS/IPv6 leak check
+    let leak_check = LeakCheckService::new(config.clone(), state.clone());
+    tasks.push(leak_check.start_check_task().await);
+    tracing::info!(
+        "Leak check started (interval: {}s)",
+        config.leak_check_interval
+    );
+
     // Start bandwidth test server
     tracing::info!(
         "Starting bandwidth test server on port {}...",
         config.bandwidth_port
     );
-    let bandwidth_service = Arc::new(BandwidthService::new(state.clone()));
-    bandwidth_service.start_server().await;
+    let bandwidth_service = Arc::new(BandwidthService::new(config.clone(), state.clone()));
+    tasks.push(bandwidth_service.start_server().await);
     tracing::info!("Bandwidth test server started");
 
     // Update state with bandwidth service
@@ -154,7 +255,18 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("  GET  http://{}/api/routing-table", addr);
     tracing::info!("  POST http://{}/api/trace-route", addr);
     tracing::info!("  POST http://{}/api/traceroute", addr);
+    tracing::info!("  POST http://{}/api/path", addr);
+    tracing::info!("  POST http://{}/api/diagnose", addr);
+    tracing::info!("  GET  http://{}/api/leak-check", addr);
+    tracing::info!("  GET  http://{}/api/diagnostics/sysctl", addr);
+    tracing::info!("  GET  http://{}/api/latency", addr);
+    tracing::info!("  GET  http://{}/api/latency/matrix", addr);
+    tracing::info!("  GET  http://{}/api/trust", addr);
+    tracing::info!("  GET  http://{}/api/discovery/stats", addr);
+    tracing::info!("  GET  http://{}/api/wireguard", addr);
+    tracing::info!("  GET  http://{}/api/vpn-peers", addr);
     tracing::info!("  GET  http://{}/api/nodes", addr);
+    tracing::info!("  GET  http://{}/api/nodes/{{id}}/routing-table", addr);
     tracing::info!("  WS   ws://{}/ws", addr);
     tracing::info!("═══════════════════════════════════════════════════════════");
     tracing::info!("Web UI: http://{}/static/index.html", addr);
@@ -191,10 +303,125 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("✓ Server started successfully!");
     tracing::info!("Press Ctrl+C to stop");
 
-    axum::serve(listener, app).await.map_err(|e| {
-        tracing::error!("Server error: {}", e);
-        anyhow::anyhow!("Server error: {}", e)
-    })?;
+    let shutdown_state = state.clone();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            tracing::info!("Shutting down...");
+            shutdown_state.begin_shutdown();
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Server error: {}", e);
+            anyhow::anyhow!("Server error: {}", e)
+        })?;
+
+    // Give background tasks (including the discovery goodbye) a moment to finish
+    let stopped = tokio::time::timeout(
+        Duration::from_secs(SHUTDOWN_TIMEOUT_SECS),
+        futures::future::join_all(tasks),
+    )
+    .await;
+    if stopped.is_err() {
+        tracing::warn!(
+            "Background tasks did not stop within {}s",
+            SHUTDOWN_TIMEOUT_SECS
+        );
+    }
+
+    if let Err(e) = trust_store.flush().await {
+        tracing::warn!("Failed to save pinned keys: {}", e);
+    }
 
+    tracing::info!("Shutdown complete");
     Ok(())
 }
+
+/// Resolves on Ctrl+C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Register the discovery sources enabled in config
+fn discovery_sources(
+    config: &Arc<Config>,
+    local_node_id: &str,
+    hostname: &str,
+    peer_registry: &Arc<PeerRegistry>,
+    state: &AppState,
+) -> SourceRegistry {
+    let mut sources = SourceRegistry::new();
+
+    if config.sources.multicast {
+        sources.register(Arc::new(MulticastSource::new(config.clone())));
+    }
+    if config.sources.wireguard {
+        sources.register(Arc::new(WireGuardSource::new(config.clone())));
+    }
+    if config.sources.vpn_scan {
+        sources.register(Arc::new(VpnScanSource::new(config.clone())));
+    }
+    for (tool, enabled) in [
+        (VpnTool::Tailscale, config.sources.tailscale),
+        (VpnTool::ZeroTier, config.sources.zerotier),
+        (VpnTool::OpenVpn, config.sources.openvpn),
+    ] {
+        if enabled {
+            sources.register(Arc::new(VpnPeerSource::new(
+                tool,
+                config.clone(),
+                state.vpn_peers.clone(),
+            )));
+        }
+    }
+    if config.sources.gossip {
+        sources.register(Arc::new(GossipSource::new(
+            config.clone(),
+            peer_registry.clone(),
+        )));
+    }
+    if config.sources.static_peers && !config.seed_peers.is_empty() {
+        tracing::info!("Seed peers: {}", config.seed_peers.join(", "));
+        sources.register(Arc::new(StaticSource::new(
+            config.clone(),
+            state.node_client.clone(),
+        )));
+    }
+    if config.sources.mdns {
+        match MdnsSource::new(config.clone(), local_node_id.to_string(), hostname) {
+            Ok(mdns) => sources.register(Arc::new(mdns)),
+            Err(e) => {
+                tracing::error!("Failed to start mDNS discovery: {}", e);
+                tracing::warn!("Continuing without mDNS discovery");
+            }
+        }
+    }
+
+    sources
+}
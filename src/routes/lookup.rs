@@ -16,6 +16,11 @@ impl RouteEngine {
 
     /// Find the best matching route for a destination IP using longest prefix matching
     pub fn lookup(&self, dest: IpAddr) -> Option<&Route> {
+        self.lookup_with_prefix(dest).map(|(route, _)| route)
+    }
+
+    /// Like `lookup`, but also returns the prefix length of the matched route
+    pub fn lookup_with_prefix(&self, dest: IpAddr) -> Option<(&Route, u8)> {
         let mut best_match: Option<(&Route, u8)> = None;
 
         for route in &self.routes {
@@ -30,15 +35,17 @@ impl RouteEngine {
             }
         }
 
-        best_match.map(|(route, _)| route)
+        best_match
     }
 }
 
 /// Check if an IP matches a CIDR and return the prefix length if it does
-fn matches_cidr(cidr: &str, ip: IpAddr) -> Option<u8> {
-    // Handle special cases
-    if cidr == "default" || cidr == "0.0.0.0/0" || cidr == "::/0" {
-        return Some(0);
+pub(crate) fn matches_cidr(cidr: &str, ip: IpAddr) -> Option<u8> {
+    // Handle special cases; the explicit defaults only match their own family
+    match (cidr, ip) {
+        ("default", _) | ("0.0.0.0/0", IpAddr::V4(_)) | ("::/0", IpAddr::V6(_)) => return Some(0),
+        ("0.0.0.0/0", IpAddr::V6(_)) | ("::/0", IpAddr::V4(_)) => return None,
+        _ => {}
     }
 
     // Parse CIDR notation
@@ -143,5 +150,40 @@ mod tests {
         assert_eq!(matches_cidr("192.168.0.0/16", ip), Some(16));
         assert_eq!(matches_cidr("0.0.0.0/0", ip), Some(0));
         assert_eq!(matches_cidr("192.168.2.0/24", ip), None);
+        assert_eq!(matches_cidr("::/0", ip), None);
+    }
+
+    #[test]
+    fn test_lookup_per_family_default() {
+        let table = RoutingTable {
+            hostname: "test".to_string(),
+            routes: vec![
+                Route {
+                    destination: "0.0.0.0/0".to_string(),
+                    gateway: Some("192.168.1.1".parse().unwrap()),
+                    interface: "eth0".to_string(),
+                    metric: 100,
+                    flags: Vec::new(),
+                    wireguard_peers: Vec::new(),
+                },
+                Route {
+                    destination: "::/0".to_string(),
+                    gateway: Some("fd00::1".parse().unwrap()),
+                    interface: "wg0".to_string(),
+                    metric: 1024,
+                    flags: Vec::new(),
+                    wireguard_peers: Vec::new(),
+                },
+            ],
+            timestamp: String::new(),
+        };
+        let engine = RouteEngine::new(&table);
+
+        let v4 = engine.lookup("8.8.8.8".parse().unwrap()).unwrap();
+        assert_eq!(v4.interface, "eth0");
+        let v6 = engine
+            .lookup("2001:4860:4860::8888".parse().unwrap())
+            .unwrap();
+        assert_eq!(v6.interface, "wg0");
     }
 }
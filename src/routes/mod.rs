@@ -2,9 +2,11 @@
 
 pub mod lookup;
 pub mod parser;
+pub mod path;
+pub mod resolver;
 
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Route {
@@ -13,6 +15,20 @@ pub struct Route {
     pub interface: String,
     pub metric: u32,
     pub flags: Vec<String>,
+    /// For routes over a WireGuard interface, the peers whose allowed IPs it covers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wireguard_peers: Vec<WireGuardTarget>,
+}
+
+/// A WireGuard peer that traffic on a route is handed to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WireGuardTarget {
+    pub public_key: String,
+    pub endpoint: Option<SocketAddr>,
+    /// The peer's allowed IP that overlaps the route
+    pub allowed_ip: String,
+    /// The peer hasn't completed a recent handshake
+    pub stale: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,3 +37,28 @@ pub struct RoutingTable {
     pub routes: Vec<Route>,
     pub timestamp: String,
 }
+
+/// Route and table factories shared by the test modules
+#[cfg(test)]
+pub mod test_support {
+    use super::{Route, RoutingTable};
+
+    pub fn route(destination: &str, gateway: Option<&str>, interface: &str) -> Route {
+        Route {
+            destination: destination.to_string(),
+            gateway: gateway.map(|g| g.parse().unwrap()),
+            interface: interface.to_string(),
+            metric: 0,
+            flags: Vec::new(),
+            wireguard_peers: Vec::new(),
+        }
+    }
+
+    pub fn table(routes: Vec<Route>) -> RoutingTable {
+        RoutingTable {
+            hostname: "test".to_string(),
+            routes,
+            timestamp: String::new(),
+        }
+    }
+}
@@ -17,6 +17,41 @@ pub fn get_routing_table() -> Result<RoutingTable, String> {
     return Err("Unsupported platform".to_string());
 }
 
+/// Get the routes of a specific policy-routing table (e.g. "51820" from `ip rule`)
+pub fn get_policy_routing_table(table: &str) -> Result<RoutingTable, String> {
+    #[cfg(target_os = "linux")]
+    return get_policy_routing_table_linux(table);
+
+    #[cfg(not(target_os = "linux"))]
+    return Err(format!(
+        "Policy routing table {} is only available on Linux",
+        table
+    ));
+}
+
+#[cfg(target_os = "linux")]
+fn get_policy_routing_table_linux(table: &str) -> Result<RoutingTable, String> {
+    let mut routes = Vec::new();
+
+    for (family, default_destination) in [("-4", "0.0.0.0/0"), ("-6", "::/0")] {
+        let output = Command::new("ip")
+            .args([family, "-json", "route", "show", "table", table])
+            .output()
+            .map_err(|e| format!("Failed to execute ip command: {}", e))?;
+
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            routes.extend(parse_ip_json(&stdout, default_destination)?);
+        }
+    }
+
+    Ok(RoutingTable {
+        hostname: get_hostname(),
+        routes,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    })
+}
+
 #[cfg(target_os = "linux")]
 fn get_routing_table_linux() -> Result<RoutingTable, String> {
     // Try using `ip -json route show` first
@@ -27,7 +62,27 @@ fn get_routing_table_linux() -> Result<RoutingTable, String> {
 
     if output.status.success() {
         let stdout = String::from_utf8_lossy(&output.stdout);
-        return parse_ip_json(&stdout);
+        let mut routes = parse_ip_json(&stdout, "0.0.0.0/0")?;
+
+        // IPv6 routes are listed separately; hosts without IPv6 simply return none
+        if let Ok(output) = Command::new("ip")
+            .args(["-6", "-json", "route", "show"])
+            .output()
+        {
+            if output.status.success() {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                match parse_ip_json(&stdout, "::/0") {
+                    Ok(v6_routes) => routes.extend(v6_routes),
+                    Err(e) => tracing::debug!("Failed to parse IPv6 routes: {}", e),
+                }
+            }
+        }
+
+        return Ok(RoutingTable {
+            hostname: get_hostname(),
+            routes,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        });
     }
 
     // Fallback to parsing `ip route show` (non-JSON)
@@ -45,19 +100,18 @@ fn get_routing_table_linux() -> Result<RoutingTable, String> {
 }
 
 #[cfg(target_os = "linux")]
-fn parse_ip_json(json_str: &str) -> Result<RoutingTable, String> {
-    // Parse JSON output from `ip -json route show`
+fn parse_ip_json(json_str: &str, default_destination: &str) -> Result<Vec<Route>, String> {
+    // Parse JSON output from `ip -json route show` (or `ip -6 -json route show`)
     let routes: Vec<serde_json::Value> =
         serde_json::from_str(json_str).map_err(|e| format!("Failed to parse JSON: {}", e))?;
 
     let mut parsed_routes = Vec::new();
 
     for route in routes {
-        let destination = route
-            .get("dst")
-            .and_then(|v| v.as_str())
-            .unwrap_or("default")
-            .to_string();
+        let destination = match route.get("dst").and_then(|v| v.as_str()) {
+            Some("default") | None => default_destination.to_string(),
+            Some(dst) => dst.to_string(),
+        };
 
         let gateway = route
             .get("gateway")
@@ -88,17 +142,11 @@ fn parse_ip_json(json_str: &str) -> Result<RoutingTable, String> {
             interface,
             metric,
             flags,
+            wireguard_peers: Vec::new(),
         });
     }
 
-    let hostname = get_hostname();
-    let timestamp = chrono::Utc::now().to_rfc3339();
-
-    Ok(RoutingTable {
-        hostname,
-        routes: parsed_routes,
-        timestamp,
-    })
+    Ok(parsed_routes)
 }
 
 #[cfg(target_os = "linux")]
@@ -158,6 +206,7 @@ fn parse_ip_route(output: &str) -> Result<RoutingTable, String> {
             interface,
             metric,
             flags,
+            wireguard_peers: Vec::new(),
         });
     }
 
@@ -187,3 +236,20 @@ fn get_hostname() -> String {
         .and_then(|h| h.into_string().ok())
         .unwrap_or_else(|| "unknown".to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_parse_ip_json_ipv6_default() {
+        let json = r#"[{"dst":"fd00::/64","dev":"eth0","protocol":"kernel","metric":256,"flags":[]},
+            {"dst":"default","gateway":"fd00::1","dev":"eth0","metric":1024,"flags":[]}]"#;
+
+        let routes = super::parse_ip_json(json, "::/0").unwrap();
+        assert_eq!(routes.len(), 2);
+        let default = routes.get(1).unwrap();
+        assert_eq!(default.destination, "::/0");
+        assert_eq!(default.gateway, Some("fd00::1".parse().unwrap()));
+        assert_eq!(default.metric, 1024);
+    }
+}
@@ -0,0 +1,346 @@
+// Multi-node path computation
+// Follows route lookups hop by hop across the routing tables of discovered
+// nodes: each hop's gateway is mapped back to the node that owns that address,
+// until the destination is reached, the trail goes cold, or a node repeats
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use super::lookup::{matches_cidr, RouteEngine};
+use super::{Route, RoutingTable};
+
+/// Give up on paths longer than this; real topologies are far shorter
+const MAX_HOPS: usize = 32;
+
+/// A node taking part in path computation
+#[derive(Debug, Clone)]
+pub struct PathNode {
+    pub id: String,
+    pub hostname: String,
+    pub addresses: Vec<IpAddr>,
+    /// None when the node's routing table could not be fetched
+    pub routing_table: Option<RoutingTable>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathOutcome {
+    /// The packet reaches a node that owns the destination address
+    Delivered,
+    /// A node on the path has no route to the destination
+    BlackHole,
+    /// The packet comes back to a node it already visited
+    Loop,
+    /// The next hop is not a known node, or its routing table is unavailable
+    Unresolved,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathHop {
+    pub node_id: String,
+    pub hostname: String,
+    /// Route used to forward the packet (None at the final hop)
+    pub route: Option<Route>,
+    /// Address the packet is handed to next
+    pub next_hop: Option<IpAddr>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathTrace {
+    pub from: String,
+    pub to: String,
+    pub destination: IpAddr,
+    pub hops: Vec<PathHop>,
+    pub outcome: PathOutcome,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathAnalysis {
+    pub forward: PathTrace,
+    /// Path replies take back to the source address used on the forward path
+    pub return_path: Option<PathTrace>,
+    /// Forward and return paths are both delivered, through different nodes
+    pub asymmetric: bool,
+}
+
+/// Compute forward and return paths between two nodes
+pub fn compute_path(nodes: &[PathNode], from: &str, to: &str) -> Result<PathAnalysis, String> {
+    let source = find_node(nodes, from).ok_or_else(|| format!("Node {} not found", from))?;
+    let target = find_node(nodes, to).ok_or_else(|| format!("Node {} not found", to))?;
+
+    if target.addresses.is_empty() {
+        return Err(format!("Node {} has no known addresses", target.hostname));
+    }
+
+    // Try every address of the target and keep the first that is actually delivered
+    let mut traces: Vec<PathTrace> = target
+        .addresses
+        .iter()
+        .map(|address| trace(nodes, source, *address))
+        .collect();
+    let delivered = traces
+        .iter()
+        .position(|t| t.outcome == PathOutcome::Delivered)
+        .unwrap_or(0);
+    let forward = traces.swap_remove(delivered);
+
+    if forward.outcome != PathOutcome::Delivered {
+        return Ok(PathAnalysis {
+            forward,
+            return_path: None,
+            asymmetric: false,
+        });
+    }
+
+    // Replies go to the source address on the interface the packet left through
+    let return_path = forward
+        .hops
+        .first()
+        .and_then(|hop| hop.route.as_ref())
+        .and_then(|route| source_address(source, route))
+        .map(|address| trace(nodes, target, address));
+
+    // A return path that never arrives is a failure, not an asymmetry
+    let asymmetric = return_path.as_ref().is_some_and(|back| {
+        if back.outcome != PathOutcome::Delivered {
+            return false;
+        }
+        let there: Vec<&str> = forward.hops.iter().map(|h| h.node_id.as_str()).collect();
+        let mut back: Vec<&str> = back.hops.iter().map(|h| h.node_id.as_str()).collect();
+        back.reverse();
+        there != back
+    });
+
+    Ok(PathAnalysis {
+        forward,
+        return_path,
+        asymmetric,
+    })
+}
+
+/// Follow route lookups from `start` towards `destination`
+pub fn trace(nodes: &[PathNode], start: &PathNode, destination: IpAddr) -> PathTrace {
+    let mut hops = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current = start;
+
+    let finish = |hops: Vec<PathHop>, outcome: PathOutcome, description: String| PathTrace {
+        from: start.id.clone(),
+        to: owner_of(nodes, destination)
+            .map(|n| n.id.clone())
+            .unwrap_or_else(|| destination.to_string()),
+        destination,
+        hops,
+        outcome,
+        description,
+    };
+
+    loop {
+        if !visited.insert(current.id.as_str()) {
+            let description = format!("Routing loop: {} is visited twice", current.hostname);
+            return finish(hops, PathOutcome::Loop, description);
+        }
+
+        if current.addresses.contains(&destination) {
+            hops.push(PathHop {
+                node_id: current.id.clone(),
+                hostname: current.hostname.clone(),
+                route: None,
+                next_hop: None,
+            });
+            let description = format!("{} reached in {} hop(s)", destination, hops.len() - 1);
+            return finish(hops, PathOutcome::Delivered, description);
+        }
+
+        if hops.len() >= MAX_HOPS {
+            let description = format!("Gave up after {} hops", MAX_HOPS);
+            return finish(hops, PathOutcome::Unresolved, description);
+        }
+
+        let Some(table) = &current.routing_table else {
+            hops.push(PathHop {
+                node_id: current.id.clone(),
+                hostname: current.hostname.clone(),
+                route: None,
+                next_hop: None,
+            });
+            let description = format!("Routing table of {} is unavailable", current.hostname);
+            return finish(hops, PathOutcome::Unresolved, description);
+        };
+
+        let engine = RouteEngine::new(table);
+        let Some(route) = engine.lookup(destination).cloned() else {
+            hops.push(PathHop {
+                node_id: current.id.clone(),
+                hostname: current.hostname.clone(),
+                route: None,
+                next_hop: None,
+            });
+            let description = format!("{} has no route to {}", current.hostname, destination);
+            return finish(hops, PathOutcome::BlackHole, description);
+        };
+
+        // On-link routes hand the packet straight to the destination
+        let next_hop = route.gateway.unwrap_or(destination);
+        hops.push(PathHop {
+            node_id: current.id.clone(),
+            hostname: current.hostname.clone(),
+            route: Some(route.clone()),
+            next_hop: Some(next_hop),
+        });
+
+        match owner_of(nodes, next_hop) {
+            Some(next) => current = next,
+            None => {
+                let description = format!(
+                    "{} forwards to {} via {}, which is not a known node",
+                    current.hostname, next_hop, route.interface
+                );
+                return finish(hops, PathOutcome::Unresolved, description);
+            }
+        }
+    }
+}
+
+fn find_node<'a>(nodes: &'a [PathNode], key: &str) -> Option<&'a PathNode> {
+    nodes
+        .iter()
+        .find(|n| n.id == key)
+        .or_else(|| nodes.iter().find(|n| n.hostname.eq_ignore_ascii_case(key)))
+}
+
+fn owner_of(nodes: &[PathNode], address: IpAddr) -> Option<&PathNode> {
+    nodes.iter().find(|n| n.addresses.contains(&address))
+}
+
+/// Pick the node's own address on the subnet attached to the route's interface
+fn source_address(node: &PathNode, route: &Route) -> Option<IpAddr> {
+    let subnets: Vec<&Route> = node
+        .routing_table
+        .as_ref()
+        .map(|t| {
+            t.routes
+                .iter()
+                .filter(|r| r.interface == route.interface && r.gateway.is_none())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    node.addresses
+        .iter()
+        .copied()
+        .find(|address| {
+            subnets
+                .iter()
+                .any(|subnet| matches_cidr(&subnet.destination, *address).is_some())
+        })
+        .or_else(|| {
+            // Fall back to any address of the same family
+            node.addresses.iter().copied().find(|address| {
+                route
+                    .gateway
+                    .is_none_or(|gw| gw.is_ipv4() == address.is_ipv4())
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::test_support::route;
+
+    fn node(id: &str, addresses: &[&str], routes: Vec<Route>) -> PathNode {
+        PathNode {
+            id: id.to_string(),
+            hostname: id.to_string(),
+            addresses: addresses.iter().map(|a| a.parse().unwrap()).collect(),
+            routing_table: Some(RoutingTable {
+                hostname: id.to_string(),
+                routes,
+                timestamp: String::new(),
+            }),
+        }
+    }
+
+    /// a (192.168.1.10) -- b (192.168.1.1 / 10.8.0.1) -- k (10.8.0.15)
+    fn topology(k_routes: Vec<Route>) -> Vec<PathNode> {
+        vec![
+            node(
+                "a",
+                &["192.168.1.10"],
+                vec![
+                    route("192.168.1.0/24", None, "eth0"),
+                    route("10.8.0.0/24", Some("192.168.1.1"), "eth0"),
+                ],
+            ),
+            node(
+                "b",
+                &["192.168.1.1", "10.8.0.1"],
+                vec![
+                    route("192.168.1.0/24", None, "eth0"),
+                    route("10.8.0.0/24", None, "wg0"),
+                ],
+            ),
+            node("k", &["10.8.0.15"], k_routes),
+        ]
+    }
+
+    #[test]
+    fn test_symmetric_path() {
+        let nodes = topology(vec![
+            route("10.8.0.0/24", None, "wg0"),
+            route("192.168.1.0/24", Some("10.8.0.1"), "wg0"),
+        ]);
+
+        let analysis = compute_path(&nodes, "a", "k").unwrap();
+        assert_eq!(analysis.forward.outcome, PathOutcome::Delivered);
+        let hops: Vec<&str> = analysis
+            .forward
+            .hops
+            .iter()
+            .map(|h| h.node_id.as_str())
+            .collect();
+        assert_eq!(hops, vec!["a", "b", "k"]);
+
+        let back = analysis.return_path.unwrap();
+        assert_eq!(back.outcome, PathOutcome::Delivered);
+        assert_eq!(back.destination, "192.168.1.10".parse::<IpAddr>().unwrap());
+        assert!(!analysis.asymmetric);
+    }
+
+    #[test]
+    fn test_missing_return_route_is_a_black_hole() {
+        let nodes = topology(vec![route("10.8.0.0/24", None, "wg0")]);
+
+        let analysis = compute_path(&nodes, "a", "k").unwrap();
+        assert_eq!(analysis.forward.outcome, PathOutcome::Delivered);
+
+        let back = analysis.return_path.unwrap();
+        assert_eq!(back.outcome, PathOutcome::BlackHole);
+        assert_eq!(back.hops.first().map(|h| h.node_id.as_str()), Some("k"));
+        // The return hops (just k) differ from the forward ones, but nothing came back
+        assert!(!analysis.asymmetric);
+    }
+
+    #[test]
+    fn test_routing_loop() {
+        let nodes = vec![
+            node(
+                "a",
+                &["192.168.1.10"],
+                vec![route("10.9.0.0/16", Some("192.168.1.1"), "eth0")],
+            ),
+            node(
+                "b",
+                &["192.168.1.1"],
+                vec![route("10.9.0.0/16", Some("192.168.1.10"), "eth0")],
+            ),
+        ];
+
+        let forward = trace(&nodes, nodes.first().unwrap(), "10.9.0.5".parse().unwrap());
+        assert_eq!(forward.outcome, PathOutcome::Loop);
+        assert_eq!(forward.hops.len(), 2);
+    }
+}
@@ -0,0 +1,287 @@
+// DNS resolver discovery
+// Reads `resolvectl status` (systemd-resolved) or /etc/resolv.conf to find the
+// configured nameservers, the link each one belongs to, and its routing domains
+// so split-DNS setups (e.g. a VPN that only serves ~corp.example.com) are visible
+
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::process::Command;
+
+/// A configured DNS resolver
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Resolver {
+    /// Nameserver address
+    pub address: IpAddr,
+    /// Link the resolver is configured on (per-link servers from resolvectl)
+    pub link: Option<String>,
+    /// Search/routing domains served by this resolver ("~." is the catch-all)
+    pub domains: Vec<String>,
+    /// Whether this resolver is used for names not matched by any routing domain
+    pub default_route: bool,
+    /// Where the resolver was read from ("resolvectl" or "resolv.conf")
+    pub source: String,
+}
+
+/// Get the configured resolvers, preferring systemd-resolved's per-link view
+pub fn get_resolvers() -> Vec<Resolver> {
+    if let Ok(output) = Command::new("resolvectl").arg("status").output() {
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let resolvers = parse_resolvectl_status(&stdout);
+            if !resolvers.is_empty() {
+                return resolvers;
+            }
+        }
+    }
+
+    match std::fs::read_to_string("/etc/resolv.conf") {
+        Ok(content) => parse_resolv_conf(&content),
+        Err(e) => {
+            tracing::debug!("Failed to read /etc/resolv.conf: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Parse /etc/resolv.conf `nameserver` and `search`/`domain` lines
+pub fn parse_resolv_conf(content: &str) -> Vec<Resolver> {
+    let mut addresses = Vec::new();
+    let mut domains = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("nameserver") => {
+                if let Some(addr) = parts.next().and_then(parse_server_address) {
+                    addresses.push(addr);
+                }
+            }
+            Some("search") | Some("domain") => {
+                domains = parts.map(String::from).collect();
+            }
+            _ => {}
+        }
+    }
+
+    addresses
+        .into_iter()
+        .map(|address| Resolver {
+            address,
+            link: None,
+            domains: domains.clone(),
+            default_route: true,
+            source: "resolv.conf".to_string(),
+        })
+        .collect()
+}
+
+/// Parse `resolvectl status` output into one entry per (link, server) pair
+pub fn parse_resolvectl_status(output: &str) -> Vec<Resolver> {
+    // Sections start with "Global" or "Link N (name)"; keys are right-aligned
+    // and values may continue on following lines that have no "key:" prefix
+    struct Section {
+        link: Option<String>,
+        servers: Vec<IpAddr>,
+        domains: Vec<String>,
+        default_route: Option<bool>,
+    }
+
+    let mut sections: Vec<Section> = Vec::new();
+    let mut current_key = String::new();
+
+    for raw_line in output.lines() {
+        let line = raw_line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line == "Global" {
+            sections.push(Section {
+                link: None,
+                servers: Vec::new(),
+                domains: Vec::new(),
+                default_route: None,
+            });
+            current_key.clear();
+            continue;
+        }
+
+        if line.starts_with("Link ") {
+            let link = line
+                .split_once('(')
+                .and_then(|(_, rest)| rest.split_once(')'))
+                .map(|(name, _)| name.to_string());
+            sections.push(Section {
+                link,
+                servers: Vec::new(),
+                domains: Vec::new(),
+                default_route: None,
+            });
+            current_key.clear();
+            continue;
+        }
+
+        let Some(section) = sections.last_mut() else {
+            continue;
+        };
+
+        let value = match line.split_once(": ") {
+            Some((key, value)) => {
+                current_key = key.trim().to_string();
+                value
+            }
+            None if line.ends_with(':') => {
+                current_key = line.trim_end_matches(':').trim().to_string();
+                ""
+            }
+            None => line,
+        };
+
+        match current_key.as_str() {
+            "DNS Servers" => {
+                section
+                    .servers
+                    .extend(value.split_whitespace().filter_map(parse_server_address));
+            }
+            "DNS Domain" => {
+                section
+                    .domains
+                    .extend(value.split_whitespace().map(String::from));
+            }
+            "Protocols" => {
+                if value.contains("+DefaultRoute") {
+                    section.default_route = Some(true);
+                } else if value.contains("-DefaultRoute") {
+                    section.default_route = Some(false);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut resolvers = Vec::new();
+    for section in sections {
+        // Global servers and links without an explicit flag take unmatched queries
+        // unless they only carry routing-only domains
+        let default_route = section.default_route.unwrap_or_else(|| {
+            section.domains.is_empty() || section.domains.iter().any(|d| d == "~.")
+        });
+
+        for address in section.servers {
+            resolvers.push(Resolver {
+                address,
+                link: section.link.clone(),
+                domains: section.domains.clone(),
+                default_route,
+                source: "resolvectl".to_string(),
+            });
+        }
+    }
+
+    resolvers
+}
+
+/// Pick the resolver that will be asked for `name`
+///
+/// Mirrors systemd-resolved's routing: the longest matching routing/search
+/// domain wins, otherwise the query goes to the default-route resolvers.
+pub fn select_resolver<'a>(resolvers: &'a [Resolver], name: &str) -> Option<&'a Resolver> {
+    let name = name.trim_end_matches('.').to_lowercase();
+    let mut best: Option<(&Resolver, usize)> = None;
+
+    for resolver in resolvers {
+        for domain in &resolver.domains {
+            let domain = domain.trim_start_matches('~').trim_end_matches('.');
+            if domain.is_empty() {
+                continue;
+            }
+            let domain = domain.to_lowercase();
+            let matches = name == domain || name.ends_with(&format!(".{}", domain));
+            if matches && best.is_none_or(|(_, len)| domain.len() > len) {
+                best = Some((resolver, domain.len()));
+            }
+        }
+    }
+
+    best.map(|(resolver, _)| resolver)
+        .or_else(|| resolvers.iter().find(|r| r.default_route))
+        .or_else(|| resolvers.first())
+}
+
+/// Parse a nameserver entry, dropping DoT server names ("#name") and zone ids ("%eth0")
+fn parse_server_address(entry: &str) -> Option<IpAddr> {
+    let entry = entry.split('#').next()?;
+    let entry = entry.split('%').next()?;
+    entry.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resolv_conf() {
+        let content = "# Generated by NetworkManager\n\
+                       search lan example.com\n\
+                       nameserver 192.168.1.1\n\
+                       nameserver fe80::1%eth0\n\
+                       options edns0\n";
+
+        let resolvers = parse_resolv_conf(content);
+        assert_eq!(resolvers.len(), 2);
+        assert_eq!(
+            resolvers.first().map(|r| r.address),
+            Some("192.168.1.1".parse().unwrap())
+        );
+        assert_eq!(
+            resolvers.get(1).map(|r| r.address),
+            Some("fe80::1".parse().unwrap())
+        );
+        assert!(resolvers.iter().all(|r| r.domains.len() == 2));
+    }
+
+    #[test]
+    fn test_parse_resolvectl_split_dns() {
+        let output = r#"Global
+           Protocols: +LLMNR +mDNS -DNSOverTLS DNSSEC=no/unsupported
+    resolv.conf mode: stub
+
+Link 2 (eth0)
+    Current Scopes: DNS
+         Protocols: +DefaultRoute +LLMNR -mDNS -DNSOverTLS DNSSEC=no/unsupported
+Current DNS Server: 192.168.1.1
+       DNS Servers: 192.168.1.1
+                    2001:db8::53
+        DNS Domain: lan
+
+Link 5 (tun0)
+    Current Scopes: DNS
+         Protocols: -DefaultRoute +LLMNR -mDNS -DNSOverTLS DNSSEC=no/unsupported
+       DNS Servers: 10.8.0.1#dns.corp.example.com
+        DNS Domain: ~corp.example.com
+"#;
+
+        let resolvers = parse_resolvectl_status(output);
+        assert_eq!(resolvers.len(), 3);
+
+        let vpn = select_resolver(&resolvers, "git.corp.example.com").unwrap();
+        assert_eq!(vpn.address, "10.8.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(vpn.link.as_deref(), Some("tun0"));
+
+        let public = select_resolver(&resolvers, "example.org").unwrap();
+        assert_eq!(public.address, "192.168.1.1".parse::<IpAddr>().unwrap());
+        assert_eq!(public.link.as_deref(), Some("eth0"));
+    }
+
+    #[test]
+    fn test_parse_server_address() {
+        assert!(parse_server_address("1.1.1.1#cloudflare-dns.com").is_some());
+        assert!(parse_server_address("fe80::1%wlan0").is_some());
+        assert!(parse_server_address("not-an-ip").is_none());
+    }
+}
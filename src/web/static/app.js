@@ -31,6 +31,7 @@ class RouteVisualizer {
         this.discoveredNodes = new Map();
         this.localNodeId = null;
         this.latencyData = new Map(); // nodeId -> latency_ms
+        this.peerLatencyData = new Map(); // "fromId|toId" -> latency_ms between remote nodes
         this.bandwidthTests = new Map(); // testId -> test data
         this.bandwidthResults = new Map(); // nodeId -> result
         this.tracerouteData = null; // Current traceroute result
@@ -197,6 +198,9 @@ class RouteVisualizer {
             case 'bandwidth_test_result':
                 this.handleBandwidthTestResult(message);
                 break;
+            case 'remote_routing_table':
+                this.handleRemoteRoutingTable(message);
+                break;
         }
     }
 
@@ -214,6 +218,38 @@ class RouteVisualizer {
         this.showSuccess(`Bandwidth test complete: ↑${message.upload_mbps.toFixed(2)} Mbps ↓${message.download_mbps.toFixed(2)} Mbps`);
     }
 
+    requestRemoteRoutingTable(nodeId) {
+        this.wsSend({
+            type: 'get_remote_routing_table',
+            request_id: `routes-${nodeId}-${Date.now()}`,
+            node_id: nodeId
+        });
+        this.showInfo(`Fetching routes from node ${nodeId.substring(0, 8)}...`);
+    }
+
+    handleRemoteRoutingTable(message) {
+        const table = message.routing_table;
+        const detailsDiv = document.getElementById('route-details');
+        detailsDiv.replaceChildren();
+
+        // The table comes from another node, so every field goes in as text
+        const line = (strong, rest) => {
+            const p = document.createElement('p');
+            const name = document.createElement('strong');
+            name.textContent = strong;
+            p.append(name, rest);
+            return p;
+        };
+
+        detailsDiv.append(line(table.hostname, ` - ${table.routes.length} routes`));
+        table.routes.forEach(route => {
+            const item = document.createElement('div');
+            item.className = 'route-item';
+            item.append(line(route.destination, ` via ${route.gateway || 'direct'} (${route.interface})`));
+            detailsDiv.append(item);
+        });
+    }
+
     handleNodeDiscovered(node) {
         console.log('Node discovered:', node);
         this.discoveredNodes.set(node.id, node);
@@ -227,6 +263,7 @@ class RouteVisualizer {
         if (node) {
             node.status = status;
             console.log(`Node ${nodeId} status changed to ${status}`);
+            this.updateDiscoveredNodesList();
             this.visualizeDiscoveredNodes();
         }
     }
@@ -235,6 +272,13 @@ class RouteVisualizer {
         console.log('Latency update:', connections);
 
         connections.forEach(conn => {
+            // Measurements between two remote nodes get their own edge
+            if (conn.from !== this.localNodeId && conn.from !== 'local') {
+                this.peerLatencyData.set(`${conn.from}|${conn.to}`, conn.latency_ms);
+                this.updatePeerEdge(conn.from, conn.to, conn.latency_ms);
+                return;
+            }
+
             // Store latency data
             this.latencyData.set(conn.to, conn.latency_ms);
 
@@ -269,6 +313,32 @@ class RouteVisualizer {
         });
     }
 
+    updatePeerEdge(fromId, toId, latencyMs) {
+        const fromMesh = this.nodes.get(`discovered-${fromId}`);
+        const toMesh = this.nodes.get(`discovered-${toId}`);
+        if (!fromMesh || !toMesh) return;
+
+        // One edge per unordered pair; the newest measurement wins
+        const pairKey = [fromId, toId].sort().join('|');
+        const edgeIndex = this.edges.findIndex(e =>
+            e.userData.type === 'peer-edge' && e.userData.pairKey === pairKey
+        );
+        if (edgeIndex !== -1) {
+            this.scene.remove(this.edges[edgeIndex]);
+            this.edges.splice(edgeIndex, 1);
+        }
+
+        const edge = this.createEdge(fromMesh.position, toMesh.position, this.getLatencyColor(latencyMs), false);
+        edge.userData = {
+            type: 'peer-edge',
+            pairKey: pairKey
+        };
+        this.addEdgeLabel(edge, `${latencyMs}ms`, `latency-${pairKey}`);
+
+        this.edges.push(edge);
+        this.scene.add(edge);
+    }
+
     getLatencyColor(latencyMs) {
         // Color code based on latency thresholds
         // < 20ms: green, 20-50ms: yellow-green, 50-100ms: yellow, 100-200ms: orange, > 200ms: red
@@ -371,9 +441,13 @@ class RouteVisualizer {
                         <div style="font-size: 11px; color: #a0a0a0;">
                             ${node.addresses.join(', ')}<br>
                             ${latency !== undefined ? `Latency: ${latency}ms<br>` : ''}
+                            ${node.learned_from ? `Learned via ${node.learned_from.substring(0, 8)}...<br>` : ''}
+                            ${node.wireguard_path ? `WireGuard: ${node.wireguard_path === 'tunnel' ? 'through tunnel' : 'underlay only'}<br>` : ''}
+                            ${node.clock_skewed ? `<span style="color: #f59e0b;">Clock off by ${Math.round(node.clock_offset_ms / 1000)}s</span><br>` : ''}
                         </div>
                         <div class="node-status ${node.status}">${node.status}</div>
                         ${bandwidthInfo}
+                        ${node.status === 'online' ? `<button class="remote-routes-btn" data-node-id="${node.id}" style="margin-top: 6px; margin-right: 4px; padding: 4px 8px; background: #10b981; border: none; color: white; border-radius: 3px; cursor: pointer; font-size: 11px;">Show Routes</button>` : ''}
                         ${!activeTest && node.status === 'online' ? `<button class="bandwidth-test-btn" data-node-id="${node.id}" style="margin-top: 6px; padding: 4px 8px; background: #3b82f6; border: none; color: white; border-radius: 3px; cursor: pointer; font-size: 11px;">Test Bandwidth</button>` : ''}
                     </div>
                 `;
@@ -381,6 +455,13 @@ class RouteVisualizer {
 
         container.innerHTML = nodesList;
 
+        document.querySelectorAll('.remote-routes-btn').forEach(btn => {
+            btn.addEventListener('click', (e) => {
+                const nodeId = e.target.getAttribute('data-node-id');
+                this.requestRemoteRoutingTable(nodeId);
+            });
+        });
+
         // Add event listeners to bandwidth test buttons
         document.querySelectorAll('.bandwidth-test-btn').forEach(btn => {
             btn.addEventListener('click', (e) => {
@@ -488,7 +569,13 @@ class RouteVisualizer {
             this.nodes.set(`route-${index}`, node);
             this.scene.add(node);
 
-            const label = isDefault ? 'Default' : route.destination.split('/')[0];
+            let label = isDefault ? 'Default' : route.destination.split('/')[0];
+            const wgPeers = route.wireguard_peers || [];
+            if (wgPeers.length === 1) {
+                label += ` → ${wgPeers[0].public_key.substring(0, 8)}`;
+            } else if (wgPeers.length > 1) {
+                label += ` → ${wgPeers.length} WG peers`;
+            }
             this.addLabel(node, label, `route-label-${index}`);
 
             const edge = this.createEdge(
@@ -520,6 +607,15 @@ class RouteVisualizer {
             }
         });
 
+        // Remove edges that point at the old meshes
+        this.edges = this.edges.filter(edge => {
+            if (edge.userData.type === 'mesh-edge' || edge.userData.type === 'peer-edge') {
+                this.scene.remove(edge);
+                return false;
+            }
+            return true;
+        });
+
         // Position discovered nodes in a separate layer
         const nodeArray = Array.from(this.discoveredNodes.values());
         const meshRadius = 25;
@@ -571,6 +667,12 @@ class RouteVisualizer {
             }
         });
 
+        // Redraw edges between remote nodes from the last known measurements
+        this.peerLatencyData.forEach((latencyMs, key) => {
+            const [fromId, toId] = key.split('|');
+            this.updatePeerEdge(fromId, toId, latencyMs);
+        });
+
         this.updateStats();
     }
 
@@ -779,6 +881,10 @@ class RouteVisualizer {
                 <p><strong>Interface:</strong> ${route.interface}</p>
                 <p><strong>Metric:</strong> ${route.metric}</p>
                 ${route.flags && route.flags.length > 0 ? `<p><strong>Flags:</strong> ${route.flags.join(', ')}</p>` : ''}
+                ${(route.wireguard_peers || []).map(peer => `
+                    <p><strong>WireGuard peer:</strong> ${peer.public_key.substring(0, 12)}… via ${peer.endpoint || 'no endpoint'}
+                    (allowed ${peer.allowed_ip})${peer.stale ? ' <span style="color: #ef4444;">stale handshake</span>' : ''}</p>
+                `).join('')}
             </div>
         `;
     }
@@ -1183,7 +1289,7 @@ class RouteVisualizer {
                 this.highlightRoute(object.userData.index);
             } else if (object.userData && object.userData.type === 'discovered-node') {
                 console.log('Clicked discovered node:', object.userData.node);
-                // TODO: Show node details or switch to that node's view
+                this.requestRemoteRoutingTable(object.userData.node.id);
             }
         }
     }